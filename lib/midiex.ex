@@ -40,8 +40,10 @@ defmodule Midiex do
   # Returns an output connection:
   # %Midiex.OutConn{
   #   conn_ref: #Reference<0.1633267383.3718381569.210768>,
+  #   midi_port: %Midiex.MidiPort{direction: :input, name: "piano", num: 0, ...},
   #   name: "piano",
-  #   port_num: 0
+  #   port_num: 0,
+  #   virtual: true
   # }
 
   # Send MIDI messages to a connection
@@ -57,13 +59,28 @@ defmodule Midiex do
   Also see the introductory tour in LiveBook at [/livebook/midiex_notebook.livemd](https://github.com/haubie/midiex/blob/main/livebook/midiex_notebook.livemd).
 
   [![Run in Livebook](https://livebook.dev/badge/v1/blue.svg)](https://livebook.dev/run?url=https%3A%2F%2Fgithub.com%2Fhaubie%2Fmidiex%2Fblob%2Fmain%2Flivebook%2Fmidiex_notebook.livemd)
+
+  ## Errors
+  Rather than raising, functions return `{:error, {kind, message}}` when something goes wrong, where `message` is a string describing the error and `kind` is one of:
+  - `:no_driver_found`, `:invalid_port`, `:port_name`, `:poisoned_lock`, `:connection_closed`, `:unsupported`, `:alsa` (Linux only) or `:other` for errors from the MIDI backend
+  - `:backend` when the backend fails to send a message
+  - `:invalid_smf` for a Standard MIDI File that can't be read or written, and `:io` when its file can't be read
+  - `:invalid_argument` for an option out of range, and `:player_stopped` for a command sent to a player that has finished or been stopped
+  - `:handshake_timeout` when no ACK arrives while sending a SysEx dump with a handshake
+  - `:invalid_ump` for Universal MIDI Packets that can't be converted, and `:invalid_ci` for a MIDI-CI message that can't be parsed or built
+
+  `send_msg/3` also returns two other shapes: `{:error, :invalid_data}` when the backend rejects the bytes given, and `{:error, {:invalid_message, reason}}` with `strict: true` when the message is malformed, where `reason` is an atom such as `:missing_status` or `:wrong_length`.
+
+  If the connection behind a subscription can't be made, the subscribing process is also sent `{:error, {kind, message}}`.
   """
   alias Midiex.Backend
 
   defguardp is_output_conn(midi_conn) when is_struct(midi_conn, Midiex.OutConn)
+  defguardp is_input_conn(midi_conn) when is_struct(midi_conn, Midiex.InConn)
   defguardp is_output_port(midi_port) when is_struct(midi_port, Midiex.MidiPort) and midi_port.direction == :output
   defguardp is_input_port(midi_port) when is_struct(midi_port, Midiex.MidiPort) and midi_port.direction == :input
   defguardp is_virtual_input_port(midi_port) when is_struct(midi_port, Midiex.VirtualMidiPort) and midi_port.direction == :input
+  defguardp is_virtual_device(device) when is_struct(device, Midiex.VirtualDevice)
 
 
   # ##########
@@ -98,7 +115,7 @@ defmodule Midiex do
   # ]
   ```
   """
-  def ports(), do: Backend.list_ports()
+  def ports(), do: Backend.list_ports(names_options([]))
 
   @doc section: :ports
   @spec ports(:input | :output) :: [%Midiex.MidiPort{}]
@@ -132,6 +149,20 @@ defmodule Midiex do
   """
   def ports(direction) when is_atom(direction), do: filter_port_direction(ports(), direction)
 
+  @doc section: :ports
+  @spec ports(keyword) :: [%Midiex.MidiPort{}]
+  @doc """
+  Lists MIDI ports available on the system, as `ports/0` does, with the ports listed by a client with the name given.
+
+  Takes the following options:
+  - `client_name:` the name of the client listing the ports, as other software sees it. Defaults to the name set with `set_names/1`, or `"MIDIex"`.
+
+  ```
+  Midiex.ports(client_name: "My App")
+  ```
+  """
+  def ports(opts) when is_list(opts), do: Backend.list_ports(names_options(opts))
+
   @doc section: :ports
   @spec ports(binary | map, (:input | :output)|nil) :: [%Midiex.MidiPort{}]
   @doc """
@@ -175,17 +206,142 @@ defmodule Midiex do
   """
   def port_count(), do: Backend.count_ports()
 
+  @doc section: :ports
+  @spec find_port_by_id(String.t(), :input | :output) :: %Midiex.MidiPort{} | nil
+  @doc """
+  Finds the port with the given `id` (see `Midiex.MidiPort`) and direction, or returns `nil` if there isn't one.
+
+  Unlike a port's `num`, its id stays the same as other devices are plugged in or unplugged, so it can be saved and used to find the same port later.
+
+  ```
+  id = port.id
+
+  # Later, after devices have come and gone
+  Midiex.find_port_by_id(id, :input)
+
+  # Returns:
+  # %Midiex.MidiPort{direction: :input, name: "KeyStep Pro:KeyStep Pro MIDI 1 20:0", num: 1, id: "alsa:20:0", native_id: {20, 0}, ...}
+  ```
+  """
+  def find_port_by_id(id, direction) when is_binary(id) and direction in [:input, :output] do
+    Backend.find_port_by_id(id, direction)
+  end
+
+  @doc section: :ports
+  @spec port_info(%Midiex.MidiPort{} | [%Midiex.MidiPort{}]) :: map | [map] | {:error, term}
+  @doc """
+  Returns details of a port, or of each port in a list, from the backend. Without a port, returns details of every port (see `ports/0`).
+
+  Each is a map with the following keys:
+  - `port:` the `%Midiex.MidiPort{}`
+  - `client_name:` the name of the device or application the port belongs to, e.g. `"KeyStep Pro"`
+  - `port_name:` the name of the port itself, e.g. `"KeyStep Pro MIDI 1"`
+  - `manufacturer:` the device's manufacturer, if the driver gives one (USB devices do), otherwise `nil`
+  - `client_type:` `:kernel` for a device's driver, or `:user` for an application
+  - `card:` the number of the sound card the device is, or `nil`
+  - `capabilities:` what can be done with the port, a list of `:read`, `:write`, `:subscribe_read`, `:subscribe_write` and `:duplex`
+  - `types:` what kind of port it is, a list of `:midi_generic`, `:hardware`, `:software`, `:synth`, `:application` and `:virtual` (a port an application has made for itself, rather than a device's)
+  - `midi_channels:` the number of MIDI channels the port has
+  - `own:` whether the port belongs to this process, e.g. a virtual port made with `create_virtual_output/1`
+
+  Details come from the ALSA sequencer, so are only available on Linux. Elsewhere `{:error, {:unsupported, message}}` is returned, except for the ports of loopback devices (see `Midiex.Loopback`).
+
+  ```
+  # Ports grouped by device, leaving out this application's own virtual ports
+  Midiex.port_info()
+  |> Enum.reject(& &1.own)
+  |> Enum.group_by(& &1.client_name, & &1.port)
+  ```
+  """
+  def port_info(), do: port_info(ports())
+  def port_info(ports) when is_list(ports), do: Backend.port_info(ports)
+
+  def port_info(%Midiex.MidiPort{} = port) do
+    with [info] <- Backend.port_info([port]), do: info
+  end
+
+  @doc section: :ports
+  @spec set_names(keyword) :: :ok
+  @doc """
+  Sets the names other software sees this application's connections and virtual ports by, such as in `aconnect -l` on Linux or in a patchbay.
+
+  Takes the following options:
+  - `client_name:` the name of the client each connection or virtual port is made with, and ports are listed with. By default output connections and virtual outputs are made with a client called `"MIDIex"`, input connections and virtual inputs with one called `"MIDIex input"`, and a virtual device's client is named after the device.
+  - `port_name:` the name of the port a connection is made from, by default `"MIDIex"` for an output connection and `"midir-read-input"` for an input connection. A virtual port is named by the name it's created with.
+
+  Names left out go back to their defaults. The names apply to connections and virtual ports made afterwards, unless they're given their own with the same options, e.g. `open/2`, `subscribe/2` or `create_virtual_output/2`.
+
+  ```
+  Midiex.set_names(client_name: "Drum Machine", port_name: "Drum Machine out")
+
+  # Opened as "Drum Machine:Drum Machine out"
+  Midiex.open(out_port)
+
+  # Opened as "Sequencer:Drum Machine out"
+  Midiex.open(out_port, client_name: "Sequencer")
+  ```
+  """
+  def set_names(opts), do: Backend.set_names(names_options(opts))
+
+  @doc section: :ports
+  @spec names() :: keyword
+  @doc """
+  Returns the names set with `set_names/1`, e.g. `[client_name: "Drum Machine", port_name: nil]`. A name of `nil` is the default.
+  """
+  def names() do
+    %{client_name: client_name, port_name: port_name} = Backend.get_names()
+    [client_name: client_name, port_name: port_name]
+  end
+
+  @doc section: :ports
+  @spec owned_clients() :: [map] | {:error, term}
+  @doc """
+  Lists the MIDI clients this application has open, with their ports and what each port is connected to.
+
+  Each client is a map with the keys:
+  - `client_name:` the client's name, see `set_names/1`
+  - `client:` its number on the ALSA sequencer
+  - `ports:` a list of maps of the client's ports, each with a `port_name:`, an `id:` and `connections:`, the ids of the ports it's connected to. The ids are those of `%Midiex.MidiPort{}`, see `find_port_by_id/2`.
+
+  This includes the client used to receive notifications, but not the ones ports are listed with, which are only open while they're listed. While the loopback backend is enabled, each of the virtual ports made on it is a client, with no `client:` number and no connections listed.
+
+  Only available on Linux, and with the loopback backend.
+
+  ```
+  Midiex.owned_clients()
+
+  # Returns, for example:
+  # [
+  #   %{
+  #     client_name: "Drum Machine",
+  #     client: 129,
+  #     ports: [%{port_name: "Drum Machine out", id: "alsa:129:0", connections: ["alsa:20:0"]}]
+  #   }
+  # ]
+  ```
+  """
+  def owned_clients(), do: Backend.owned_clients()
+
   @doc section: :connections
-  @spec open(%Midiex.MidiPort{direction: :output} | [%Midiex.MidiPort{direction: :output}]) :: %Midiex.OutConn{} | [%Midiex.OutConn{}]
+  @spec open(%Midiex.MidiPort{} | [%Midiex.MidiPort{}], keyword) :: %Midiex.OutConn{} | %Midiex.InConn{} | [%Midiex.OutConn{} | %Midiex.InConn{}]
   @doc """
   Creates a connection to the MIDI port.
 
   Accepts one of the following as a parameter:
   - MIDI output port, e.g. a `%Midiex.MidiPort{direction: :output}` struct
-  - List of MIDI output ports.
+  - MIDI input port, e.g. a `%Midiex.MidiPort{direction: :input}` struct
+  - List of MIDI ports.
+
+  Takes the following options:
+  - `client_name:` the name of the client the connection is made with, as other software sees it
+  - `port_name:` the name of the port the connection is made from
+
+  They default to the names set with `set_names/1`.
 
   Returns an output connection (`%Midiex.OutConn{)`) or a list of output connections if a list was output ports was given as the first parameter.
 
+  For an input port an input connection (`%Midiex.InConn{}`) is returned instead, and the calling process will receive the MIDI messages (`%Midiex.MidiMessage{}`) sent to that port until the connection is closed with `close/1`.
+
   ## Example
 
   ### Connect to a single output port
@@ -264,21 +420,26 @@ defmodule Midiex do
   ]
   ```
   """
-  def open([midi_output_port | rest_ports]) when is_output_port(midi_output_port) do
-    ([Backend.connect(midi_output_port)] ++ open(rest_ports))
+  def open(midi_port, opts \\ [])
+  def open([midi_port | rest_ports], opts) when is_output_port(midi_port) or is_input_port(midi_port) do
+    ([open(midi_port, opts)] ++ open(rest_ports, opts))
   end
-  def open([]), do: []
-  def open(midi_output_port) when is_output_port(midi_output_port), do: Backend.connect(midi_output_port)
+  def open([], _opts), do: []
+  def open(midi_output_port, opts) when is_output_port(midi_output_port), do: Backend.connect(midi_output_port, names_options(opts))
+  def open(midi_input_port, opts) when is_input_port(midi_input_port), do: Backend.connect_input(midi_input_port, names_options(opts))
 
 
   @doc section: :connections
-  @spec close(%Midiex.OutConn{} | [%Midiex.OutConn{}]) :: any
+  @spec close(%Midiex.OutConn{} | %Midiex.InConn{} | %Midiex.VirtualMidiPort{} | %Midiex.VirtualDevice{} | [%Midiex.OutConn{} | %Midiex.InConn{} | %Midiex.VirtualMidiPort{} | %Midiex.VirtualDevice{}]) :: any
   @doc """
-  Closes a MIDI output connection.
+  Closes a MIDI output or input connection, or a virtual input port.
 
   Accepts as the first parameter either a:
   - MIDI output connection, e.g. a `%Midiex.OutConn{}` struct
-  - List of output connections.
+  - MIDI input connection, e.g. a `%Midiex.InConn{}` struct
+  - virtual input port, a `%Midiex.VirtualMidiPort{}` struct, which is removed along with its subscriptions
+  - virtual device, a `%Midiex.VirtualDevice{}` struct, whose input and output are both removed
+  - List of connections.
 
   ## Example
   ```
@@ -290,14 +451,17 @@ defmodule Midiex do
   # Will return :ok if successful
   ```
   """
-  def close([out_conn | rest_conns]) do
-    ([Backend.close_out_conn(out_conn)] ++ close(rest_conns))
+  def close([conn | rest_conns]) do
+    ([close(conn)] ++ close(rest_conns))
   end
   def close([]), do: []
+  def close(in_conn) when is_input_conn(in_conn), do: Backend.close_in_conn(in_conn)
+  def close(virtual_in_port) when is_virtual_input_port(virtual_in_port), do: Backend.close_virtual_input(virtual_in_port)
+  def close(device) when is_virtual_device(device), do: [close(device.input), close(device.output)]
   def close(out_conn), do: Backend.close_out_conn(out_conn)
 
   @doc section: :virtual
-  @spec create_virtual_output(String.t()) :: %Midiex.OutConn{}
+  @spec create_virtual_output(String.t(), keyword) :: %Midiex.OutConn{}
   @doc """
   Creates a virtual output connection.
 
@@ -328,15 +492,24 @@ defmodule Midiex do
   >
   > It also means it will show as `%Midiex.MidiPort{direction: :input}` when calling `Midiex.ports()`.
 
+  The connection's `midi_port` is that input port, so it can be subscribed to, e.g. to check what's sent:
+  ```
+  Midiex.subscribe(piano_conn.midi_port)
+  ```
+
+  Takes the following options:
+  - `client_name:` the name of the client the port belongs to, as other software sees it. Defaults to the name set with `set_names/1`, or `"MIDIex"`.
   """
-  def create_virtual_output(name), do: Backend.create_virtual_output_conn(name)
+  def create_virtual_output(name, opts \\ []), do: Backend.create_virtual_output_conn(name, names_options(opts))
 
   @doc section: :virtual
-  @spec create_virtual_input(String.t()) :: %Midiex.VirtualMidiPort{}
+  @spec create_virtual_input(String.t(), keyword) :: %Midiex.VirtualMidiPort{}
   @doc """
-  Creates a virtual input port struct.
+  Creates a virtual input port.
 
-  Takes a name as the first parameter.
+  Takes a name as the first parameter, and the following options:
+  - `buffer:` how many of the messages the port receives while nothing is subscribed to it are kept, to be sent to the next process to subscribe. The most recent messages are kept, the rest being dropped. Defaults to `0`, dropping every message received while nothing is subscribed.
+  - `client_name:` the name of the client the port belongs to, as other software sees it. Defaults to the name set with `set_names/1`, or `"MIDIex input"`.
 
   This is only available on platforms that support virtual ports (currently every platform but Windows).
 
@@ -348,7 +521,7 @@ defmodule Midiex do
   >
   > It also means it will show as `%Midiex.MidiPort{direction: :output}` when calling `Midiex.ports()`.
   >
-  > Note that it won't be discoverable until the input port is subscribed.
+  > The port can be discovered as soon as it's created, and lasts until it's closed with `close/1`, or until the `%Midiex.VirtualMidiPort{}` is garbage collected.
 
   ## Example
   ```
@@ -356,7 +529,7 @@ defmodule Midiex do
   my_virtual_in = Midiex.create_virtual_input("My Virtual Input")
 
   # This will return a VirtualMidiPort struct in the following format
-  # %Midiex.VirtualMidiPort{direction: :input, name: "My Virtual Input", num: 1}
+  # %Midiex.VirtualMidiPort{direction: :input, name: "My Virtual Input", num: 1, port_ref: #Reference<0.1229765843.2394423314.94621>}
   ```
   The `%Midiex.VirtualMidiPort{}` struct can then be passed to MIDI input port listener functions, such as:
   - `Midiex.subscribe(my_virtual_in)`
@@ -364,25 +537,67 @@ defmodule Midiex do
     - `Midiex.Listener.start_link(port: my_virtual_in)`
     - `Midiex.Listener.subscribe(listener, my_virtual_in)`
 
-  Likewise, once subscribed to, the virtual input port can be unsubscribed to:
+  Likewise, once subscribed to, the virtual input port can be unsubscribed to, which leaves the port open:
   - `Midiex.unsubscribe(my_virtual_in)`
   - If using a Listener GenServer: `Midiex.Listener.unsubscribe(my_virtual_in)`
+
+  To remove the port, close it with `Midiex.close(my_virtual_in)`.
   """
-  def create_virtual_input(name), do: Backend.create_virtual_input(name)
+  def create_virtual_input(name, opts \\ []), do: Backend.create_virtual_input(name, Keyword.get(opts, :buffer, 0), names_options(opts))
+
+  @doc section: :virtual
+  @spec create_virtual_device(String.t(), keyword) :: %Midiex.VirtualDevice{} | {:error, term}
+  @doc """
+  Creates a virtual device, with a virtual input and a virtual output which other software sees as one device, such as a software instrument.
+
+  On Linux the device is one ALSA sequencer client, named after the device unless it's given a `client_name:`, with an input and an output port. Elsewhere it's a virtual input and a virtual output with the same name. Note this is only available on platforms that support virtual ports (currently every platform but Windows).
+
+  Messages sent to the device's input are sent to a process as `%Midiex.MidiMessage{}` structs, with the device's input (a `%Midiex.VirtualMidiPort{}`) as the port. Messages can be sent from the device's output with `send_msg/3`, passing it the device.
+
+  Takes a name as the first parameter, and the following options:
+  - `pid:` the process to send messages to. Defaults to the calling process.
+  - `decode:`, `note_off:`, `filter:` and `sysex:` as for `subscribe/2`.
+  - `client_name:` the name of the device's client, as other software sees it. Defaults to the name set with `set_names/1`, or on Linux the device's name.
+
+  ```
+  synth = Midiex.create_virtual_device("My Synth", decode: true)
+
+  receive do
+    %Midiex.MidiMessage{decoded: {:note_on, _channel, note, _velocity}} -> note
+  end
+
+  Midiex.send_msg(synth, <<0xB0, 7, 100>>)
+  ```
+
+  The device lasts until it's closed with `close/1`, as its input is kept subscribed to. Unsubscribing from its input with `unsubscribe/1` stops messages being sent to the process, leaving the device open.
+  """
+  def create_virtual_device(name, opts \\ []) do
+    Backend.create_virtual_device(Keyword.get(opts, :pid, self()), name, Keyword.get(opts, :decode, false), Keyword.get(opts, :note_off, false), filter_options(opts), sysex_options(opts), names_options(opts))
+  end
 
   # MIDI messaging functions
 
   @doc section: :messages
-  @spec send_msg(%Midiex.OutConn{} | [%Midiex.OutConn{}], binary) :: %Midiex.OutConn{} | [%Midiex.OutConn{}]
+  @spec send_msg(%Midiex.OutConn{} | %Midiex.VirtualDevice{} | [%Midiex.OutConn{}], binary, keyword) :: %Midiex.OutConn{} | %Midiex.VirtualDevice{} | [%Midiex.OutConn{}] | {:error, term}
   @doc """
   Sends a binary MIDI message to one or more output connection(s).
 
   Takes the following parameters:
-  1. Output connection: which is an %Midiex.OutConn{} struct or a list of Midiex.OutConn{} structs
+  1. Output connection: which is an %Midiex.OutConn{} struct or a list of Midiex.OutConn{} structs. A `%Midiex.VirtualDevice{}` can be given too, sending from its output.
   2. MIDI message: which is in a binary format, such as <<0x90, 60, 127>>
+  3. Options (optional keyword list):
+     - `strict: true` checks the message is a single, complete MIDI 1.0 message before sending it: that it starts with a status byte, has the right number of data bytes for that status, that its data bytes are below 0x80 and that a SysEx is terminated by 0xF7. Defaults to `false`.
 
   Returns the same output connection or a list of output connections passed to it. This is so you can chain messages together.
 
+  With running status turned on for the connection (see `set_running_status/3`), which is only possible where the backend takes messages as a stream of bytes, a repeated status byte is left out before the message is sent.
+
+  If the message can't be sent, `{:error, reason}` is returned instead (in place of the connection when sending to a list), where reason is:
+  - `:invalid_data` if the backend rejected the message
+  - `{:backend, message}` if the backend failed to send it
+  - `{:invalid_message, reason}` if strict checking failed, with reason being one of `:empty`, `:missing_status`, `:undefined_status`, `:unexpected_end_of_exclusive`, `:wrong_length`, `:invalid_data_byte` or `:unterminated_sysex`
+  - `{:connection_closed, message}` if the connection has been closed
+
   ## Example
   ### Send a message to single output connection
   ```
@@ -409,18 +624,167 @@ defmodule Midiex do
   |> tap(fn _ -> :timer.sleep(3000) end) # wait 3 seconds
   |> Midiex.send_msg(Midiex.Message.note_off(:D3))
   ```
+  ### Check the message before sending it
+  ```
+  Midiex.send_msg(out_conn, <<0x90, 60>>, strict: true)
+  # Returns:
+  {:error, {:invalid_message, :wrong_length}}
+  ```
   """
-  def send_msg([out_port_conn | rest_conn], midi_msg) when is_output_conn(out_port_conn) do
-    [Backend.send_msg(out_port_conn, midi_msg)] ++ send_msg(rest_conn, midi_msg)
+  def send_msg(out_port_conn, midi_msg, opts \\ [])
+  def send_msg([out_port_conn | rest_conn], midi_msg, opts) when is_output_conn(out_port_conn) do
+    [send_msg(out_port_conn, midi_msg, opts)] ++ send_msg(rest_conn, midi_msg, opts)
+  end
+  def send_msg([], _midi_msg, _opts), do: []
+  def send_msg(out_port_conn, midi_msg, opts) when is_output_conn(out_port_conn) do
+    if Keyword.get(opts, :strict, false),
+      do: Backend.send_msg_strict(out_port_conn, midi_msg),
+      else: Backend.send_msg(out_port_conn, midi_msg)
+  end
+  def send_msg(device, midi_msg, opts) when is_virtual_device(device) do
+    with %Midiex.OutConn{} <- send_msg(device.output, midi_msg, opts), do: device
+  end
+
+  @doc section: :messages
+  @spec now_us() :: non_neg_integer()
+  @doc """
+  Returns the current time, in microseconds, on the monotonic clock used by `send_at/2`.
+
+  The clock starts the first time it's used, so only the difference between two readings is meaningful.
+  """
+  def now_us(), do: Backend.now_us()
+
+  @doc section: :messages
+  @spec send_at(%Midiex.OutConn{}, [{non_neg_integer(), binary}]) :: %Midiex.OutConn{} | {:error, term}
+  @doc """
+  Schedules MIDI messages to be sent at a given time.
+
+  Takes an output connection and a list of `{timestamp_us, message}` tuples, where `timestamp_us` is a time on the clock returned by `now_us/0`. Each connection has its own scheduler (an OS thread in Rust) which sends each message at its time, without the few milliseconds of jitter that comes from timing messages in Elixir. Messages with the same timestamp are sent in the order they were scheduled, and messages with a timestamp in the past are sent straight away.
+
+  Returns the output connection, so calls can be chained together.
+
+  See also `flush/1`, `cancel_all/1` and `queue_len/1`.
+
+  ## Example
+  ```
+  out_conn = Midiex.ports(:output) |> List.first() |> Midiex.open()
+
+  # Play the note D3 for half a second, starting 100 ms from now
+  start = Midiex.now_us() + 100_000
+
+  Midiex.send_at(out_conn, [
+    {start, Midiex.Message.note_on(:D3)},
+    {start + 500_000, Midiex.Message.note_off(:D3)}
+  ])
+  ```
+  """
+  def send_at(out_port_conn, timed_msgs) when is_output_conn(out_port_conn), do: Backend.send_at(out_port_conn, timed_msgs)
+
+  @doc section: :messages
+  @spec flush(%Midiex.OutConn{}) :: %Midiex.OutConn{} | {:error, term}
+  @doc """
+  Sends every message scheduled on the output connection with `send_at/2` straight away, rather than at their scheduled times.
+
+  Returns the output connection once the messages have been sent.
+  """
+  def flush(out_port_conn) when is_output_conn(out_port_conn), do: Backend.flush(out_port_conn)
+
+  @doc section: :messages
+  @spec cancel_all(%Midiex.OutConn{}) :: %Midiex.OutConn{} | {:error, term}
+  @doc """
+  Drops every message scheduled on the output connection with `send_at/2` without sending it.
+
+  Returns the output connection.
+  """
+  def cancel_all(out_port_conn) when is_output_conn(out_port_conn), do: Backend.cancel_all(out_port_conn)
+
+  @doc section: :messages
+  @spec queue_len(%Midiex.OutConn{}) :: non_neg_integer() | {:error, term}
+  @doc """
+  Returns the number of messages scheduled on the output connection with `send_at/2` that are yet to be sent.
+  """
+  def queue_len(out_port_conn) when is_output_conn(out_port_conn), do: Backend.queue_len(out_port_conn)
+
+  @doc section: :messages
+  @spec set_running_status(%Midiex.OutConn{}, boolean, keyword) :: %Midiex.OutConn{} | {:error, term}
+  @doc """
+  Turns running status on or off for an output connection.
+
+  With running status on, a channel message's status byte is left out when it's the same as the one sent before it, as MIDI 1.0 allows. This saves up to a third of the bytes sent, which matters on 5-pin DIN links (31.25 kbaud), e.g. for dense controller or pitch bend streams. Only send to ports that understand running status, such as hardware interfaces. Real-time messages don't affect the running status, and any other system message cancels it.
+
+  Only supported on Linux, where ALSA takes messages as a stream of bytes, and with the loopback backend. CoreMIDI and Windows need every message sent with its status byte, so `{:error, {:unsupported, message}}` is returned there.
+
+  Takes the following option:
+  - `refresh:` how often, in milliseconds, the status byte is sent again anyway, so a device that missed it (or was plugged in later) picks it up. Defaults to `1_000`, `0` never sends it again.
+
+  Returns the output connection, so calls can be chained together.
+
+  ## Example
+  ```
+  out_conn =
+    Midiex.ports("MIDI Out", :output)
+    |> List.first()
+    |> Midiex.open()
+    |> Midiex.set_running_status(true, refresh: 500)
+
+  # Only the first message is sent with its 0xE0 status byte
+  for bend <- 0..16383//128, do: Midiex.send_msg(out_conn, Midiex.Message.pitch_bend(bend))
+  ```
+  """
+  def set_running_status(out_port_conn, enabled, opts \\ []) when is_output_conn(out_port_conn) and is_boolean(enabled) do
+    Backend.set_running_status(out_port_conn, enabled, Keyword.get(opts, :refresh, 1_000))
+  end
+
+  @doc section: :messages
+  @spec send_sysex(%Midiex.OutConn{}, binary, keyword) :: {:ok, integer} | {:error, term}
+  @doc """
+  Sends a dump of one or more System Exclusive messages in small chunks, paced on its own OS thread in Rust, for hardware whose input buffer would be overrun by the whole dump at once.
+
+  Takes an output connection and a binary of one or more SysExs (such as the contents of a `.syx` file), returning `{:ok, id}` straight away, or `{:error, {:invalid_message, reason}}` if the binary isn't made up of well formed SysExs. Each SysEx (packet) is split into chunks, and the calling process is sent:
+  - `{:midiex_sysex, id, {:progress, bytes_sent, total_bytes}}` after each chunk
+  - `{:midiex_sysex, id, :done}` once the whole dump has been sent
+  - `{:midiex_sysex, id, {:error, error}}` if sending fails, e.g. `{:error, {:handshake_timeout, message}}`
+
+  Takes the following options:
+  - `chunk_size:` the most bytes to send at once. Chunks never span two packets. Defaults to `256`, `0` sends each packet whole. On Linux each chunk is sent as an ALSA SysEx event, and on macOS as a CoreMIDI packet. Windows can't send part of a SysEx, so there each packet is sent whole.
+  - `inter_chunk_delay_us:` how long to wait between chunks, in microseconds. Defaults to `10_000`.
+  - `handshake:` waits for the device to acknowledge each packet before sending the next, see below
+
+  ## Handshake mode
+  Some devices reply to each packet of a dump with an acknowledgement (ACK) SysEx, and need it to be waited for. The `handshake:` option takes:
+  - `port:` the input port (`%Midiex.MidiPort{direction: :input}`) the device replies on, which is connected to for the length of the dump
+  - `ack:` the start of the ACK SysEx, e.g. `<<0xF0, 0x7E, 0x00, 0x7F>>` for a universal ACK. Defaults to `<<0xF0>>`, any SysEx.
+  - `timeout:` how long to wait for each ACK, in milliseconds. Defaults to `1_000`.
+
+  ## Example
+  ```
+  out_conn = Midiex.ports("MicroFreak", :output) |> Midiex.open()
+
+  {:ok, id} = Midiex.send_sysex(out_conn, File.read!("patches.syx"), chunk_size: 128, inter_chunk_delay_us: 20_000)
+
+  receive do
+    {:midiex_sysex, ^id, :done} -> :ok
+  end
+  ```
+  """
+  def send_sysex(out_port_conn, dump, opts \\ []) when is_output_conn(out_port_conn) and is_binary(dump) do
+    handshake =
+      case Keyword.get(opts, :handshake) do
+        nil ->
+          nil
+
+        handshake ->
+          {Keyword.fetch!(handshake, :port), Keyword.get(handshake, :ack, <<0xF0>>), Keyword.get(handshake, :timeout, 1_000)}
+      end
+
+    Backend.send_sysex(out_port_conn, dump, Keyword.get(opts, :chunk_size, 256), Keyword.get(opts, :inter_chunk_delay_us, 10_000), handshake)
   end
-  def send_msg([], _midi_msg), do: []
-  def send_msg(out_port_conn, midi_msg) when is_output_conn(out_port_conn), do: Backend.send_msg(out_port_conn, midi_msg)
 
 
   @doc section: :messages
   # Midiex callback functions
   @doc """
-  Low-level API for subscribing to one or more MIDI input ports. This includes ports created via `create_virtual_input/1`.
+  Low-level API for subscribing to one or more MIDI input ports. This includes ports created via `create_virtual_input/2`.
 
   The first parameter accepts either:
   - A single `%Midiex.MidiPort{direction: :input}` or `%Midiex.VirtualMidiPort{direction: :input}` struct
@@ -428,6 +792,66 @@ defmodule Midiex do
 
   The calling process will receive MIDI messages from the ports subscribed to. The source of the message will be differentiated by the input port, but also consider using a different calling process for different inputs if they need to be handled separately.
 
+  Takes the following options:
+  - `decode:` if `true`, each message is also decoded (see `decode/2`), and put in the `decoded:` key of the `%Midiex.MidiMessage{}`. Messages which can't be decoded have a `decoded:` of `nil`. Defaults to `false`.
+  - `note_off:` if `true`, decoded Note On messages with a velocity of 0 become Note Off messages, see `decode/2`. Defaults to `false`.
+  - `filter:` a keyword list of the messages to receive, see below. By default every message is received.
+  - `batch:` sends messages in batches rather than one at a time, see below. Not supported for virtual input ports.
+  - `sysex:` limits on reassembling System Exclusive messages, see below
+  - `client_name:` and `port_name:` the names of the client and port the connection to the port is made with, as for `open/2`. Not used for virtual input ports.
+
+  ## Filtering
+  Filters are checked in Rust as each message arrives, so messages filtered out are never sent to the calling process. This is useful for devices sending MIDI clock (24 messages per quarter note) or active sensing. The `filter:` option takes:
+  - `ignore:` a list of `:sysex`, `:time` (clock and MIDI time code quarter frames) and `:active_sense`, which are dropped by the MIDI backend itself
+  - `channels:` a list of channels (0 to 15) to receive channel messages on. System messages aren't on a channel so aren't affected.
+  - `allow:` a list of message types to receive, named as in `decode/2`, e.g. `[:note_on, :note_off]`. Channel mode messages are `:control_change`.
+  - `deny:` a list of message types not to receive, e.g. `[:clock, :active_sensing]`
+  - `notes:` a range of notes to receive in Note On, Note Off and Polyphonic Aftertouch messages, e.g. `36..51`
+  - `controls:` a range of controllers to receive in Control Change messages
+  - `min_velocity:` the lowest velocity of Note On messages to receive. Note Ons with a velocity of 0 are always received, as they end a note.
+
+  ```
+  # Only notes on channel 9 (drums), ignoring clock and active sensing
+  Midiex.subscribe(input_port, filter: [ignore: [:time, :active_sense], channels: [9], allow: [:note_on, :note_off]])
+  ```
+
+  ## System Exclusive messages
+  MIDI backends can deliver a long SysEx, such as a patch dump, in several chunks. These are joined back together, so each SysEx is received as one message from its 0xF0 to its 0xF7. Real-time messages (such as clock) sent in the middle of a SysEx are received as separate messages.
+
+  The `sysex:` option takes:
+  - `max_size:` the largest SysEx, in bytes, to receive. Larger ones are dropped, and the calling process is sent `{:error, :sysex_overflow}`. Defaults to `1_048_576` (1 MiB).
+  - `timeout:` how long, in milliseconds, to wait for the next chunk of a SysEx. If it doesn't arrive, the SysEx is dropped and the calling process is sent `{:error, :sysex_timeout}`. Any more of it arriving later is dropped too. Defaults to `1_000`, `0` waits forever.
+
+  A SysEx interrupted by another message (other than a real-time one) is dropped and the calling process is sent `{:error, :unterminated_sysex}`.
+
+  ```
+  # Receive patch dumps of up to 64 KB
+  Midiex.subscribe(input_port, sysex: [max_size: 65_536, timeout: 2_000])
+  ```
+
+  ## Batching
+  When a port sends thousands of messages a second, sending each one as its own `%Midiex.MidiMessage{}` puts pressure on the calling process's mailbox. With the `batch:` option messages are collected in Rust and sent together as:
+
+  ```
+  {:midi_batch, id, [{timestamp, binary}, ...]}
+  ```
+
+  where `id` is an integer identifying the subscription, used in place of the port struct. Subscribing returns `{:ok, id}` rather than `:ok`, so subscribe to each port separately when batching more than one. The `decode:` option doesn't apply to batches, use `decode/2` on each binary instead.
+
+  The `batch:` option is either `true`, or a keyword list of:
+  - `max_messages:` the most messages to send in one batch, from `1` to `65_536`. Defaults to `64`.
+  - `max_delay:` the longest, in microseconds, a message waits before its batch is sent. Defaults to `10_000` (10 ms).
+
+  A batch is sent when either is reached, whichever comes first.
+
+  ```
+  {:ok, id} = Midiex.subscribe(input_port, batch: [max_messages: 128, max_delay: 5_000])
+
+  receive do
+    {:midi_batch, ^id, messages} -> Enum.each(messages, fn {timestamp, data} -> IO.inspect({timestamp, data}) end)
+  end
+  ```
+
   ## Example
   ```
   # Get a list of MIDI input ports on the system
@@ -487,12 +911,69 @@ defmodule Midiex do
   # Msg received: [146, 84, 30]
   ```
   """
-  def subscribe([midi_port | rest_ports]) when is_input_port(midi_port) or is_virtual_input_port(midi_port) do
-    if rest_ports != [], do: subscribe(rest_ports)
-    subscribe(midi_port)
+  def subscribe(midi_port, opts \\ [])
+  def subscribe([midi_port | rest_ports], opts) when is_input_port(midi_port) or is_virtual_input_port(midi_port) do
+    if rest_ports != [], do: subscribe(rest_ports, opts)
+    subscribe(midi_port, opts)
+  end
+  def subscribe(midi_port, opts) when is_input_port(midi_port) do
+    Backend.subscribe(midi_port, Keyword.get(opts, :decode, false), Keyword.get(opts, :note_off, false), filter_options(opts), batch_options(opts), sysex_options(opts), names_options(opts))
+  end
+  def subscribe(midi_port, opts) when is_virtual_input_port(midi_port) do
+    Backend.subscribe_virtual_input(midi_port, Keyword.get(opts, :decode, false), Keyword.get(opts, :note_off, false), filter_options(opts), sysex_options(opts))
+  end
+
+  @doc section: :messages
+  @spec decode(binary, keyword) :: tuple | {:error, term}
+  @doc """
+  Decodes a single MIDI 1.0 message into a tagged tuple.
+
+  Channels are in the range 0 to 15, as elsewhere in Midiex. The message is checked in the same way as `send_msg/3` with `strict: true`, returning `{:error, {:invalid_message, reason}}` if it isn't exactly one well formed message.
+
+  Channel voice messages:
+  - `{:note_off, channel, note, velocity}`
+  - `{:note_on, channel, note, velocity}`
+  - `{:poly_aftertouch, channel, note, pressure}`
+  - `{:control_change, channel, control, value}`
+  - `{:program_change, channel, program}`
+  - `{:channel_aftertouch, channel, pressure}`
+  - `{:pitch_bend, channel, bend}` where `bend` is the 14 bit value from 0 to 16383, 8192 being the centre
+
+  Channel mode messages (control changes 120 to 127):
+  - `{:all_sound_off, channel}`
+  - `{:reset_all_controllers, channel}`
+  - `{:local_control, channel, on?}`
+  - `{:all_notes_off, channel}`
+  - `{:omni_off, channel}` and `{:omni_on, channel}`
+  - `{:mono_on, channel, number_of_channels}` and `{:poly_on, channel}`
+
+  System common messages:
+  - `{:sysex, manufacturer_id, payload}` where `manufacturer_id` is a binary of 1 byte, or 3 bytes for IDs starting with 0, and `payload` is the binary between the ID and the End of Exclusive. Any real-time bytes within the SysEx are left out.
+  - `{:mtc_quarter_frame, type, value}`
+  - `{:song_position, beats}`
+  - `{:song_select, song}`
+  - `{:tune_request}`
+
+  System real-time messages: `{:clock}`, `{:start}`, `{:continue}`, `{:stop}`, `{:active_sensing}` and `{:reset}`.
+
+  Takes the following option:
+  - `note_off:` if `true`, a Note On with a velocity of 0 is decoded as `{:note_off, channel, note, 64}`, as the two are meant to be treated the same. Defaults to `false`.
+
+  ## Example
+  ```
+  Midiex.decode(<<0x92, 60, 100>>)
+  # Returns: {:note_on, 2, 60, 100}
+
+  Midiex.decode(<<0x92, 60, 0>>, note_off: true)
+  # Returns: {:note_off, 2, 60, 64}
+
+  Midiex.decode(Midiex.Message.sysex(0x41, <<1, 52>>))
+  # Returns: {:sysex, <<65>>, <<1, 52>>}
+  ```
+  """
+  def decode(message, opts \\ []) when is_binary(message) do
+    Backend.decode(message, Keyword.get(opts, :note_off, false))
   end
-  def subscribe(midi_port) when is_input_port(midi_port), do: Backend.subscribe(midi_port)
-  def subscribe(midi_port) when is_virtual_input_port(midi_port), do: Backend.subscribe_virtual_input(midi_port)
 
   @doc section: :messages
   @doc """
@@ -566,7 +1047,7 @@ defmodule Midiex do
   @doc """
   Low-level API for subscribing to MIDI notification messages.
 
-  Currently only MacOS is supported.
+  Currently MacOS and Linux (using the ALSA sequencer) are supported.
 
   The calling process will receive MIDI notification messages.
 
@@ -585,6 +1066,8 @@ defmodule Midiex do
   @doc """
   Ensures that hot-plugging of devices is supported on MacOS.
 
+  On Linux the ALSA sequencer always reports the current ports, so this function only returns `:ok`.
+
   By default on MacOS, Midiex port based functions, such as `Midiex.ports()` will only list ports visible when the Elixir app was first started. That means devices added or removed afterwards will not be reflected in `Midiex.ports()`.
 
   > #### Important {: .warning}
@@ -600,10 +1083,257 @@ defmodule Midiex do
   """
   def hotplug(), do: Backend.hotplug()
 
+  # Standard MIDI files
+
+  @doc section: :smf
+  @spec read_smf(binary) :: %Midiex.Smf{} | {:error, term}
+  @doc """
+  Reads a Standard MIDI File (a `.mid` file), returning a `%Midiex.Smf{}` struct.
+
+  Takes either a path to the file, or the file's contents as a binary (anything starting with the `MThd` header is taken to be the file's contents).
+
+  Formats 0, 1 and 2 are supported. See `Midiex.Smf` for how the file's tracks and events are represented.
+
+  Returns `{:error, {:io, message}}` if the file can't be read, or `{:error, {:invalid_smf, message}}` if it isn't a valid Standard MIDI File.
+
+  ## Example
+  ```
+  smf = Midiex.read_smf("my_song.mid")
+
+  # Or, from a binary
+  smf = File.read!("my_song.mid") |> Midiex.read_smf()
+
+  # Returns:
+  %Midiex.Smf{
+    format: 1,
+    division: {:ppq, 480},
+    tracks: [
+      [
+        {0, {:track_name, "Tempo"}},
+        {0, {:time_signature, 4, 4, 24, 8}},
+        {0, {:tempo, 500000}},
+        {0, :end_of_track}
+      ],
+      [
+        {0, {:track_name, "Piano"}},
+        {0, {:midi, <<0x90, 60, 100>>}},
+        {480, {:midi, <<0x80, 60, 64>>}},
+        {0, :end_of_track}
+      ]
+    ]
+  }
+  ```
+  """
+  def read_smf(path_or_binary) when is_binary(path_or_binary), do: Backend.read_smf(path_or_binary)
+
+  @doc section: :smf
+  @spec write_smf(%Midiex.Smf{} | [[{non_neg_integer(), term}]] | [{non_neg_integer(), term}], keyword) :: binary | {:error, term}
+  @doc """
+  Writes tracks of events to a format 0 or format 1 Standard MIDI File, returning the file's contents as a binary.
+
+  Takes as its first parameter either:
+  - a list of tracks, each being a list of `{delta_ticks, event}` tuples, using the same events as `read_smf/1` (see `Midiex.Smf`)
+  - a single track, as a list of `{delta_ticks, event}` tuples
+  - a `%Midiex.Smf{}` struct, such as one returned by `read_smf/1`
+
+  Every track is ended with an end of track event, so these don't need to be added (any already in a track are moved to its end).
+
+  Takes the following options:
+  - `format:` `0` or `1`. Defaults to `0` for a single track, otherwise `1` (or the struct's format). Writing several tracks as format 0 merges them into one.
+  - `ppq:` ticks per quarter note, defaulting to `480` (or the struct's division, which an explicit `ppq:` replaces)
+  - `division:` instead of `ppq:`, either `{:ppq, ticks}` or `{:smpte, frames_per_second, ticks_per_frame}`
+  - `tempo:` either a tempo in microseconds per quarter note (e.g. `500_000` for 120 BPM), or a tempo map as a list of `{absolute_ticks, microseconds_per_quarter_note}` tuples. In format 1 the tempo map is written as its own first track, in format 0 it's merged into the track.
+  - `running_status:` whether to leave out status bytes which are the same as the previous message's, making the file smaller. Defaults to `true`.
+
+  Returns `{:error, {:invalid_smf, message}}` if an event can't be written.
+
+  ## Example
+  ```
+  track = [
+    {0, {:track_name, "Piano"}},
+    {0, {:midi, Midiex.Message.note_on(:C4)}},
+    {480, {:midi, Midiex.Message.note_off(:C4)}}
+  ]
+
+  smf_binary = Midiex.write_smf(track, ppq: 480, tempo: 500_000)
+  File.write!("my_song.mid", smf_binary)
+  ```
+  """
+  def write_smf(tracks_or_smf, opts \\ [])
+
+  def write_smf(%Midiex.Smf{} = smf, opts) do
+    # An explicit ppq: overrides the struct's division, as well as division: does
+    defaults =
+      if Keyword.has_key?(opts, :ppq),
+        do: [format: smf.format],
+        else: [format: smf.format, division: smf.division]
+
+    write_smf(smf.tracks, Keyword.merge(defaults, opts))
+  end
+
+  def write_smf([{_delta, _event} | _rest] = track, opts), do: write_smf([track], opts)
+
+  def write_smf(tracks, opts) when is_list(tracks) do
+    format = Keyword.get(opts, :format, if(length(tracks) == 1, do: 0, else: 1))
+    division = Keyword.get(opts, :division, {:ppq, Keyword.get(opts, :ppq, 480)})
+    running_status = Keyword.get(opts, :running_status, true)
+
+    tempo_map =
+      case Keyword.get(opts, :tempo, []) do
+        tempo when is_integer(tempo) -> [{0, tempo}]
+        tempo_map when is_list(tempo_map) -> tempo_map
+      end
+
+    Backend.write_smf(%Midiex.Smf{format: format, division: division, tracks: tracks}, tempo_map, running_status)
+  end
+
+  @doc section: :smf
+  @spec play_smf(%Midiex.OutConn{}, %Midiex.Smf{} | binary, keyword) :: %Midiex.Player{} | {:error, term}
+  @doc """
+  Plays a Standard MIDI File to an output connection, returning a `%Midiex.Player{}` which can be used to control playback.
+
+  Takes an output connection, and either a `%Midiex.Smf{}` struct or anything `read_smf/1` accepts (a path or the file's contents).
+
+  The file is played on its own OS thread in Rust, using the file's tempo map. The calling process is sent messages with the player's position and when it finishes, see `Midiex.Player` for these and for pausing, seeking, looping and stopping playback.
+
+  Takes the following options:
+  - `tempo_scale:` how fast to play relative to the file's tempo, e.g. `2.0` for double speed. Defaults to `1.0`.
+  - `position_interval:` how often, in milliseconds, to send position messages. Defaults to `100`, `0` turns them off.
+  - `start:` the position to start playing from, as `{:tick, ticks}` or `{:ms, milliseconds}`
+  - `loop:` a `{start_position, end_position}` tuple to loop between
+
+  ## Example
+  ```
+  out_conn = Midiex.ports(:output) |> List.first() |> Midiex.open()
+
+  player = Midiex.play_smf(out_conn, "my_song.mid", tempo_scale: 0.8)
+
+  # The calling process receives messages such as:
+  # {:midiex_player, 1, {:position, 480, 500}}
+  # {:midiex_player, 1, :finished}
+  ```
+  """
+  def play_smf(out_port_conn, smf, opts \\ [])
+
+  def play_smf(out_port_conn, %Midiex.Smf{} = smf, opts) when is_output_conn(out_port_conn) do
+    tempo_scale = Keyword.get(opts, :tempo_scale, 1.0) / 1
+    position_interval = Keyword.get(opts, :position_interval, 100) || 0
+    start = opts |> Keyword.get(:start) |> Midiex.Player.position()
+
+    loop =
+      case Keyword.get(opts, :loop) do
+        {start_position, end_position} -> {Midiex.Player.position(start_position), Midiex.Player.position(end_position)}
+        nil -> nil
+      end
+
+    Backend.play_smf(out_port_conn, smf, tempo_scale, position_interval, start, loop)
+  end
+
+  def play_smf(out_port_conn, path_or_binary, opts) when is_binary(path_or_binary) do
+    case read_smf(path_or_binary) do
+      %Midiex.Smf{} = smf -> play_smf(out_port_conn, smf, opts)
+      error -> error
+    end
+  end
+
+  @doc section: :smf
+  @spec start_recording(%Midiex.MidiPort{}, keyword) :: %Midiex.Recorder{} | {:error, term}
+  @doc """
+  Starts recording messages received on an input port, returning a `%Midiex.Recorder{}`. Stop the recording and get the take with `stop_recording/2`.
+
+  Messages are buffered in Rust with their timestamps, see `Midiex.Recorder`.
+
+  Takes the following options:
+  - `tempo:` the tempo to record at, in microseconds per quarter note. Defaults to `500_000` (120 BPM).
+  - `ppq:` the number of ticks per quarter note. Defaults to `480`.
+  - `count_in:` how long to wait before recording starts, as `{:tick, ticks}` or `{:ms, milliseconds}`. Messages received during the count in aren't recorded.
+  - `punch_in:` only record messages from this position, measured from the end of the count in
+  - `punch_out:` only record messages before this position, measured from the end of the count in
+  - `quantize:` the grid, in ticks, to round each message's time to, e.g. `120` for sixteenth notes at 480 PPQ. Defaults to `1` (the nearest tick).
+
+  Positions can also be given as a plain integer, which is taken to be ticks.
+
+  ## Example
+  ```
+  in_port = Midiex.ports(:input) |> List.first()
+
+  # Record at 100 BPM with a one bar count in, quantized to eighth notes
+  recorder = Midiex.start_recording(in_port, tempo: 600_000, count_in: {:tick, 1920}, quantize: 240)
+
+  # Play something, then
+  %Midiex.Smf{tracks: [track]} = Midiex.stop_recording(recorder)
+  ```
+  """
+  def start_recording(midi_port, opts \\ []) when is_input_port(midi_port) do
+    tempo = Keyword.get(opts, :tempo, 500_000)
+    ppq = Keyword.get(opts, :ppq, 480)
+    count_in = opts |> Keyword.get(:count_in) |> Midiex.Player.position()
+    punch_in = opts |> Keyword.get(:punch_in) |> Midiex.Player.position()
+    punch_out = opts |> Keyword.get(:punch_out) |> Midiex.Player.position()
+    quantize = Keyword.get(opts, :quantize, 1)
+
+    Backend.start_recording(midi_port, tempo, ppq, count_in, punch_in, punch_out, quantize)
+  end
+
+  @doc section: :smf
+  @spec stop_recording(%Midiex.Recorder{}, keyword) :: %Midiex.Smf{} | binary | {:error, term}
+  @doc """
+  Stops a recording started with `start_recording/2`, closing its input connection, and returns the take.
+
+  By default the take is returned as a format 0 `%Midiex.Smf{}` with a single track, which starts with a tempo event. Pass `as: :binary` to get the contents of a `.mid` file instead, ready to be written with `File.write/2`.
+
+  Stopping a recording again returns the same take.
+
+  ## Example
+  ```
+  File.write!("take.mid", Midiex.stop_recording(recorder, as: :binary))
+  ```
+  """
+  def stop_recording(%Midiex.Recorder{} = recorder, opts \\ []) do
+    Backend.stop_recording(recorder, Keyword.get(opts, :as, :smf) == :binary)
+  end
+
   # #######
   # HELPERS
   # #######
 
+  # The Rust side expects every key of the filter to be present
+  defp filter_options(opts) do
+    filter = Keyword.get(opts, :filter, [])
+
+    %{
+      ignore: Keyword.get(filter, :ignore, []),
+      channels: Keyword.get(filter, :channels),
+      allow: Keyword.get(filter, :allow),
+      deny: Keyword.get(filter, :deny, []),
+      notes: filter |> Keyword.get(:notes) |> range_bounds(),
+      controls: filter |> Keyword.get(:controls) |> range_bounds(),
+      min_velocity: Keyword.get(filter, :min_velocity, 0)
+    }
+  end
+
+  defp batch_options(opts) do
+    case Keyword.get(opts, :batch) do
+      nil -> nil
+      false -> nil
+      true -> batch_options(batch: [])
+      batch -> {Keyword.get(batch, :max_messages, 64), Keyword.get(batch, :max_delay, 10_000)}
+    end
+  end
+
+  # Names left out are nil, for the Rust side to fill in
+  defp names_options(opts) do
+    %{client_name: Keyword.get(opts, :client_name), port_name: Keyword.get(opts, :port_name)}
+  end
+
+  defp sysex_options(opts) do
+    sysex = Keyword.get(opts, :sysex, [])
+    {Keyword.get(sysex, :max_size, 1_048_576), Keyword.get(sysex, :timeout, 1_000)}
+  end
+
+  defp range_bounds(nil), do: nil
+  defp range_bounds(first..last//_), do: {min(first, last), max(first, last)}
+
   defp filter_port_name(ports_list, comparison_name_or_pattern, opts) do
     direction = Keyword.get(opts, :direction, nil)
     ports_list
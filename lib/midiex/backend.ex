@@ -31,23 +31,48 @@ defmodule Midiex.Backend do
   # ##########
 
   # MIDI port functions
-  def list_ports(), do: err()
+  def list_ports(_names), do: err()
   def count_ports(), do: err()
-  def connect(_midi_port), do: err()
+  def find_port_by_id(_id, _direction), do: err()
+  def port_info(_midi_ports), do: err()
+  def set_names(_names), do: err()
+  def get_names(), do: err()
+  def owned_clients(), do: err()
+  def connect(_midi_port, _names), do: err()
   def close_out_conn(_out_conn), do: err()
-  def create_virtual_output_conn(_name \\ "MIDIex-virtual-output"), do: err()
-  def create_virtual_input(_name \\ "MIDIex-virtual-input"), do: err()
+  def connect_input(_midi_port, _names), do: err()
+  def close_in_conn(_in_conn), do: err()
+  def create_virtual_output_conn(_name, _names), do: err()
+  def create_virtual_input(_name, _buffer_size, _names), do: err()
+  def close_virtual_input(_virtual_midi_port), do: err()
+  def create_virtual_device(_pid, _name, _decode, _note_off, _filter, _sysex, _names), do: err()
 
   # MIDI messaging functions
   def send_msg(_out_port_conn, _midi_msg), do: err()
+  def send_msg_strict(_out_port_conn, _midi_msg), do: err()
+  def decode(_message, _note_off), do: err()
+  def ump_from_midi1(_message, _group, _midi2), do: err()
+  def ump_to_midi1(_words), do: err()
+  def ump_packets(_words), do: err()
+  def ump_scale_up(_value, _src_bits, _dst_bits), do: err()
+  def ump_scale_down(_value, _src_bits, _dst_bits), do: err()
+  def now_us(), do: err()
+  def send_at(_out_port_conn, _timed_msgs), do: err()
+  def flush(_out_port_conn), do: err()
+  def cancel_all(_out_port_conn), do: err()
+  def queue_len(_out_port_conn), do: err()
+  def set_running_status(_out_port_conn, _enabled, _refresh_ms), do: err()
+  def new_stream_decoder(), do: err()
+  def stream_decode(_decoder, _bytes), do: err()
+  def send_sysex(_out_port_conn, _dump, _chunk_size, _inter_chunk_delay_us, _handshake), do: err()
 
   # Midiex callback functions
-  def subscribe(_midi_port), do: err()
+  def subscribe(_midi_port, _decode, _note_off, _filter, _batch, _sysex, _names), do: err()
   def unsubscribe_all_ports(), do: err()
   def unsubscribe_port(_midi_port), do: err()
   def unsubscribe_port_by_index(_port_index), do: err()
 
-  def subscribe_virtual_input(_name \\ "MIDIex-virtual-input"), do: err()
+  def subscribe_virtual_input(_virtual_midi_port, _decode, _note_off, _filter, _sysex), do: err()
   def unsubscribe_virtual_port(_name), do: err()
   def unsubscribe_all_virtual_ports(), do: err()
   def get_subscribed_ports(), do: err()
@@ -56,6 +81,40 @@ defmodule Midiex.Backend do
   def notifications(), do: err()
   def hotplug(), do: err()
 
+  # Standard MIDI file functions
+  def read_smf(_path_or_binary), do: err()
+  def write_smf(_smf, _tempo_map, _running_status), do: err()
+  def play_smf(_out_port_conn, _smf, _tempo_scale, _position_interval, _start, _loop), do: err()
+  def player_pause(_player), do: err()
+  def player_resume(_player), do: err()
+  def player_seek(_player, _position), do: err()
+  def player_set_tempo_scale(_player, _tempo_scale), do: err()
+  def player_loop(_player, _loop), do: err()
+  def player_stop(_player), do: err()
+  def start_recording(_midi_port, _tempo, _ppq, _count_in, _punch_in, _punch_out, _quantize), do: err()
+  def stop_recording(_recorder, _as_binary), do: err()
+
+  # MIDI Capability Inquiry functions
+  def ci_parse(_sysex), do: err()
+  def ci_build(_message), do: err()
+  def ci_new_muid(), do: err()
+  def ci_start_responder(_out_conn, _input, _options), do: err()
+  def ci_send(_responder, _message), do: err()
+  def ci_discover(_responder), do: err()
+  def ci_peers(_responder), do: err()
+  def ci_set_property(_responder, _resource, _data), do: err()
+  def ci_set_profile(_responder, _profile, _enabled), do: err()
+  def ci_stop_responder(_responder), do: err()
+
+  # Loopback backend functions
+  def loopback_enable(_device_names), do: err()
+  def loopback_disable(), do: err()
+  def loopback_enabled(), do: err()
+  def loopback_add_device(_name, _input, _output), do: err()
+  def loopback_remove_device(_name), do: err()
+  def loopback_set_clock(_us), do: err()
+  def loopback_advance_clock(_us), do: err()
+  def loopback_clock(), do: err()
 
   defp err(), do: :erlang.nif_error(:nif_not_loaded)
 
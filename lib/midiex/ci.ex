@@ -0,0 +1,118 @@
+defmodule Midiex.CI do
+  @moduledoc """
+  Functions for MIDI 2.0 Capability Inquiry (MIDI-CI), which lets MIDI devices discover each other and find out what they can do, over ordinary SysEx messages.
+
+  MIDI-CI messages are parsed into, and built from, `%Midiex.CI.Message{}` structs with `parse/1` and `build/1`, so they can be sent with `Midiex.send_msg/3` and read from `Midiex.subscribe/2` like any other SysEx.
+
+  A responder (see `start_responder/3`) listens for MIDI-CI messages on an input and answers them on an output connection, so a Midiex application can be discovered by MIDI-CI aware software such as a DAW. It answers:
+  - **Discovery**, with the device's identity and the largest SysEx it takes
+  - **Endpoint Information**, with the device's product instance id
+  - **Protocol Negotiation**, offering MIDI 1.0, the only protocol a MIDI 1.0 port can carry
+  - **Profile Configuration**, listing the device's profiles and turning them on and off
+  - **Property Exchange**, getting and setting the device's resources, e.g. `"DeviceInfo"`. A `"ResourceList"` is made from the resources given, unless one is given itself. Data set larger than 1 MB is refused, and a set whose next chunk doesn't arrive within 10 seconds is dropped. Subscriptions aren't supported.
+
+  The responder can also start conversations of its own, as an initiator, with `Midiex.CI.Responder.discover/1` and `Midiex.CI.Responder.send/2`. The MUIDs of the devices it discovers, or which discover it, are kept in its list of peers.
+
+  ## Example
+  ```
+  # A virtual device other applications can see, made of a virtual output and input
+  out_conn = Midiex.create_virtual_output("My Synth")
+  in_port = Midiex.create_virtual_input("My Synth")
+
+  responder =
+    Midiex.CI.start_responder(out_conn, in_port,
+      manufacturer: <<0x7D, 0, 0>>,
+      profiles: [{<<0x7E, 0x00, 0x01, 0x01, 0x00>>, false}],
+      properties: %{"DeviceInfo" => ~s({"manufacturer":"Me","model":"My Synth"})}
+    )
+
+  # Find the other MIDI-CI devices listening to the virtual output
+  Midiex.CI.Responder.discover(responder)
+
+  receive do
+    {:midiex_ci, _id, %Midiex.CI.Message{type: :discovery_reply, source: muid}} -> muid
+  end
+  ```
+  """
+
+  alias Midiex.Backend
+
+  @broadcast_muid 0x0FFFFFFF
+
+  @doc """
+  Parses a MIDI-CI SysEx into a `%Midiex.CI.Message{}`.
+
+  Returns `{:error, {:invalid_ci, message}}` if the SysEx isn't a MIDI-CI message, or ends before all of its fields.
+
+  ## Example
+  ```
+  Midiex.CI.parse(<<0xF0, 0x7E, 0x7F, 0x0D, 0x7E, 0x02, 1, 0, 0, 0, 0x7F, 0x7F, 0x7F, 0x7F, 1, 0, 0, 0, 0xF7>>)
+  # Returns:
+  # %Midiex.CI.Message{type: :invalidate_muid, version: 2, device_id: 127, source: 1, destination: 0x0FFFFFFF, body: %{target: 1}}
+  ```
+  """
+  @spec parse(binary) :: %Midiex.CI.Message{} | {:error, term}
+  def parse(sysex) when is_binary(sysex), do: Backend.ci_parse(sysex)
+
+  @doc """
+  Builds a `%Midiex.CI.Message{}` into a SysEx, ready to be sent with `Midiex.send_msg/3`.
+
+  Returns `{:error, {:invalid_ci, message}}` if a field is too large for the message, e.g. a data byte of `0x80` or above.
+  """
+  @spec build(%Midiex.CI.Message{}) :: binary | {:error, term}
+  def build(%Midiex.CI.Message{} = message), do: Backend.ci_build(message)
+
+  @doc """
+  Returns a random MUID, outside the range reserved by the MIDI-CI specification.
+  """
+  @spec new_muid() :: non_neg_integer
+  def new_muid(), do: Backend.ci_new_muid()
+
+  @doc """
+  Returns the broadcast MUID, `0x0FFFFFFF`, used to send a message to every device.
+  """
+  @spec broadcast_muid() :: non_neg_integer
+  def broadcast_muid(), do: @broadcast_muid
+
+  @doc """
+  Starts a responder, which listens for MIDI-CI messages on the input and answers them on the output connection.
+
+  The input is either an input port (`%Midiex.MidiPort{direction: :input}`) or a virtual input (see `Midiex.create_virtual_input/2`), which the responder subscribes to until it's stopped. Usually the output connection is to the same device as the input, or is a virtual output created with `Midiex.create_virtual_output/1` with the same name as a virtual input.
+
+  Only messages addressed to the responder's MUID, or broadcast, are answered. The calling process is sent:
+  - `{:midiex_ci, id, %Midiex.CI.Message{}}` for each of those messages, whether or not it was answered
+  - `{:midiex_ci, id, {:profile, profile_id, enabled}}` when another device turns one of the responder's profiles on or off
+  - `{:midiex_ci, id, {:property, resource, data}}` when another device sets a property
+  - `{:midiex_ci, id, {:error, reason}}` when a reply can't be built or sent
+
+  where `id` is the `id` of the `%Midiex.CI.Responder{}` returned.
+
+  Takes the following options:
+  - `muid:` the responder's MUID. Defaults to a new one, see `new_muid/0`.
+  - `manufacturer:` the 3 byte SysEx id of the device's manufacturer, a 1 byte id being followed by two zeros. Defaults to `<<0x7D, 0, 0>>` (non-commercial use).
+  - `family:` and `model:` 14-bit numbers identifying the device. Default to `0`.
+  - `version:` the device's 4 byte software version. Defaults to `<<0, 0, 0, 0>>`.
+  - `max_sysex_size:` the largest SysEx the device takes. Defaults to `4096`.
+  - `product_instance_id:` a binary, such as a serial number, telling apart devices of the same model. Defaults to `""`.
+  - `profiles:` a list of `{profile_id, enabled}` for each profile the device has, each profile id being 5 bytes. Defaults to `[]`.
+  - `properties:` a map of Property Exchange resource names to their data, usually JSON. Defaults to `%{}`.
+  - `auto_reply:` whether to answer inquiries. If `false` messages are only passed on to the calling process. Defaults to `true`.
+  """
+  @spec start_responder(%Midiex.OutConn{}, %Midiex.MidiPort{} | %Midiex.VirtualMidiPort{}, keyword) :: %Midiex.CI.Responder{} | {:error, term}
+  def start_responder(%Midiex.OutConn{} = out_conn, input, opts \\ []) do
+    options = %{
+      muid: Keyword.get_lazy(opts, :muid, &new_muid/0),
+      manufacturer: Keyword.get(opts, :manufacturer, <<0x7D, 0, 0>>),
+      family: Keyword.get(opts, :family, 0),
+      model: Keyword.get(opts, :model, 0),
+      version: Keyword.get(opts, :version, <<0, 0, 0, 0>>),
+      max_sysex_size: Keyword.get(opts, :max_sysex_size, 4096),
+      product_instance_id: Keyword.get(opts, :product_instance_id, ""),
+      profiles: Keyword.get(opts, :profiles, []),
+      properties: opts |> Keyword.get(:properties, %{}) |> Enum.to_list(),
+      auto_reply: Keyword.get(opts, :auto_reply, true)
+    }
+
+    Backend.ci_start_responder(out_conn, input, options)
+  end
+end
@@ -0,0 +1,53 @@
+defmodule Midiex.CI.Message do
+  @moduledoc """
+  A struct representing a MIDI Capability Inquiry (MIDI-CI) message, as parsed by `Midiex.CI.parse/1` or built by `Midiex.CI.build/1`.
+
+  MIDI-CI messages are universal non-realtime SysExs (`0xF0 0x7E device_id 0x0D ...`), sent from one MUID to another. A MUID is a random 28-bit number each MIDI-CI device picks for itself (see `Midiex.CI.new_muid/0`). Messages for every device are sent to the broadcast MUID, `0x0FFFFFFF`.
+
+  The keys are as follows:
+  - *type* an atom for the kind of message, e.g. `:discovery` or `:get_property`
+  - *version* the MIDI-CI message version. Defaults to `2` (MIDI-CI 1.2).
+  - *device_id* `0x7F` for the whole port (the function block), `0x7E` for the group, or a channel from `0` to `15`. Defaults to `0x7F`.
+  - *source* the MUID of the device sending the message
+  - *destination* the MUID of the device the message is for. Defaults to the broadcast MUID.
+  - *body* a map of the message's other fields, depending on its type
+
+  ## Types and bodies
+  Binaries are used for fixed length ids (a manufacturer is 3 bytes, a version 4, profile and protocol ids 5), and for data such as Property Exchange headers. Keys left out when building a message are taken to be `0` or empty.
+
+  | Type | Body keys |
+  | ---- | --------- |
+  | `:discovery` | `manufacturer`, `family`, `model`, `version`, `categories`, `max_sysex_size`, `output_path` |
+  | `:discovery_reply` | as `:discovery`, plus `function_block` |
+  | `:endpoint_inquiry` | `status` |
+  | `:endpoint_reply` | `status`, `data` |
+  | `:invalidate_muid` | `target` |
+  | `:ack`, `:nak` | `original` (the sub-ID #2 of the message being answered), `status_code`, `status_data`, `details`, `text` |
+  | `:protocol_negotiation`, `:protocol_negotiation_reply` | `authority`, `protocols` |
+  | `:set_protocol` | `authority`, `protocol` |
+  | `:test_protocol`, `:test_protocol_reply` | `authority`, `data` |
+  | `:protocol_confirmed` | `authority` |
+  | `:profile_inquiry` | none |
+  | `:profile_inquiry_reply` | `enabled`, `disabled` (lists of profile ids) |
+  | `:set_profile_on`, `:profile_enabled`, `:profile_disabled` | `profile`, `channels` |
+  | `:set_profile_off`, `:profile_added`, `:profile_removed` | `profile` |
+  | `:profile_specific_data` | `profile`, `data` |
+  | `:property_capabilities`, `:property_capabilities_reply` | `max_requests`, `major`, `minor` |
+  | `:get_property`, `:get_property_reply`, `:set_property`, `:set_property_reply`, `:subscription`, `:subscription_reply`, `:property_notify` | `request_id`, `header`, `chunks`, `chunk`, `data` |
+  | `:other` | `sub_id`, `data` (everything after the destination MUID) |
+
+  ## Example
+  ```
+  %Midiex.CI.Message{
+    type: :get_property,
+    version: 2,
+    device_id: 127,
+    source: 0x1234567,
+    destination: 0x0ABCDEF,
+    body: %{request_id: 1, header: ~s({"resource":"DeviceInfo"}), chunks: 1, chunk: 1, data: ""}
+  }
+  ```
+  """
+
+  defstruct type: nil, version: 2, device_id: 0x7F, source: 0, destination: 0x0FFFFFFF, body: %{}
+end
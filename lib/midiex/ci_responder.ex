@@ -0,0 +1,68 @@
+defmodule Midiex.CI.Responder do
+  @moduledoc """
+  A struct representing a MIDI-CI responder, as returned by `Midiex.CI.start_responder/3`.
+
+  The responder answers MIDI-CI inquiries on its own thread in Rust, passing the messages it receives on to the process which started it (see `Midiex.CI.start_responder/3`).
+
+  The keys are as follows:
+  - *responder_ref* the reference to the responder in Rust
+  - *id* an integer identifying the responder in the messages it sends
+  - *muid* the responder's MUID
+
+  If the struct is garbage collected the responder's input connection is closed, without other devices being told its MUID is no longer in use. Call `stop/1` to do both.
+  """
+
+  import Kernel, except: [send: 2]
+
+  alias Midiex.Backend
+
+  defstruct ~w/responder_ref id muid/a
+
+  @doc """
+  Sends a message from the responder, its `source` being set to the responder's MUID.
+
+  ## Example
+  ```
+  # Ask a device discovered earlier for its DeviceInfo
+  Midiex.CI.Responder.send(responder, %Midiex.CI.Message{
+    type: :get_property,
+    destination: muid,
+    body: %{request_id: 1, header: ~s({"resource":"DeviceInfo"}), chunks: 1, chunk: 1}
+  })
+  ```
+  """
+  def send(%__MODULE__{} = responder, %Midiex.CI.Message{} = message), do: Backend.ci_send(responder, message)
+
+  @doc """
+  Broadcasts a Discovery message. The devices which reply are added to the responder's peers, and their replies are sent to the process which started the responder.
+  """
+  def discover(%__MODULE__{} = responder), do: Backend.ci_discover(responder)
+
+  @doc """
+  Lists the devices the responder has had a Discovery or Reply to Discovery from, and which haven't since invalidated their MUID.
+
+  Each is a map of its `muid`, `manufacturer`, `family`, `model`, `version` and `max_sysex_size`.
+  """
+  def peers(%__MODULE__{} = responder), do: Backend.ci_peers(responder)
+
+  @doc """
+  Sets the data of a Property Exchange resource, adding the resource if the responder doesn't have it.
+  """
+  def set_property(%__MODULE__{} = responder, resource, data) when is_binary(resource) and is_binary(data) do
+    Backend.ci_set_property(responder, resource, data)
+  end
+
+  @doc """
+  Turns one of the responder's profiles on or off, broadcasting a Profile Enabled or Profile Disabled Report.
+
+  Returns `{:error, {:invalid_argument, message}}` if the responder doesn't have the profile.
+  """
+  def set_profile(%__MODULE__{} = responder, profile_id, enabled) when is_binary(profile_id) and is_boolean(enabled) do
+    Backend.ci_set_profile(responder, profile_id, enabled)
+  end
+
+  @doc """
+  Stops the responder, broadcasting an Invalidate MUID message for its MUID and closing its input connection.
+  """
+  def stop(%__MODULE__{} = responder), do: Backend.ci_stop_responder(responder)
+end
@@ -0,0 +1,91 @@
+defmodule Midiex.Loopback do
+  @moduledoc """
+  An in-process loopback backend, standing in for the OS's MIDI backend so Midiex (and applications built on it) can be tested without sound hardware or a MIDI driver, e.g. on a Linux CI runner.
+
+  While the loopback backend is enabled, `Midiex.ports/0` and `Midiex.port_count/0` list only the ports of loopback devices. Each device works like a loopback cable: messages sent to its output port with `Midiex.send_msg/3` are received on its input port by `Midiex.subscribe/2`, `Midiex.open/1` and the other functions taking an input port. Virtual inputs and outputs are also created as loopback devices, with only one port each.
+
+  Messages are timestamped with the loopback clock, which starts at `0` and only moves when it's set with `set_clock/1` or `advance_clock/1`, so the timestamps a test sees are the same every time it's run.
+
+  ## Example
+  ```
+  # In test/test_helper.exs
+  Midiex.Loopback.enable(["Loopback 1"])
+
+  # In a test
+  [output] = Midiex.ports("Loopback 1", :output)
+  [input] = Midiex.ports("Loopback 1", :input)
+
+  Midiex.subscribe(input)
+  out_conn = Midiex.open(output)
+
+  Midiex.Loopback.set_clock(1_000)
+  Midiex.send_msg(out_conn, <<0x90, 60, 100>>)
+
+  receive do
+    %Midiex.MidiMessage{data: [0x90, 60, 100], timestamp: 1_000} -> :ok
+  end
+  ```
+  """
+
+  alias Midiex.Backend
+
+  @doc """
+  Switches to the loopback backend, with a loopback device for each name given, each with an input and an output port.
+
+  Any loopback devices there were are removed, and the clock is set back to `0`.
+  """
+  @spec enable([String.t()]) :: :ok
+  def enable(device_names \\ ["Loopback"]) when is_list(device_names), do: Backend.loopback_enable(device_names)
+
+  @doc """
+  Switches back to the OS's MIDI backend, removing every loopback device. Connections to loopback devices stop sending and receiving.
+  """
+  @spec disable() :: :ok
+  def disable(), do: Backend.loopback_disable()
+
+  @doc """
+  Returns `true` if the loopback backend is enabled.
+  """
+  @spec enabled?() :: boolean
+  def enabled?(), do: Backend.loopback_enabled()
+
+  @doc """
+  Adds a loopback device, like plugging in a new MIDI device.
+
+  Takes the following options:
+  - `input:` whether the device has an input port. Defaults to `true`.
+  - `output:` whether the device has an output port. Defaults to `true`.
+
+  Returns `{:error, {:unsupported, message}}` if the loopback backend isn't enabled.
+  """
+  @spec add_device(String.t(), keyword) :: :ok | {:error, term}
+  def add_device(name, opts \\ []) when is_binary(name) do
+    Backend.loopback_add_device(name, Keyword.get(opts, :input, true), Keyword.get(opts, :output, true))
+  end
+
+  @doc """
+  Removes the loopback devices with this name, like unplugging a MIDI device. Sending to them returns an error, and connections listening to them stop receiving.
+
+  Returns `{:error, {:invalid_port, message}}` if there are none.
+  """
+  @spec remove_device(String.t()) :: :ok | {:error, term}
+  def remove_device(name) when is_binary(name), do: Backend.loopback_remove_device(name)
+
+  @doc """
+  Sets the loopback clock, in microseconds. Messages sent from now on are timestamped with this time.
+  """
+  @spec set_clock(non_neg_integer) :: :ok
+  def set_clock(us) when is_integer(us) and us >= 0, do: Backend.loopback_set_clock(us)
+
+  @doc """
+  Moves the loopback clock on by this many microseconds, returning the new time.
+  """
+  @spec advance_clock(non_neg_integer) :: non_neg_integer
+  def advance_clock(us) when is_integer(us) and us >= 0, do: Backend.loopback_advance_clock(us)
+
+  @doc """
+  Returns the time on the loopback clock, in microseconds.
+  """
+  @spec clock() :: non_neg_integer
+  def clock(), do: Backend.loopback_clock()
+end
@@ -0,0 +1,47 @@
+defmodule Midiex.InConn do
+  @moduledoc """
+  A struct representing an open connection to a MIDI input port.
+
+  Messages received on the port are sent to the process which opened the connection as `Midiex.MidiMessage` structs.
+
+  The keys are as follows:
+  - *conn_ref* the reference (e.g. `#Reference<0.2239960018.1937899544.176288>`) to the connection object in midir (Rust).
+  - *name* a string containing the name of the port this connection is to
+  - *port_num* a integer representing the index of the input port.
+
+  The connection stays open until it is closed with `Midiex.close/1`, or until the struct is garbage collected.
+
+  ## Documentation from midir
+  See MidiInputConnection at: https://docs.rs/midir/latest/midir/struct.MidiInputConnection.html
+
+  ## Example
+  ```
+  # Pass a port from taken from Midiex.ports(:input)
+  # e.g. port = Midiex.ports(:input) |> List.first()
+  port =
+    %Midiex.MidiPort{
+      direction: :input,
+      name: "IAC Driver Bus 1",
+      num: 0,
+      port_ref: #Reference<0.3876911033.1674706968.249863>
+    }
+
+  input_conn = Midiex.open(port)
+  ```
+  input_conn will look something like this:
+  ```
+   %Midiex.InConn{
+      conn_ref: #Reference<0.3876911033.1674706945.249917>,
+      name: "IAC Driver Bus 1",
+      port_num: 0
+    }
+  ```
+  An input connection can be closed as follows:
+  ```
+  Midiex.close(input_conn)
+  # :ok is returned if successful
+  ```
+  """
+
+  defstruct ~w/conn_ref name port_num/a
+end
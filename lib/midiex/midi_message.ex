@@ -5,9 +5,10 @@ defmodule Midiex.MidiMessage do
   These are recieved via the `Midiex.subscribe()` function or from the `Midiex.Listener` GenServer.
 
   The keys are as follows:
-  - `port:` which is the input port (`%Midiex.MidiPort{}`) that sent the message
+  - `port:` which is the input port (`%Midiex.MidiPort{}`) that sent the message, or the virtual input (`%Midiex.VirtualMidiPort{}`) it was sent to
   - `data:` the MIDI message data, usually in the form of a three item list, e.g. [153, 60, 70]
-  - `timestamp:` from the [midir docs](https://docs.rs/midir/latest/midir/struct.MidiInput.html#method.connect): "a timestamp (in microseconds) designating the time since some unspecified point in the past (which will not change during the lifetime of an input connection)".
+  - `timestamp:` for virtual inputs as well as ports, from the [midir docs](https://docs.rs/midir/latest/midir/struct.MidiInput.html#method.connect): "a timestamp (in microseconds) designating the time since some unspecified point in the past (which will not change during the lifetime of an input connection)".
+  - `decoded:` the message decoded into a tagged tuple, e.g. `{:note_on, 9, 60, 70}`, when subscribed with the `decode: true` option (see `Midiex.decode/2`), otherwise `nil`
 
   ## Example messages
   ```
@@ -48,7 +49,7 @@ defmodule Midiex.MidiMessage do
   See `Midiex.Listener` for examples of subscribing to MIDI messages and adding your own callback functions to process them.
   """
 
-  defstruct ~w/port data timestamp/a
+  defstruct ~w/port data timestamp decoded/a
 
 
 end
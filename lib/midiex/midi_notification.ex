@@ -2,7 +2,7 @@ defmodule Midiex.MidiNotification do
   @moduledoc """
   A struct representing notifications of MIDI changes.
 
-  This is currently only implemented on MacOS and is capturing added or removed messages only (e.g. a device or port has been added or removed).
+  This is currently implemented on MacOS and Linux (ALSA) and is capturing added or removed messages only (e.g. a device or port has been added or removed).
 
   An example use of this is for hot swapping of devices, responding to if a device has been added or removed.
 
@@ -17,6 +17,12 @@ defmodule Midiex.MidiNotification do
   - `parent_id:` the unique numerical ID reported by coreaudio for the parent
   - `native_id:` the unique numerical ID reported by coreaudio for the port
 
+  On Linux these come from the ALSA sequencer instead. Clients are reported with a `direction:` of `:device`. Ports are reported once for each direction they support, with:
+  - `parent_name:` the name of the ALSA client the port belongs to
+  - `parent_type:` always `:device`
+  - `parent_id:` the ALSA client number
+  - `native_id:` the ALSA client and port numbers packed as `client * 256 + port`, e.g. port `24:0` is `6144`
+
   ## Example
   ```
   # KeyStep Pro keyboard has been hot-plugged into the Mac:
@@ -4,8 +4,10 @@ defmodule Midiex.OutConn do
 
   The keys are as follows:
   - *conn_ref* the reference (e.g. `#Reference<0.2239960018.1937899544.176288>`) to the connection object in midir (Rust).
+  - *midi_port* the `%Midiex.MidiPort{}` this connection is to. For a virtual output (see `Midiex.create_virtual_output/1`) this is the port other applications see, which is listed as an input.
   - *name* a string containing the name of the port this connection is to
   - *port_num* a integer representing the index of the output port.
+  - *virtual* `true` if this is a virtual output, created for other applications to connect to, otherwise `false`.
 
   ## Documentation from midir
   See MidiOutputConnection at: https://docs.rs/midir/latest/midir/struct.MidiOutputConnection.html
@@ -28,8 +30,10 @@ defmodule Midiex.OutConn do
   ```
    %Midiex.OutConn{
       conn_ref: #Reference<0.3876911033.1674706945.249916>,
+      midi_port: %Midiex.MidiPort{direction: :output, name: "IAC Driver Bus 1", num: 0, ...},
       name: "IAC Driver Bus 1",
-      port_num: 0
+      port_num: 0,
+      virtual: false
     }
   ```
   An output port can be closed as follows:
@@ -39,5 +43,5 @@ defmodule Midiex.OutConn do
   ```
   """
 
-  defstruct ~w/conn_ref name port_num/a
+  defstruct ~w/conn_ref midi_port name port_num virtual/a
 end
@@ -5,12 +5,16 @@ defmodule Midiex.MidiPort do
   The keys are as follows:
   - *direction* which is an atom of value `:input` or `:output` (for input or output port)
   - *name* which is a string the backend reported as the name of the port. With MIDI hardware, this is often the name of the device.
-  - *num* an integer index representing the port starting at 0. Both input and output ports will start with 0.
+  - *num* an integer index representing the port starting at 0. Both input and output ports will start with 0. As it's the port's place in the list of ports, it changes when devices are plugged in or unplugged.
+  - *id* a string identifying the port which, unlike `num`, doesn't change as other ports come and go. It's made from the port's native id where there is one, e.g. `"alsa:20:0"` on Linux or `"coremidi:1234567"` on macOS, otherwise it's the port's name. Save it to find the port again later with `Midiex.find_port_by_id/2`.
+  - *native_id* the backend's own id for the port: an ALSA sequencer `{client, port}` tuple on Linux, a CoreMIDI unique id on macOS, or `nil` on other platforms.
   - *port_ref* a reference (e.g. `#Reference<0.2239960018.1937899544.176288>`) to the port object in midir (Rust).
 
   ## Notes from midir
   How a port is identified internally is backend-dependent. If the backend allows it, port objects remain valid when other ports in the system change (i.e. it is not just an index).
 
+  Two ports are equal in Rust (e.g. when unsubscribing) if they have the same direction and `id`.
+
   -  MidiInputPort: https://docs.rs/midir/latest/midir/struct.MidiInputPort.html
   -  MidiOutputPort: https://docs.rs/midir/latest/midir/struct.MidiInputPort.html
 
@@ -28,12 +32,16 @@ defmodule Midiex.MidiPort do
       direction: :input,
       name: "IAC Driver Bus 1",
       num: 0,
+      id: "coremidi:1184426375",
+      native_id: 1184426375,
       port_ref: #Reference<0.2239960018.1937899544.176288>
     },
     %Midiex.MidiPort{
       direction: :output,
       name: "IAC Driver Bus 1",
       num: 0,
+      id: "coremidi:1184426376",
+      native_id: 1184426376,
       port_ref: #Reference<0.2239960018.1937899544.176289>
     }
   ]
@@ -41,6 +49,6 @@ defmodule Midiex.MidiPort do
 
   """
 
-  defstruct ~w/direction name num port_ref/a
+  defstruct ~w/direction name num id native_id port_ref/a
 
 end
@@ -7,7 +7,7 @@ defmodule Midiex.Notifier do
   ## How this works
   On Mac, a callback function needs to be created to specially handle MIDI notification messages, such as when a device has been physically plugged (`:added`) or unplugged (`:removed`). This callback is implemented in the Rust side of this library using [coremidi](https://chris-zen.github.io/coremidi/coremidi/struct.Client.html#method.new_with_notifications).
 
-  The notifications will be delivered on MacOS to a Rust thread with the specific 'run loop' (using [CFRunLoop from the core_foundation](https://docs.rs/core-foundation/latest/core_foundation/runloop/struct.CFRunLoop.html) Rust library) that was created when the `Midiex.notifications/0` function was first called. This function is called automatically when this GenServer is started.
+  The notifications will be delivered on MacOS to a Rust thread with the specific 'run loop' (using [CFRunLoop from the core_foundation](https://docs.rs/core-foundation/latest/core_foundation/runloop/struct.CFRunLoop.html) Rust library) that was created when the `Midiex.notifications/0` function was first called. This function is called automatically when this GenServer is started. On Linux they are read from the ALSA sequencer's announce port on a Rust thread instead.
 
   Any (`:added`) or (`:removed`) notifications will be sent to this GenServer from the Rust thread.
 
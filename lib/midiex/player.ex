@@ -0,0 +1,89 @@
+defmodule Midiex.Player do
+  @moduledoc """
+  A struct representing a Standard MIDI File being played to an output connection, as returned by `Midiex.play_smf/3`.
+
+  The file is played on its own OS thread in Rust, which converts the file's ticks to time using its tempo map and sends each event to the output connection at its time. This avoids the jitter of timing messages with Elixir timers.
+
+  The keys are as follows:
+  - *player_ref* the reference to the player in Rust
+  - *id* an integer identifying the player in the messages it sends
+
+  ## Messages
+  The process which started the player is sent:
+  - `{:midiex_player, id, {:position, tick, ms}}` with the current position in the file, as both ticks and milliseconds, at the rate set by the `position_interval:` option of `Midiex.play_smf/3`
+  - `{:midiex_player, id, :finished}` when the end of the file is reached
+  - `{:midiex_player, id, :stopped}` when the player is stopped with `stop/1`, or garbage collected
+
+  ## Positions
+  Positions in the file, used by `seek/2`, `loop/3` and the options of `Midiex.play_smf/3`, are either `{:tick, ticks}` or `{:ms, milliseconds}`. A plain integer is taken to be ticks.
+
+  ## Example
+  ```
+  out_conn = Midiex.ports(:output) |> List.first() |> Midiex.open()
+
+  player = Midiex.play_smf(out_conn, "my_song.mid")
+
+  # Pause and resume
+  Midiex.Player.pause(player)
+  Midiex.Player.resume(player)
+
+  # Play at half speed, looping the first four bars (at 480 ticks per quarter note)
+  player
+  |> Midiex.Player.set_tempo_scale(0.5)
+  |> Midiex.Player.loop({:tick, 0}, {:tick, 7680})
+
+  # Stop, which also sends all notes off
+  Midiex.Player.stop(player)
+  ```
+
+  If the struct is garbage collected playback stops, as with `stop/1`.
+  """
+
+  alias Midiex.Backend
+
+  defstruct ~w/player_ref id/a
+
+  @doc """
+  Pauses playback, sending all notes off on every channel.
+  """
+  def pause(%__MODULE__{} = player), do: Backend.player_pause(player)
+
+  @doc """
+  Resumes playback from where it was paused.
+  """
+  def resume(%__MODULE__{} = player), do: Backend.player_resume(player)
+
+  @doc """
+  Moves playback to a position in the file, either `{:tick, ticks}` or `{:ms, milliseconds}`, sending all notes off on every channel.
+  """
+  def seek(%__MODULE__{} = player, position), do: Backend.player_seek(player, position(position))
+
+  @doc """
+  Sets how fast the file plays relative to its tempo map, e.g. `2.0` for double speed or `0.5` for half speed.
+  """
+  def set_tempo_scale(%__MODULE__{} = player, tempo_scale) when is_number(tempo_scale) and tempo_scale > 0 do
+    Backend.player_set_tempo_scale(player, tempo_scale / 1)
+  end
+
+  @doc """
+  Loops playback between two positions in the file (see `seek/2`), jumping back to `start_position` on reaching `end_position`.
+
+  Pass `nil` as the start and end positions to stop looping.
+  """
+  def loop(player, start_position, end_position)
+  def loop(%__MODULE__{} = player, nil, nil), do: Backend.player_loop(player, nil)
+
+  def loop(%__MODULE__{} = player, start_position, end_position) do
+    Backend.player_loop(player, {position(start_position), position(end_position)})
+  end
+
+  @doc """
+  Stops playback, sending all notes off on every channel. A stopped player can't be started again.
+  """
+  def stop(%__MODULE__{} = player), do: Backend.player_stop(player)
+
+  @doc false
+  def position(nil), do: nil
+  def position(ticks) when is_integer(ticks), do: {:tick, ticks}
+  def position({unit, _value} = position) when unit in [:tick, :ms], do: position
+end
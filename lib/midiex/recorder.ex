@@ -0,0 +1,18 @@
+defmodule Midiex.Recorder do
+  @moduledoc """
+  A struct representing a recording from an input port, as returned by `Midiex.start_recording/2`.
+
+  While recording, messages received on the port are buffered in Rust with their timestamps, rather than being sent to an Elixir process. When recording is stopped with `Midiex.stop_recording/2` the take is returned as a format 0 `%Midiex.Smf{}` (or a `.mid` binary), with each message's time converted to ticks using the recording's tempo and PPQ.
+
+  Only channel messages and System Exclusive messages are recorded. Other system messages, such as clock, are dropped.
+
+  The keys are as follows:
+  - *recorder_ref* the reference to the recorder in Rust
+  - *name* the name of the input port being recorded
+  - *port_num* the number of the input port being recorded
+
+  If the struct is garbage collected the input connection is closed and the take is lost.
+  """
+
+  defstruct ~w/recorder_ref name port_num/a
+end
@@ -0,0 +1,52 @@
+defmodule Midiex.Smf do
+  @moduledoc """
+  A struct representing a Standard MIDI File (a `.mid` file), as returned by `Midiex.read_smf/1`.
+
+  The keys are as follows:
+  - *format* the file's format, one of:
+    - `0` a single track
+    - `1` several tracks played at the same time, usually with the first track holding the tempo map
+    - `2` several independent single track patterns
+  - *division* how delta times are measured, either:
+    - `{:ppq, ticks}` ticks per quarter note, or
+    - `{:smpte, frames_per_second, ticks_per_frame}` SMPTE time, where frames per second is 24, 25, 29 (30 drop frame) or 30
+  - *tracks* a list of tracks, each being a list of `{delta_ticks, event}` tuples, where `delta_ticks` is the number of ticks since the previous event in the track.
+
+  ## Events
+  Each event is one of:
+  - `{:midi, binary}` a channel message, e.g. `{:midi, <<0x90, 60, 100>>}`. Running status in the file is expanded, so every message has its status byte and can be passed straight to `Midiex.send_msg/2`.
+  - `{:sysex, binary}` a system exclusive message, including the leading `0xF0`
+  - `{:escape, binary}` an escape (`0xF7`) event, which holds bytes to be sent as they are, such as a SysEx split across events or real-time messages
+  - `{:tempo, microseconds_per_quarter_note}`
+  - `{:time_signature, numerator, denominator, clocks_per_click, thirty_seconds_per_quarter_note}`, e.g. `{:time_signature, 6, 8, 24, 8}` for 6/8
+  - `{:key_signature, sharps_flats, :major | :minor}`, where sharps_flats is negative for flats, e.g. `{:key_signature, -3, :minor}` for C minor
+  - `{:track_name, string}`
+  - `:end_of_track`
+  - `{:meta, type, binary}` any other meta event, such as text (`1`), copyright (`2`), lyrics (`5`) or markers (`6`)
+
+  ## Example
+  ```
+  Midiex.read_smf("test/fixtures/format0.mid")
+
+  # Returns:
+  %Midiex.Smf{
+    format: 0,
+    division: {:ppq, 96},
+    tracks: [
+      [
+        {0, {:tempo, 500000}},
+        {0, {:midi, <<192, 5>>}},
+        {0, {:midi, <<144, 60, 100>>}},
+        {0, {:midi, <<144, 64, 100>>}},
+        {96, {:midi, <<144, 60, 0>>}},
+        {0, {:midi, <<144, 64, 0>>}},
+        {200, {:midi, <<128, 67, 64>>}},
+        {0, :end_of_track}
+      ]
+    ]
+  }
+  ```
+  """
+
+  defstruct format: 1, division: {:ppq, 480}, tracks: []
+end
@@ -0,0 +1,40 @@
+defmodule Midiex.StreamDecoder do
+  @moduledoc """
+  Splits a raw stream of MIDI 1.0 bytes, such as from a serial port, a file or the network, into complete messages.
+
+  The bytes can use running status (where a channel message leaves out its status byte if it's the same as the one before), which is expanded so each message returned starts with its status byte. They needn't be split on message boundaries either, as an incomplete message is kept until the rest of it is decoded.
+
+  Real-time messages are returned as soon as they're decoded, even in the middle of another message. Data bytes with no status byte before them, and undefined status bytes, are dropped.
+
+  The keys are as follows:
+  - *decoder_ref* the reference to the decoder's state in Rust
+
+  ## Example
+  ```
+  decoder = Midiex.StreamDecoder.new()
+
+  Midiex.StreamDecoder.decode(decoder, <<0x90, 60, 100, 64>>)
+  # Returns the first message, holding on to 64
+  # [<<0x90, 60, 100>>]
+
+  Midiex.StreamDecoder.decode(decoder, <<100, 60, 0>>)
+  # [<<0x90, 64, 100>>, <<0x90, 60, 0>>]
+  ```
+  """
+
+  alias Midiex.Backend
+
+  defstruct ~w/decoder_ref/a
+
+  @doc """
+  Returns a new decoder, with no running status.
+  """
+  @spec new() :: %__MODULE__{}
+  def new(), do: Backend.new_stream_decoder()
+
+  @doc """
+  Decodes the next bytes of the stream, returning a list of the messages they complete.
+  """
+  @spec decode(%__MODULE__{}, binary) :: [binary] | {:error, term}
+  def decode(%__MODULE__{} = decoder, bytes) when is_binary(bytes), do: Backend.stream_decode(decoder, bytes)
+end
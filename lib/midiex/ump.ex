@@ -0,0 +1,122 @@
+defmodule Midiex.Ump do
+  @moduledoc """
+  Functions for converting between MIDI 1.0 messages and MIDI 2.0 Universal MIDI Packets (UMP).
+
+  A UMP is one to four 32-bit words, represented here as a list of integers. The top 4 bits of the first word are the message type, which sets the packet's length, and the next 4 bits are the group (0 to 15):
+
+  | Message type | Words | Carries |
+  | ------------ | ----- | ------- |
+  | `0x0` | 1 | Utility messages (NOOP, jitter reduction) |
+  | `0x1` | 1 | System common and real-time messages |
+  | `0x2` | 1 | MIDI 1.0 channel voice messages |
+  | `0x3` | 2 | 7-bit SysEx, up to 6 bytes per packet |
+  | `0x4` | 2 | MIDI 2.0 channel voice messages |
+  | `0x5` | 4 | 8-bit SysEx and mixed data sets |
+  | `0xD` | 4 | Flex data (tempo, time signature, lyrics, etc.) |
+  | `0xF` | 4 | UMP stream messages (endpoint discovery, function blocks) |
+
+  The remaining message types are reserved, with lengths set by the specification, so they can still be split into packets with `packets/1`.
+
+  ## Value scaling
+  MIDI 2.0 channel voice messages carry higher resolution values than MIDI 1.0: 16-bit velocities, and 32-bit controllers, pressures and pitch bends. `from_midi1/2` scales values up with the specification's min-center-max algorithm (see `scale_up/3`), so the minimum, centre and maximum of a MIDI 1.0 value are the minimum, centre and maximum of the MIDI 2.0 value, e.g. a velocity of 64 becomes `0x8000` and 127 becomes `0xFFFF`. `to_midi1/1` scales them back down (see `scale_down/3`), so converting a message to UMP and back gives the same message.
+
+  ## Example
+  ```
+  Midiex.Ump.from_midi1(<<0x91, 60, 100>>)
+  # Returns a MIDI 2.0 Note On in group 0: [0x40913C00, 0xC9240000]
+
+  Midiex.Ump.from_midi1(<<0x91, 60, 100>>, protocol: :midi1, group: 2)
+  # Returns a MIDI 1.0 Note On in group 2: [0x22913C64]
+
+  Midiex.Ump.to_midi1([0x40913C00, 0xC9240000])
+  # Returns: [<<0x91, 60, 100>>]
+  ```
+  """
+
+  alias Midiex.Backend
+
+  @doc """
+  Converts a single MIDI 1.0 message to the words of one or more UMPs.
+
+  - System common and real-time messages become system packets (message type `0x1`)
+  - Channel voice messages become MIDI 2.0 channel voice packets (`0x4`), with their values scaled up, or MIDI 1.0 channel voice packets (`0x2`) with the `protocol: :midi1` option. A Note On with a velocity of 0 becomes a MIDI 2.0 Note Off with a velocity of `0x8000`, as in MIDI 2.0 it would start a note.
+  - A SysEx becomes 7-bit SysEx packets (`0x3`) of up to 6 bytes each, without its `0xF0` and `0xF7`. Any real-time messages within it become system packets ahead of it.
+
+  Bank Select and RPN/NRPN Control Changes are converted one at a time, rather than being combined into MIDI 2.0 Program Change and controller messages.
+
+  Returns `{:error, {:invalid_message, reason}}` if the message isn't a single, complete MIDI 1.0 message (see `Midiex.send_msg/3`).
+
+  Takes the following options:
+  - `group:` the group (0 to 15) of the packets. Defaults to `0`.
+  - `protocol:` `:midi2` or `:midi1`, the kind of packet channel voice messages become. Defaults to `:midi2`.
+  """
+  @spec from_midi1(binary, keyword) :: [non_neg_integer] | {:error, term}
+  def from_midi1(message, opts \\ []) when is_binary(message) do
+    group = Keyword.get(opts, :group, 0)
+
+    unless group in 0..15 do
+      raise ArgumentError, "group must be 0 to 15, got: #{inspect(group)}"
+    end
+
+    midi2 =
+      case Keyword.get(opts, :protocol, :midi2) do
+        :midi2 -> true
+        :midi1 -> false
+      end
+
+    Backend.ump_from_midi1(message, group, midi2)
+  end
+
+  @doc """
+  Converts UMP words to a list of the MIDI 1.0 messages they carry.
+
+  MIDI 2.0 channel voice values are scaled down, and a MIDI 2.0 Note On that would have a velocity of 0 is given a velocity of 1. A Program Change with a valid bank becomes Bank Select Control Changes (0 and 32) followed by a Program Change, and RPN and NRPN messages become their MIDI 1.0 sequence of Control Changes (101/100 or 99/98, then 6 and 38). A SysEx split across 7-bit SysEx packets is joined back into one message.
+
+  Packets with no MIDI 1.0 equivalent are dropped: utility, 8-bit SysEx and mixed data, flex data and UMP stream messages, per-note and relative controllers, per-note pitch bend and per-note management. The group is ignored.
+
+  Returns `{:error, {:invalid_ump, message}}` if the words end part way through a packet.
+  """
+  @spec to_midi1([non_neg_integer]) :: [binary] | {:error, term}
+  def to_midi1(words) when is_list(words), do: Backend.ump_to_midi1(words)
+
+  @doc """
+  Splits UMP words into a list of packets, each a list of one to four words, by the message type of each packet.
+
+  Returns `{:error, {:invalid_ump, message}}` if the words end part way through a packet.
+
+  ## Example
+  ```
+  Midiex.Ump.packets([0x10F80000, 0x40913C00, 0xC9240000])
+  # Returns: [[0x10F80000], [0x40913C00, 0xC9240000]]
+  ```
+  """
+  @spec packets([non_neg_integer]) :: [[non_neg_integer]] | {:error, term}
+  def packets(words) when is_list(words), do: Backend.ump_packets(words)
+
+  @doc """
+  Scales a value up from `from_bits` to `to_bits` (at most 32) with the min-center-max algorithm from the UMP specification.
+
+  ## Example
+  ```
+  Midiex.Ump.scale_up(64, 7, 16)
+  # Returns: 0x8000
+
+  Midiex.Ump.scale_up(127, 7, 32)
+  # Returns: 0xFFFFFFFF
+  ```
+  """
+  @spec scale_up(non_neg_integer, 1..32, 1..32) :: non_neg_integer | {:error, term}
+  def scale_up(value, from_bits, to_bits), do: Backend.ump_scale_up(value, from_bits, to_bits)
+
+  @doc """
+  Scales a value down from `from_bits` (at most 32) to `to_bits`, dropping its lowest bits.
+
+  ## Example
+  ```
+  Midiex.Ump.scale_down(0x8000, 16, 7)
+  # Returns: 64
+  ```
+  """
+  @spec scale_down(non_neg_integer, 1..32, 1..32) :: non_neg_integer | {:error, term}
+  def scale_down(value, from_bits, to_bits), do: Backend.ump_scale_down(value, from_bits, to_bits)
+end
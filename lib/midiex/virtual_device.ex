@@ -0,0 +1,33 @@
+defmodule Midiex.VirtualDevice do
+  @moduledoc """
+  A struct representing a virtual MIDI device: a virtual input and a virtual output which other software sees as one device, such as a software instrument.
+
+  On Linux the device is one ALSA sequencer client, named after the device, with an input and an output port. Elsewhere it's a virtual input and a virtual output with the same name.
+
+  The keys of the struct are as follows:
+  - *name* which is a string containing the name of the device
+  - *input* the device's virtual input, a `%Midiex.VirtualMidiPort{}`. Messages other software sends to it are sent on to the process given when the device was created.
+  - *output* an output connection, a `%Midiex.OutConn{}` with `virtual: true`, sending to the software listening to the device.
+
+  ## Example
+  ```
+  synth = Midiex.create_virtual_device("My Synth")
+
+  # Messages sent to the device arrive as %Midiex.MidiMessage{} structs, with the device's input as the port
+  receive do
+    %Midiex.MidiMessage{port: %Midiex.VirtualMidiPort{name: "My Synth"}, data: data} -> data
+  end
+
+  # Send to whatever's listening to the device
+  Midiex.send_msg(synth, <<0x90, 60, 100>>)
+
+  # Remove the device
+  Midiex.close(synth)
+  ```
+
+  ## More information
+  To create a virtual device see `Midiex.create_virtual_device/2`
+  """
+
+  defstruct ~w/name input output/a
+end
@@ -10,6 +10,7 @@ defmodule Midiex.VirtualMidiPort do
   - *direction* which is an atom currently of value `:input`
   - *name* which is a string containing the name of the port
   - *num* the port number
+  - *port_ref* a reference to the port in Rust, which owns it. The port is closed when this is garbage collected, or when it's closed with `Midiex.close/1`.
 
   ## Example
   #### Virtual input port
@@ -18,13 +19,13 @@ defmodule Midiex.VirtualMidiPort do
   my_virtual_in = Midiex.create_virtual_input("My Virtual Input")
 
   # This will return a VirtualMidiPort struct in the following format
-  # %Midiex.VirtualMidiPort{direction: :input, name: "My Virtual Input", num: 1}
+  # %Midiex.VirtualMidiPort{direction: :input, name: "My Virtual Input", num: 1, port_ref: #Reference<0.1229765843.2394423314.94621>}
   ```
 
   ## More information
-  To create a virtual input port see `Midiex.create_virtual_input/1`
+  To create a virtual input port see `Midiex.create_virtual_input/2`
   """
 
-  defstruct ~w/direction name num/a
+  defstruct ~w/direction name num port_ref/a
 
 end
@@ -32,17 +32,28 @@ defmodule Midiex.MixProject do
           Main: [
             Midiex,
             Midiex.Message,
+            Midiex.Ump,
+            Midiex.CI,
+            Midiex.CI.Responder,
             Midiex.Listener,
-            Midiex.Notifier
+            Midiex.Player,
+            Midiex.Recorder,
+            Midiex.StreamDecoder,
+            Midiex.Notifier,
+            Midiex.Loopback
           ],
           "Structs and Resources": [
             Midiex.MidiIO,
             Midiex.MidiOutput,
             Midiex.OutConn,
+            Midiex.InConn,
             Midiex.MidiPort,
             Midiex.VirtualMidiPort,
+            Midiex.VirtualDevice,
             Midiex.MidiNotification,
             Midiex.MidiMessage,
+            Midiex.Smf,
+            Midiex.CI.Message,
           ],
           Backend: [
             Midiex.Backend
@@ -54,6 +65,7 @@ defmodule Midiex.MixProject do
           "Virtual ports & connections": &(&1[:section] == :virtual),
           "Send & receive messages": &(&1[:section] == :messages),
           "Notifications & hot-plugging": &(&1[:section] == :notifications),
+          "Standard MIDI files": &(&1[:section] == :smf),
           "Channel voice messages": &(&1[:section] == :channel_voice),
           "Channel change messages": &(&1[:section] == :control_change),
           "Channel mode messages": &(&1[:section] == :channel_mode),
@@ -0,0 +1,619 @@
+// ---------------------------------------
+// ALSA SEQUENCER
+// ---------------------------------------
+// Linux only. Used for things midir doesn't expose, such as the
+// sequencer's announce port, the address of a virtual port, a
+// client with more than one virtual port, which clients are ours, or
+// sending a SysEx a chunk at a time.
+// ---------------------------------------
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::raw::c_int;
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+use alsa::nix::errno::Errno;
+use alsa::poll::{poll, pollfd};
+use alsa::seq::{
+    Addr, ClientIter, Event, EventType, MidiEvent, PortCap, PortIter, PortSubscribe,
+    PortSubscribeIter, PortType, QuerySubsType, Seq,
+};
+use alsa::PollDescriptors;
+use alsa::{Card, Direction};
+use midir::SendError;
+
+use crate::midi::{SYSEX_END, SYSEX_START};
+
+// A port on the ALSA sequencer, as last seen by the announce listener
+#[derive(Clone)]
+pub struct SeqPort {
+    pub addr: Addr,
+    pub name: String,
+    pub client_name: String,
+    pub capability: PortCap,
+}
+
+pub enum Announcement {
+    ClientAdded(i32, String),
+    ClientRemoved(i32, String),
+    PortAdded(SeqPort),
+    PortRemoved(SeqPort),
+}
+
+// Listens on the system announce port (client 0, port 1) for clients and ports being added to
+// or removed from the sequencer.
+pub struct AnnounceWatcher {
+    seq: Seq,
+    own_client: i32,
+    // Names are remembered so they can still be reported once a client or port has gone
+    clients: HashMap<i32, String>,
+    ports: HashMap<Addr, SeqPort>,
+}
+
+impl AnnounceWatcher {
+    pub fn open(client_name: &str) -> alsa::Result<Self> {
+        let seq = Seq::open(None, Some(Direction::Capture), false)?;
+        seq.set_client_name(&to_cstring(client_name))?;
+
+        // The port has no MIDI type, so midir won't list it alongside the other output ports
+        let port = seq.create_simple_port(
+            &to_cstring("announcements"),
+            PortCap::WRITE | PortCap::SUBS_WRITE | PortCap::NO_EXPORT,
+            PortType::empty(),
+        )?;
+        let own_client = seq.client_id()?;
+
+        let subscription = PortSubscribe::empty()?;
+        subscription.set_sender(Addr::system_announce());
+        subscription.set_dest(Addr {
+            client: own_client,
+            port,
+        });
+        seq.subscribe_port(&subscription)?;
+
+        let mut clients: HashMap<i32, String> = HashMap::new();
+        let mut ports: HashMap<Addr, SeqPort> = HashMap::new();
+
+        for client_info in ClientIter::new(&seq) {
+            let client = client_info.get_client();
+            let client_name = client_info.get_name().unwrap_or("").to_string();
+
+            for port_info in PortIter::new(&seq, client) {
+                let seq_port = SeqPort {
+                    addr: port_info.addr(),
+                    name: port_info.get_name().unwrap_or("").to_string(),
+                    client_name: client_name.clone(),
+                    capability: port_info.get_capability(),
+                };
+                ports.insert(seq_port.addr, seq_port);
+            }
+
+            clients.insert(client, client_name);
+        }
+
+        Ok(Self {
+            seq,
+            own_client,
+            clients,
+            ports,
+        })
+    }
+
+    // Calls on_announcement for every change to the sequencer's clients and ports. Blocks, only
+    // returning if reading from the sequencer fails.
+    pub fn run<F>(mut self, mut on_announcement: F) -> alsa::Result<()>
+    where
+        F: FnMut(Announcement),
+    {
+        let mut input = self.seq.input();
+
+        loop {
+            let (event_type, addr) = match input.event_input() {
+                Ok(event) => (event.get_type(), event.get_data::<Addr>()),
+                // The input buffer overran and events were lost, but the sequencer can still be read
+                Err(error) if error.errno() == Errno::ENOSPC => continue,
+                Err(error) => return Err(error),
+            };
+
+            let addr = match addr {
+                Some(addr) if addr.client != self.own_client => addr,
+                _ => continue,
+            };
+
+            match event_type {
+                EventType::ClientStart => {
+                    let name = match self.seq.get_any_client_info(addr.client) {
+                        Ok(client_info) => client_info.get_name().unwrap_or("").to_string(),
+                        Err(_) => "".to_string(),
+                    };
+                    self.clients.insert(addr.client, name.clone());
+                    on_announcement(Announcement::ClientAdded(addr.client, name));
+                }
+                EventType::ClientExit => {
+                    let name = self.clients.remove(&addr.client).unwrap_or_default();
+                    on_announcement(Announcement::ClientRemoved(addr.client, name));
+                }
+                EventType::PortStart => {
+                    let seq_port = match self.seq.get_any_port_info(addr) {
+                        Ok(port_info) => SeqPort {
+                            addr,
+                            name: port_info.get_name().unwrap_or("").to_string(),
+                            client_name: self.clients.get(&addr.client).cloned().unwrap_or_default(),
+                            capability: port_info.get_capability(),
+                        },
+                        Err(_) => continue,
+                    };
+                    self.ports.insert(addr, seq_port.clone());
+                    on_announcement(Announcement::PortAdded(seq_port));
+                }
+                EventType::PortExit => {
+                    if let Some(seq_port) = self.ports.remove(&addr) {
+                        on_announcement(Announcement::PortRemoved(seq_port));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+// A sequencer client of our own, shared by its virtual ports. It's locked while sending, and while reading input.
+type SharedSeq = Arc<Mutex<Seq>>;
+
+fn open_client(client_name: &str, direction: Option<Direction>) -> alsa::Result<SharedSeq> {
+    let seq = Seq::open(None, direction, true)?;
+    seq.set_client_name(&to_cstring(client_name))?;
+    Ok(Arc::new(Mutex::new(seq)))
+}
+
+fn lock(seq: &SharedSeq) -> MutexGuard<'_, Seq> {
+    seq.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn create_port(seq: &Seq, port_name: &str, capability: PortCap) -> alsa::Result<Addr> {
+    let port = seq.create_simple_port(
+        &to_cstring(port_name),
+        capability,
+        PortType::MIDI_GENERIC | PortType::APPLICATION,
+    )?;
+
+    Ok(Addr {
+        client: seq.client_id()?,
+        port,
+    })
+}
+
+// A virtual input and a virtual output as two ports of one client, so other applications see them as one device.
+// The input calls callback with every message it receives.
+pub fn create_virtual_device<F>(
+    client_name: &str,
+    port_name: &str,
+    callback: F,
+) -> alsa::Result<(VirtualInput, Output)>
+where
+    F: FnMut(&[u8]) + Send + 'static,
+{
+    let seq = open_client(client_name, None)?;
+    let input = VirtualInput::create_on(seq.clone(), port_name, callback)?;
+
+    match Output::create_on(seq, port_name) {
+        Ok(output) => Ok((input, output)),
+        Err(error) => {
+            input.close();
+            Err(error)
+        }
+    }
+}
+
+// An output port of our own: either a virtual output, which other applications subscribe to, or one connected to
+// another application's or a device's port. Unlike midir's, its address is known, so a virtual output can be told
+// apart from other ports with the same name, and a SysEx can be sent a chunk at a time.
+pub struct Output {
+    seq: SharedSeq,
+    addr: Addr,
+    encoder: MidiEvent,
+    encoder_size: u32,
+    // Whether the last chunk sent started a SysEx without ending it
+    in_sysex: bool,
+}
+
+// The encoder wraps a raw pointer, which is only used by whoever holds the Output
+unsafe impl Send for Output {}
+
+impl Output {
+    // A virtual output
+    pub fn create(client_name: &str, port_name: &str) -> alsa::Result<Self> {
+        Self::create_on(
+            open_client(client_name, Some(Direction::Playback))?,
+            port_name,
+        )
+    }
+
+    fn create_on(seq: SharedSeq, port_name: &str) -> alsa::Result<Self> {
+        let addr = create_port(&lock(&seq), port_name, PortCap::READ | PortCap::SUBS_READ)?;
+
+        Ok(Self {
+            seq,
+            addr,
+            encoder: MidiEvent::new(32)?,
+            encoder_size: 32,
+            in_sysex: false,
+        })
+    }
+
+    // An output connected to dest, as midir connects to a port
+    pub fn connect(client_name: &str, port_name: &str, dest: Addr) -> alsa::Result<Self> {
+        let output = Self::create(client_name, port_name)?;
+
+        let subscription = PortSubscribe::empty()?;
+        subscription.set_sender(output.addr);
+        subscription.set_dest(dest);
+        lock(&output.seq).subscribe_port(&subscription)?;
+
+        Ok(output)
+    }
+
+    pub fn addr(&self) -> Addr {
+        self.addr
+    }
+
+    // Sends the message to every subscriber. A SysEx, or a chunk of one, is sent as it is, as the encoder would hold
+    // back a chunk until the SysEx's end.
+    pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        if self.in_sysex || message.first() == Some(&SYSEX_START) {
+            self.in_sysex = message.last() != Some(&SYSEX_END);
+            let event = Event::new_ext(EventType::Sysex, message);
+            return output(&self.seq, self.addr, event);
+        }
+
+        let size = u32::try_from(message.len())
+            .map_err(|_| SendError::InvalidData("message is too long to send"))?;
+
+        if size > self.encoder_size {
+            self.encoder
+                .resize_buffer(size)
+                .map_err(|_| SendError::Other("could not resize ALSA encoding buffer"))?;
+            self.encoder_size = size;
+        }
+
+        match self.encoder.encode(message) {
+            Ok((_, Some(event))) => output(&self.seq, self.addr, event),
+            _ => Err(SendError::InvalidData("ALSA encoder reported invalid data")),
+        }
+    }
+}
+
+// Sends the event from addr to its subscribers
+fn output(seq: &SharedSeq, addr: Addr, mut event: Event) -> Result<(), SendError> {
+    event.set_source(addr.port);
+    event.set_subs();
+    event.set_direct();
+
+    let seq = lock(seq);
+    seq.event_output_direct(&mut event)
+        .map_err(|_| SendError::Other("could not send encoded ALSA message"))?;
+    let _ = seq.drain_output();
+    Ok(())
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let _ = lock(&self.seq).delete_port(self.addr.port);
+    }
+}
+
+// A virtual input port, which other applications send to. Its messages are read on a thread of its own until it's
+// closed, or dropped.
+pub struct VirtualInput {
+    seq: SharedSeq,
+    addr: Addr,
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl VirtualInput {
+    fn create_on<F>(seq: SharedSeq, port_name: &str, callback: F) -> alsa::Result<Self>
+    where
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        let (addr, fds) = {
+            let seq = lock(&seq);
+            let addr = create_port(&seq, port_name, PortCap::WRITE | PortCap::SUBS_WRITE)?;
+            (addr, (&*seq, Some(Direction::Capture)).get()?)
+        };
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let seq = seq.clone();
+            let stop = stop.clone();
+            std::thread::spawn(move || read_input(seq, fds, stop, callback))
+        };
+
+        Ok(Self {
+            seq,
+            addr,
+            stop,
+            handle: Some(handle),
+        })
+    }
+
+    // Stops reading and deletes the port, returning once the thread has finished with the callback
+    pub fn close(self) {
+        drop(self);
+    }
+}
+
+// The thread waits on the port until it's woken, so it's stopped however the VirtualInput goes
+impl Drop for VirtualInput {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        // The thread is woken by an event we send to the port ourselves
+        {
+            let seq = lock(&self.seq);
+            let mut event = Event::new(EventType::Usr0, &[0u8; 12]);
+            event.set_source(self.addr.port);
+            event.set_dest(self.addr);
+            event.set_direct();
+            let _ = seq.event_output_direct(&mut event);
+            let _ = seq.drain_output();
+        }
+
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        let _ = lock(&self.seq).delete_port(self.addr.port);
+    }
+}
+
+fn read_input<F>(seq: SharedSeq, fds: Vec<pollfd>, stop: Arc<AtomicBool>, mut callback: F)
+where
+    F: FnMut(&[u8]),
+{
+    let decoder = match MidiEvent::new(0) {
+        Ok(decoder) => decoder,
+        Err(_) => return,
+    };
+    decoder.enable_running_status(false);
+
+    while !stop.load(Ordering::SeqCst) {
+        let mut ready = fds.clone();
+        match poll(&mut ready, -1) {
+            Ok(_) => (),
+            Err(error) if error.errno() == Errno::EINTR => continue,
+            Err(_) => return,
+        }
+
+        // The lock is let go before the callback is called, so it can send from the client's virtual output
+        let messages = read_pending(&lock(&seq), &decoder);
+
+        for message in messages {
+            if stop.load(Ordering::SeqCst) {
+                return;
+            }
+            callback(&message);
+        }
+    }
+}
+
+// Every message waiting to be read. A SysEx split into chunks is passed on a chunk at a time.
+fn read_pending(seq: &Seq, decoder: &MidiEvent) -> Vec<Vec<u8>> {
+    let mut input = seq.input();
+    let mut messages = Vec::new();
+    let mut buffer = [0u8; 12];
+
+    while input.event_input_pending(true).unwrap_or(0) > 0 {
+        let mut event = match input.event_input() {
+            Ok(event) => event,
+            Err(_) => break,
+        };
+
+        match event.get_type() {
+            EventType::Sysex => messages.extend(event.get_ext().map(|data| data.to_vec())),
+            EventType::PortSubscribed | EventType::PortUnsubscribed | EventType::Usr0 => (),
+            _ => match decoder.decode(&mut buffer, &mut event) {
+                Ok(len) if len > 0 => messages.push(buffer[..len].to_vec()),
+                _ => (),
+            },
+        }
+    }
+
+    messages
+}
+
+// What the sequencer knows about a port and the client it belongs to
+pub struct PortDetails {
+    pub client_name: String,
+    pub port_name: String,
+    // Kernel clients are the system's own and sound card drivers', user clients are applications'
+    pub kernel_client: bool,
+    pub card: Option<i32>,
+    pub manufacturer: Option<String>,
+    pub capability: PortCap,
+    pub port_type: PortType,
+    pub midi_channels: i32,
+    // The process a user client belongs to, if the kernel tells us (Linux 4.15 and later)
+    pub pid: Option<i32>,
+}
+
+// Looks up each port, giving None for ports which have gone
+pub fn port_details(addrs: &[Addr]) -> alsa::Result<Vec<Option<PortDetails>>> {
+    let seq = Seq::open(None, Some(Direction::Playback), false)?;
+    let clients = ClientQuery::open().ok();
+
+    let details = addrs
+        .iter()
+        .map(|addr| {
+            let port_info = seq.get_any_port_info(*addr).ok()?;
+            let client_info = seq.get_any_client_info(addr.client).ok()?;
+
+            // Client numbers below 128 are kept for kernel clients, 4 for each card from 16 up
+            let (kernel_client, card, pid) = clients
+                .as_ref()
+                .and_then(|clients| clients.client(addr.client))
+                .unwrap_or((addr.client < 128, -1, -1));
+            let card = (card >= 0).then_some(card);
+
+            Some(PortDetails {
+                client_name: client_info.get_name().unwrap_or("").to_string(),
+                port_name: port_info.get_name().unwrap_or("").to_string(),
+                kernel_client,
+                card,
+                manufacturer: card.and_then(manufacturer),
+                capability: port_info.get_capability(),
+                port_type: port_info.get_type(),
+                midi_channels: port_info.get_midi_channels(),
+                pid: (pid > 0).then_some(pid),
+            })
+        })
+        .collect();
+
+    Ok(details)
+}
+
+// A client this process has open on the sequencer, with its ports
+pub struct OwnedClient {
+    pub client: i32,
+    pub name: String,
+    pub ports: Vec<OwnedPort>,
+}
+
+pub struct OwnedPort {
+    pub addr: Addr,
+    pub name: String,
+    // The ports it's subscribed to, or which are subscribed to it
+    pub connections: Vec<Addr>,
+}
+
+// The clients this process has open, apart from those used to look them up
+pub fn owned_clients() -> alsa::Result<Vec<OwnedClient>> {
+    let seq = Seq::open(None, Some(Direction::Playback), false)?;
+    let own_client = seq.client_id()?;
+    let clients: Vec<(i32, String)> = ClientIter::new(&seq)
+        .filter(|info| info.get_client() != own_client)
+        .map(|info| (info.get_client(), info.get_name().unwrap_or("").to_string()))
+        .collect();
+
+    // Opened once the clients are listed, so its own client isn't one of them
+    let query = ClientQuery::open()?;
+    let pid = std::process::id() as i32;
+
+    let owned = clients
+        .into_iter()
+        .filter(|(client, _)| {
+            matches!(query.client(*client), Some((_, _, client_pid)) if client_pid == pid)
+        })
+        .map(|(client, name)| OwnedClient {
+            client,
+            name,
+            ports: PortIter::new(&seq, client)
+                .map(|port_info| {
+                    let addr = port_info.addr();
+                    OwnedPort {
+                        addr,
+                        name: port_info.get_name().unwrap_or("").to_string(),
+                        connections: port_connections(&seq, addr),
+                    }
+                })
+                .collect(),
+        })
+        .collect();
+
+    Ok(owned)
+}
+
+fn port_connections(seq: &Seq, addr: Addr) -> Vec<Addr> {
+    let mut connections: Vec<Addr> = PortSubscribeIter::new(seq, addr, QuerySubsType::READ)
+        .map(|subscription| subscription.get_dest())
+        .chain(
+            PortSubscribeIter::new(seq, addr, QuerySubsType::WRITE)
+                .map(|subscription| subscription.get_sender()),
+        )
+        .collect();
+
+    connections.sort_unstable();
+    connections.dedup();
+    connections
+}
+
+// snd-usb-audio gives a card the long name "<manufacturer> <product> at <usb path>", its name being the product.
+// Other drivers don't name the manufacturer.
+fn manufacturer(card: i32) -> Option<String> {
+    let card = Card::new(card);
+    let name = card.get_name().ok()?;
+    let longname = card.get_longname().ok()?;
+
+    let (device, _path) = longname.split_once(" at ")?;
+    let manufacturer = device.strip_suffix(name.as_str())?.trim_end();
+    (!manufacturer.is_empty()).then(|| manufacturer.to_string())
+}
+
+// alsa doesn't wrap a client's type, card or process id, so they're read with alsa-sys, on a handle of our own
+struct ClientQuery {
+    seq: *mut alsa_sys::snd_seq_t,
+    info: *mut alsa_sys::snd_seq_client_info_t,
+}
+
+impl ClientQuery {
+    fn open() -> alsa::Result<Self> {
+        let name = to_cstring("default");
+        let mut seq = ptr::null_mut();
+        let mut info = ptr::null_mut();
+
+        unsafe {
+            let result = alsa_sys::snd_seq_open(
+                &mut seq,
+                name.as_ptr(),
+                alsa_sys::SND_SEQ_OPEN_OUTPUT as c_int,
+                0,
+            );
+            if result < 0 {
+                return Err(alsa::Error::new("snd_seq_open", -result));
+            }
+            let result = alsa_sys::snd_seq_client_info_malloc(&mut info);
+            if result < 0 {
+                alsa_sys::snd_seq_close(seq);
+                return Err(alsa::Error::new("snd_seq_client_info_malloc", -result));
+            }
+        }
+
+        Ok(Self { seq, info })
+    }
+
+    // Whether it's a kernel client, its card and its process id, the last two being -1 if it doesn't have one
+    fn client(&self, client: i32) -> Option<(bool, i32, i32)> {
+        unsafe {
+            if alsa_sys::snd_seq_get_any_client_info(self.seq, client, self.info) < 0 {
+                return None;
+            }
+
+            Some((
+                alsa_sys::snd_seq_client_info_get_type(self.info)
+                    == alsa_sys::SND_SEQ_KERNEL_CLIENT,
+                alsa_sys::snd_seq_client_info_get_card(self.info),
+                alsa_sys::snd_seq_client_info_get_pid(self.info),
+            ))
+        }
+    }
+}
+
+impl Drop for ClientQuery {
+    fn drop(&mut self) {
+        unsafe {
+            alsa_sys::snd_seq_client_info_free(self.info);
+            alsa_sys::snd_seq_close(self.seq);
+        }
+    }
+}
+
+// Packs an ALSA client:port address into a single number, as client and port are each a byte
+pub fn addr_to_native_id(addr: Addr) -> u32 {
+    ((addr.client as u32) << 8) | (addr.port as u32 & 0xFF)
+}
+
+fn to_cstring(name: &str) -> CString {
+    CString::new(name.replace('\0', "")).unwrap_or_default()
+}
@@ -0,0 +1,74 @@
+// ---------------------------------------
+// BATCHED INPUT
+// ---------------------------------------
+// A batched subscription's input callback hands each message to a
+// batcher thread, which collects them and sends them on together
+// once there are enough of them, or the first has waited long
+// enough. The thread exits, sending anything still collected, once
+// the callback (and so the Sender) is dropped.
+// ---------------------------------------
+
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::time::{Duration, Instant};
+
+// The most messages a batch can hold, as each batch's Vec is
+// allocated up front
+pub const MAX_MESSAGES: usize = 65_536;
+
+// A message's timestamp and data
+pub type Timestamped = (u64, Vec<u8>);
+
+#[derive(Debug, Clone, Copy)]
+pub struct BatchOptions {
+    pub max_messages: usize,
+    pub max_delay: Duration,
+}
+
+pub fn spawn<F>(options: BatchOptions, mut send_batch: F) -> Sender<Timestamped>
+where
+    F: FnMut(Vec<Timestamped>) + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel::<Timestamped>();
+    let max_messages = options.max_messages;
+
+    std::thread::spawn(move || {
+        let mut batch: Vec<Timestamped> = Vec::with_capacity(max_messages);
+        // When the batch has to be sent by, set by its first message
+        let mut send_by: Option<Instant> = None;
+
+        loop {
+            let received = match send_by {
+                None => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                Some(at) => rx.recv_timeout(at.saturating_duration_since(Instant::now())),
+            };
+
+            match received {
+                Ok(message) => {
+                    if batch.is_empty() {
+                        send_by = Some(Instant::now() + options.max_delay);
+                    }
+                    batch.push(message);
+
+                    if batch.len() < max_messages {
+                        continue;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+                Err(RecvTimeoutError::Disconnected) => {
+                    if !batch.is_empty() {
+                        send_batch(batch);
+                    }
+                    return;
+                }
+            }
+
+            send_batch(std::mem::replace(
+                &mut batch,
+                Vec::with_capacity(max_messages),
+            ));
+            send_by = None;
+        }
+    });
+
+    tx
+}
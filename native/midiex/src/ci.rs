@@ -0,0 +1,995 @@
+// ---------------------------------------
+// MIDI CAPABILITY INQUIRY
+// ---------------------------------------
+// MIDI-CI messages are universal non-realtime SysExs (0x7E) with the
+// sub-ID 0x0D, sent from one MUID (a random 28-bit id each MIDI-CI
+// device picks for itself) to another, or to every device with the
+// broadcast MUID. A Message is parsed from, or built into, one of
+// these SysExs.
+//
+// The Responder answers Discovery, Endpoint, Protocol Negotiation,
+// Profile Configuration and Property Exchange inquiries addressed to
+// its MUID, remembering the MUIDs of the devices it has discovered.
+// ---------------------------------------
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::midi::{SYSEX_END, SYSEX_START};
+
+const UNIVERSAL_NON_REALTIME: u8 = 0x7E;
+const SUB_ID_CI: u8 = 0x0D;
+
+// The MIDI-CI version (1.2) of the messages built
+pub const CI_VERSION: u8 = 0x02;
+
+pub const BROADCAST_MUID: u32 = 0x0FFF_FFFF;
+// MUIDs from here up are reserved, so are never picked by new_muid
+const RESERVED_MUIDS: u32 = 0x0FFF_FF00;
+
+// The device id addressing the whole port, rather than a channel or group
+pub const FUNCTION_BLOCK: u8 = 0x7F;
+
+// The authority level sent in Protocol Negotiation by a device acting for itself
+const AUTHORITY_LEVEL: u8 = 0x20;
+// The only protocol a MIDI 1.0 port can be switched to: MIDI 1.0, without extensions
+const MIDI1_PROTOCOL: ProtocolType = [0x01, 0x00, 0x00, 0x00, 0x00];
+
+// Capability Inquiry Category Supported bits
+const CATEGORY_PROTOCOL_NEGOTIATION: u8 = 0x02;
+const CATEGORY_PROFILE_CONFIGURATION: u8 = 0x04;
+const CATEGORY_PROPERTY_EXCHANGE: u8 = 0x08;
+
+// A Property Exchange reply's header, length fields and framing, besides the header data itself
+const PROPERTY_CHUNK_OVERHEAD: usize = 24;
+// Used to size Property Exchange chunks for a device that hasn't said how large a SysEx it takes
+const DEFAULT_MAX_SYSEX_SIZE: u32 = 512;
+// Set Property Data messages still arriving are dropped once there are this many, oldest first, or once the next
+// chunk hasn't arrived within the timeout, or once their data grows larger than the maximum size
+const MAX_PENDING_SETS: usize = 16;
+const PENDING_SET_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_PROPERTY_SIZE: usize = 1024 * 1024;
+
+pub type ProfileId = [u8; 5];
+pub type ProtocolType = [u8; 5];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiError {
+    // The SysEx isn't a universal non-realtime MIDI-CI message
+    NotCi,
+    // The message ends before all of its fields
+    TooShort,
+    // A byte inside the SysEx is 0x80 or above
+    InvalidDataByte,
+    // A field's value doesn't fit in its 7, 14 or 28 bits
+    OutOfRange,
+}
+
+impl fmt::Display for CiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CiError::NotCi => "the message isn't a MIDI-CI SysEx (0xF0 0x7E _ 0x0D ...)".fmt(f),
+            CiError::TooShort => "the message ends before all of its fields".fmt(f),
+            CiError::InvalidDataByte => "a byte inside the SysEx is 0x80 or above".fmt(f),
+            CiError::OutOfRange => "a field's value is too large for the message".fmt(f),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    pub manufacturer: [u8; 3],
+    pub family: u16,
+    pub model: u16,
+    pub version: [u8; 4],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Discovery {
+    pub identity: Identity,
+    pub categories: u8,
+    pub max_sysex_size: u32,
+    pub output_path: u8,
+}
+
+// ACK and NAK messages share their fields
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    // The sub-ID #2 of the message being acknowledged
+    pub original: u8,
+    pub status_code: u8,
+    pub status_data: u8,
+    pub details: [u8; 5],
+    pub text: Vec<u8>,
+}
+
+// One chunk of a Property Exchange message. Only the first chunk of a message carries the header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyChunk {
+    pub request_id: u8,
+    pub header: Vec<u8>,
+    pub chunks: u16,
+    pub chunk: u16,
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Body {
+    Discovery(Discovery),
+    DiscoveryReply(Discovery, u8),
+    EndpointInquiry(u8),
+    EndpointReply(u8, Vec<u8>),
+    InvalidateMuid(u32),
+    Ack(Status),
+    Nak(Status),
+    ProtocolNegotiation(u8, Vec<ProtocolType>),
+    ProtocolNegotiationReply(u8, Vec<ProtocolType>),
+    SetProtocol(u8, ProtocolType),
+    TestProtocol(u8, Vec<u8>),
+    TestProtocolReply(u8, Vec<u8>),
+    ProtocolConfirmed(u8),
+    ProfileInquiry,
+    // Enabled and disabled profiles
+    ProfileInquiryReply(Vec<ProfileId>, Vec<ProfileId>),
+    // A profile and the number of channels it spans
+    SetProfileOn(ProfileId, u16),
+    SetProfileOff(ProfileId),
+    ProfileEnabled(ProfileId, u16),
+    ProfileDisabled(ProfileId, u16),
+    ProfileAdded(ProfileId),
+    ProfileRemoved(ProfileId),
+    ProfileSpecificData(ProfileId, Vec<u8>),
+    // The number of simultaneous requests supported, and the Property Exchange major and minor versions
+    PropertyCapabilities(u8, u8, u8),
+    PropertyCapabilitiesReply(u8, u8, u8),
+    GetProperty(PropertyChunk),
+    GetPropertyReply(PropertyChunk),
+    SetProperty(PropertyChunk),
+    SetPropertyReply(PropertyChunk),
+    Subscription(PropertyChunk),
+    SubscriptionReply(PropertyChunk),
+    PropertyNotify(PropertyChunk),
+    // Any other message, as its sub-ID #2 and the bytes after its destination MUID
+    Other(u8, Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub version: u8,
+    pub device_id: u8,
+    pub source: u32,
+    pub destination: u32,
+    pub body: Body,
+}
+
+// Picks a random MUID, outside the reserved range
+pub fn new_muid() -> u32 {
+    loop {
+        let mut hasher = RandomState::new().build_hasher();
+        if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(elapsed.as_nanos());
+        }
+        let muid = hasher.finish() as u32 & BROADCAST_MUID;
+        if muid < RESERVED_MUIDS {
+            return muid;
+        }
+    }
+}
+
+// Returns true if the SysEx is a MIDI-CI message, without checking the rest of it
+pub fn is_ci(sysex: &[u8]) -> bool {
+    sysex.len() > 4
+        && sysex[0] == SYSEX_START
+        && sysex[1] == UNIVERSAL_NON_REALTIME
+        && sysex[3] == SUB_ID_CI
+}
+
+// ---------------------------------------
+// PARSING
+// ---------------------------------------
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8, CiError> {
+        Ok(self.take(1)?[0])
+    }
+
+    // Fields added in later versions of MIDI-CI are 0 when they're left out
+    fn optional_byte(&mut self) -> u8 {
+        self.byte().unwrap_or(0)
+    }
+
+    fn take(&mut self, len: usize) -> Result<&'a [u8], CiError> {
+        if self.bytes.len() < len {
+            return Err(CiError::TooShort);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], CiError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    // 14 and 28-bit values are sent 7 bits at a time, least significant first
+    fn u14(&mut self) -> Result<u16, CiError> {
+        let bytes = self.take(2)?;
+        Ok(bytes[0] as u16 | ((bytes[1] as u16) << 7))
+    }
+
+    fn optional_u14(&mut self) -> u16 {
+        self.u14().unwrap_or(0)
+    }
+
+    fn u28(&mut self) -> Result<u32, CiError> {
+        let bytes = self.take(4)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| (value << 7) | *byte as u32))
+    }
+
+    // Bytes preceded by their length as a 14-bit value
+    fn sized(&mut self) -> Result<Vec<u8>, CiError> {
+        let len = self.u14()? as usize;
+        Ok(self.take(len)?.to_vec())
+    }
+
+    fn rest(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes).to_vec()
+    }
+}
+
+pub fn parse(sysex: &[u8]) -> Result<Message, CiError> {
+    if !is_ci(sysex) || sysex.last() != Some(&SYSEX_END) {
+        return Err(CiError::NotCi);
+    }
+
+    let inner = &sysex[1..sysex.len() - 1];
+    if inner.iter().any(|byte| *byte >= 0x80) {
+        return Err(CiError::InvalidDataByte);
+    }
+
+    let mut reader = Reader { bytes: inner };
+    reader.take(1)?;
+    let device_id = reader.byte()?;
+    reader.take(1)?;
+    let sub_id = reader.byte()?;
+    let version = reader.byte()?;
+    let source = reader.u28()?;
+    let destination = reader.u28()?;
+    let body = parse_body(sub_id, &mut reader)?;
+
+    Ok(Message {
+        version,
+        device_id,
+        source,
+        destination,
+        body,
+    })
+}
+
+fn parse_body(sub_id: u8, reader: &mut Reader) -> Result<Body, CiError> {
+    let body = match sub_id {
+        0x70 => Body::Discovery(parse_discovery(reader)?),
+        0x71 => {
+            let discovery = parse_discovery(reader)?;
+            Body::DiscoveryReply(discovery, reader.optional_byte())
+        }
+        0x72 => Body::EndpointInquiry(reader.byte()?),
+        0x73 => Body::EndpointReply(reader.byte()?, reader.sized()?),
+        0x7D => Body::Ack(parse_status(reader)?),
+        0x7E => Body::InvalidateMuid(reader.u28()?),
+        0x7F => Body::Nak(parse_status(reader)?),
+        0x10 => {
+            let authority = reader.byte()?;
+            Body::ProtocolNegotiation(authority, parse_protocols(reader)?)
+        }
+        0x11 => {
+            let authority = reader.byte()?;
+            Body::ProtocolNegotiationReply(authority, parse_protocols(reader)?)
+        }
+        0x12 => Body::SetProtocol(reader.byte()?, reader.array()?),
+        0x13 => Body::TestProtocol(reader.byte()?, reader.rest()),
+        0x14 => Body::TestProtocolReply(reader.byte()?, reader.rest()),
+        0x15 => Body::ProtocolConfirmed(reader.byte()?),
+        0x20 => Body::ProfileInquiry,
+        0x21 => {
+            let enabled = parse_profiles(reader)?;
+            Body::ProfileInquiryReply(enabled, parse_profiles(reader)?)
+        }
+        0x22 => Body::SetProfileOn(reader.array()?, reader.optional_u14()),
+        0x23 => Body::SetProfileOff(reader.array()?),
+        0x24 => Body::ProfileEnabled(reader.array()?, reader.optional_u14()),
+        0x25 => Body::ProfileDisabled(reader.array()?, reader.optional_u14()),
+        0x26 => Body::ProfileAdded(reader.array()?),
+        0x27 => Body::ProfileRemoved(reader.array()?),
+        0x2F => {
+            let profile = reader.array()?;
+            let len = reader.u28()? as usize;
+            Body::ProfileSpecificData(profile, reader.take(len)?.to_vec())
+        }
+        0x30 => Body::PropertyCapabilities(
+            reader.byte()?,
+            reader.optional_byte(),
+            reader.optional_byte(),
+        ),
+        0x31 => Body::PropertyCapabilitiesReply(
+            reader.byte()?,
+            reader.optional_byte(),
+            reader.optional_byte(),
+        ),
+        0x34 => Body::GetProperty(parse_chunk(reader)?),
+        0x35 => Body::GetPropertyReply(parse_chunk(reader)?),
+        0x36 => Body::SetProperty(parse_chunk(reader)?),
+        0x37 => Body::SetPropertyReply(parse_chunk(reader)?),
+        0x38 => Body::Subscription(parse_chunk(reader)?),
+        0x39 => Body::SubscriptionReply(parse_chunk(reader)?),
+        0x3F => Body::PropertyNotify(parse_chunk(reader)?),
+        _ => Body::Other(sub_id, reader.rest()),
+    };
+
+    Ok(body)
+}
+
+fn parse_discovery(reader: &mut Reader) -> Result<Discovery, CiError> {
+    Ok(Discovery {
+        identity: Identity {
+            manufacturer: reader.array()?,
+            family: reader.u14()?,
+            model: reader.u14()?,
+            version: reader.array()?,
+        },
+        categories: reader.byte()?,
+        max_sysex_size: reader.u28()?,
+        output_path: reader.optional_byte(),
+    })
+}
+
+// MIDI-CI 1.1 NAKs have no fields
+fn parse_status(reader: &mut Reader) -> Result<Status, CiError> {
+    let original = reader.optional_byte();
+    let status_code = reader.optional_byte();
+    let status_data = reader.optional_byte();
+    let details = reader.array().unwrap_or_default();
+    let text = reader.sized().unwrap_or_default();
+
+    Ok(Status {
+        original,
+        status_code,
+        status_data,
+        details,
+        text,
+    })
+}
+
+fn parse_protocols(reader: &mut Reader) -> Result<Vec<ProtocolType>, CiError> {
+    let count = reader.byte()?;
+    (0..count).map(|_| reader.array()).collect()
+}
+
+fn parse_profiles(reader: &mut Reader) -> Result<Vec<ProfileId>, CiError> {
+    let count = reader.u14()?;
+    (0..count).map(|_| reader.array()).collect()
+}
+
+fn parse_chunk(reader: &mut Reader) -> Result<PropertyChunk, CiError> {
+    Ok(PropertyChunk {
+        request_id: reader.byte()?,
+        header: reader.sized()?,
+        chunks: reader.u14()?,
+        chunk: reader.u14()?,
+        data: reader.sized()?,
+    })
+}
+
+// ---------------------------------------
+// BUILDING
+// ---------------------------------------
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn byte(&mut self, byte: u8) {
+        self.bytes.push(byte);
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.bytes.extend_from_slice(bytes);
+    }
+
+    fn u14(&mut self, value: u16) -> Result<(), CiError> {
+        if value > 0x3FFF {
+            return Err(CiError::OutOfRange);
+        }
+        self.bytes
+            .extend_from_slice(&[(value & 0x7F) as u8, (value >> 7) as u8]);
+        Ok(())
+    }
+
+    fn u28(&mut self, value: u32) -> Result<(), CiError> {
+        if value > 0x0FFF_FFFF {
+            return Err(CiError::OutOfRange);
+        }
+        for shift in [0, 7, 14, 21] {
+            self.bytes.push(((value >> shift) & 0x7F) as u8);
+        }
+        Ok(())
+    }
+
+    fn sized(&mut self, bytes: &[u8]) -> Result<(), CiError> {
+        self.u14(u16::try_from(bytes.len()).map_err(|_| CiError::OutOfRange)?)?;
+        self.bytes(bytes);
+        Ok(())
+    }
+}
+
+pub fn build(message: &Message) -> Result<Vec<u8>, CiError> {
+    let mut writer = Writer {
+        bytes: vec![
+            SYSEX_START,
+            UNIVERSAL_NON_REALTIME,
+            message.device_id,
+            SUB_ID_CI,
+            sub_id(&message.body),
+            message.version,
+        ],
+    };
+    writer.u28(message.source)?;
+    writer.u28(message.destination)?;
+    build_body(&message.body, &mut writer)?;
+
+    if writer.bytes[1..].iter().any(|byte| *byte >= 0x80) {
+        return Err(CiError::InvalidDataByte);
+    }
+    writer.byte(SYSEX_END);
+
+    Ok(writer.bytes)
+}
+
+fn sub_id(body: &Body) -> u8 {
+    match body {
+        Body::Discovery(_) => 0x70,
+        Body::DiscoveryReply(_, _) => 0x71,
+        Body::EndpointInquiry(_) => 0x72,
+        Body::EndpointReply(_, _) => 0x73,
+        Body::Ack(_) => 0x7D,
+        Body::InvalidateMuid(_) => 0x7E,
+        Body::Nak(_) => 0x7F,
+        Body::ProtocolNegotiation(_, _) => 0x10,
+        Body::ProtocolNegotiationReply(_, _) => 0x11,
+        Body::SetProtocol(_, _) => 0x12,
+        Body::TestProtocol(_, _) => 0x13,
+        Body::TestProtocolReply(_, _) => 0x14,
+        Body::ProtocolConfirmed(_) => 0x15,
+        Body::ProfileInquiry => 0x20,
+        Body::ProfileInquiryReply(_, _) => 0x21,
+        Body::SetProfileOn(_, _) => 0x22,
+        Body::SetProfileOff(_) => 0x23,
+        Body::ProfileEnabled(_, _) => 0x24,
+        Body::ProfileDisabled(_, _) => 0x25,
+        Body::ProfileAdded(_) => 0x26,
+        Body::ProfileRemoved(_) => 0x27,
+        Body::ProfileSpecificData(_, _) => 0x2F,
+        Body::PropertyCapabilities(_, _, _) => 0x30,
+        Body::PropertyCapabilitiesReply(_, _, _) => 0x31,
+        Body::GetProperty(_) => 0x34,
+        Body::GetPropertyReply(_) => 0x35,
+        Body::SetProperty(_) => 0x36,
+        Body::SetPropertyReply(_) => 0x37,
+        Body::Subscription(_) => 0x38,
+        Body::SubscriptionReply(_) => 0x39,
+        Body::PropertyNotify(_) => 0x3F,
+        Body::Other(sub_id, _) => *sub_id,
+    }
+}
+
+fn build_body(body: &Body, writer: &mut Writer) -> Result<(), CiError> {
+    match body {
+        Body::Discovery(discovery) => build_discovery(discovery, writer)?,
+        Body::DiscoveryReply(discovery, function_block) => {
+            build_discovery(discovery, writer)?;
+            writer.byte(*function_block);
+        }
+        Body::EndpointInquiry(status) => writer.byte(*status),
+        Body::EndpointReply(status, data) => {
+            writer.byte(*status);
+            writer.sized(data)?;
+        }
+        Body::Ack(status) | Body::Nak(status) => {
+            writer.bytes(&[status.original, status.status_code, status.status_data]);
+            writer.bytes(&status.details);
+            writer.sized(&status.text)?;
+        }
+        Body::InvalidateMuid(target) => writer.u28(*target)?,
+        Body::ProtocolNegotiation(authority, protocols)
+        | Body::ProtocolNegotiationReply(authority, protocols) => {
+            writer.byte(*authority);
+            writer.byte(u8::try_from(protocols.len()).map_err(|_| CiError::OutOfRange)?);
+            for protocol in protocols {
+                writer.bytes(protocol);
+            }
+        }
+        Body::SetProtocol(authority, protocol) => {
+            writer.byte(*authority);
+            writer.bytes(protocol);
+        }
+        Body::TestProtocol(authority, data) | Body::TestProtocolReply(authority, data) => {
+            writer.byte(*authority);
+            writer.bytes(data);
+        }
+        Body::ProtocolConfirmed(authority) => writer.byte(*authority),
+        Body::ProfileInquiry => (),
+        Body::ProfileInquiryReply(enabled, disabled) => {
+            for profiles in [enabled, disabled] {
+                writer.u14(u16::try_from(profiles.len()).map_err(|_| CiError::OutOfRange)?)?;
+                for profile in profiles {
+                    writer.bytes(profile);
+                }
+            }
+        }
+        Body::SetProfileOn(profile, channels)
+        | Body::ProfileEnabled(profile, channels)
+        | Body::ProfileDisabled(profile, channels) => {
+            writer.bytes(profile);
+            writer.u14(*channels)?;
+        }
+        Body::SetProfileOff(profile) => {
+            writer.bytes(profile);
+            writer.u14(0)?;
+        }
+        Body::ProfileAdded(profile) | Body::ProfileRemoved(profile) => writer.bytes(profile),
+        Body::ProfileSpecificData(profile, data) => {
+            writer.bytes(profile);
+            writer.u28(u32::try_from(data.len()).map_err(|_| CiError::OutOfRange)?)?;
+            writer.bytes(data);
+        }
+        Body::PropertyCapabilities(max_requests, major, minor)
+        | Body::PropertyCapabilitiesReply(max_requests, major, minor) => {
+            writer.bytes(&[*max_requests, *major, *minor]);
+        }
+        Body::GetProperty(chunk)
+        | Body::GetPropertyReply(chunk)
+        | Body::SetProperty(chunk)
+        | Body::SetPropertyReply(chunk)
+        | Body::Subscription(chunk)
+        | Body::SubscriptionReply(chunk)
+        | Body::PropertyNotify(chunk) => {
+            writer.byte(chunk.request_id);
+            writer.sized(&chunk.header)?;
+            writer.u14(chunk.chunks)?;
+            writer.u14(chunk.chunk)?;
+            writer.sized(&chunk.data)?;
+        }
+        Body::Other(_, data) => writer.bytes(data),
+    }
+
+    Ok(())
+}
+
+fn build_discovery(discovery: &Discovery, writer: &mut Writer) -> Result<(), CiError> {
+    writer.bytes(&discovery.identity.manufacturer);
+    writer.u14(discovery.identity.family)?;
+    writer.u14(discovery.identity.model)?;
+    writer.bytes(&discovery.identity.version);
+    writer.byte(discovery.categories);
+    writer.u28(discovery.max_sysex_size)?;
+    writer.byte(discovery.output_path);
+    Ok(())
+}
+
+// ---------------------------------------
+// RESPONDER
+// ---------------------------------------
+
+pub struct ResponderOptions {
+    pub muid: u32,
+    pub identity: Identity,
+    pub max_sysex_size: u32,
+    pub product_instance_id: Vec<u8>,
+    // Every profile the device has, and whether it's enabled
+    pub profiles: Vec<(ProfileId, bool)>,
+    // Property Exchange resources by name, each holding its (usually JSON) data
+    pub properties: Vec<(String, Vec<u8>)>,
+    // If false, inquiries are passed on without being answered
+    pub auto_reply: bool,
+}
+
+// A device the responder has had a Discovery or Reply to Discovery from
+#[derive(Debug, Clone)]
+pub struct Peer {
+    pub muid: u32,
+    pub identity: Identity,
+    pub max_sysex_size: u32,
+}
+
+pub enum Event {
+    // A message addressed to the responder, or broadcast
+    Received(Message),
+    // A profile was turned on (true) or off (false) by another device
+    ProfileChanged(ProfileId, bool),
+    // A property was set by another device
+    PropertySet(String, Vec<u8>),
+}
+
+// A Set Property Data message still arriving
+struct PendingSet {
+    header: Vec<u8>,
+    data: Vec<u8>,
+    last_chunk_at: Instant,
+}
+
+pub struct Responder {
+    options: ResponderOptions,
+    peers: HashMap<u32, Peer>,
+    // By the sender's MUID and request id
+    pending_sets: HashMap<(u32, u8), PendingSet>,
+}
+
+impl Responder {
+    pub fn new(options: ResponderOptions) -> Self {
+        Self {
+            options,
+            peers: HashMap::new(),
+            pending_sets: HashMap::new(),
+        }
+    }
+
+    pub fn peers(&self) -> Vec<Peer> {
+        let mut peers: Vec<Peer> = self.peers.values().cloned().collect();
+        peers.sort_unstable_by_key(|peer| peer.muid);
+        peers
+    }
+
+    // Starts a message from the responder
+    pub fn message(&self, device_id: u8, destination: u32, body: Body) -> Message {
+        Message {
+            version: CI_VERSION,
+            device_id,
+            source: self.options.muid,
+            destination,
+            body,
+        }
+    }
+
+    pub fn discovery(&self) -> Message {
+        self.message(
+            FUNCTION_BLOCK,
+            BROADCAST_MUID,
+            Body::Discovery(self.own_discovery()),
+        )
+    }
+
+    // Sent when the responder stops, so other devices forget its MUID
+    pub fn invalidate(&self) -> Message {
+        self.message(
+            FUNCTION_BLOCK,
+            BROADCAST_MUID,
+            Body::InvalidateMuid(self.options.muid),
+        )
+    }
+
+    pub fn set_property(&mut self, resource: String, data: Vec<u8>) {
+        match self
+            .options
+            .properties
+            .iter_mut()
+            .find(|(name, _)| *name == resource)
+        {
+            Some((_, value)) => *value = data,
+            None => self.options.properties.push((resource, data)),
+        }
+    }
+
+    // Turns a profile on or off, returning the report to broadcast, or None if the device doesn't have the profile
+    pub fn set_profile(&mut self, profile: ProfileId, enabled: bool) -> Option<Message> {
+        let (_, state) = self
+            .options
+            .profiles
+            .iter_mut()
+            .find(|(id, _)| *id == profile)?;
+        *state = enabled;
+
+        let body = match enabled {
+            true => Body::ProfileEnabled(profile, 0),
+            false => Body::ProfileDisabled(profile, 0),
+        };
+        Some(self.message(FUNCTION_BLOCK, BROADCAST_MUID, body))
+    }
+
+    // Handles a message received from another device, returning the replies to send and the events to pass on.
+    // Messages addressed to other devices, or sent by this one, are ignored.
+    pub fn handle(&mut self, message: Message) -> (Vec<Message>, Vec<Event>) {
+        if message.source == self.options.muid
+            || (message.destination != self.options.muid && message.destination != BROADCAST_MUID)
+        {
+            return (Vec::new(), Vec::new());
+        }
+
+        match &message.body {
+            Body::Discovery(discovery) | Body::DiscoveryReply(discovery, _) => {
+                self.peers.insert(
+                    message.source,
+                    Peer {
+                        muid: message.source,
+                        identity: discovery.identity.clone(),
+                        max_sysex_size: discovery.max_sysex_size,
+                    },
+                );
+            }
+            Body::InvalidateMuid(target) => {
+                self.peers.remove(target);
+                self.pending_sets.retain(|(muid, _), _| muid != target);
+            }
+            _ => (),
+        }
+
+        let (replies, mut events) = match self.options.auto_reply {
+            true => self.reply(&message),
+            false => (Vec::new(), Vec::new()),
+        };
+        events.insert(0, Event::Received(message));
+
+        (replies, events)
+    }
+
+    fn reply(&mut self, message: &Message) -> (Vec<Message>, Vec<Event>) {
+        match &message.body {
+            Body::SetProfileOn(profile, _) => return self.switch_profile(message, *profile, true),
+            Body::SetProfileOff(profile) => return self.switch_profile(message, *profile, false),
+            Body::SetProperty(chunk) => return self.set_property_chunk(message, chunk),
+            _ => (),
+        }
+
+        let source = message.source;
+        let device_id = message.device_id;
+        let reply = |body: Body| vec![self.message(device_id, source, body)];
+
+        let replies = match &message.body {
+            Body::Discovery(_) => reply(Body::DiscoveryReply(self.own_discovery(), FUNCTION_BLOCK)),
+            // Status 0 asks for the product instance id, the only status defined so far
+            Body::EndpointInquiry(0) => reply(Body::EndpointReply(
+                0,
+                self.options.product_instance_id.clone(),
+            )),
+            Body::ProtocolNegotiation(_, _) => reply(Body::ProtocolNegotiationReply(
+                AUTHORITY_LEVEL,
+                vec![MIDI1_PROTOCOL],
+            )),
+            Body::TestProtocol(_, data) => {
+                reply(Body::TestProtocolReply(AUTHORITY_LEVEL, data.clone()))
+            }
+            Body::ProfileInquiry => {
+                let profiles = |enabled: bool| -> Vec<ProfileId> {
+                    self.options
+                        .profiles
+                        .iter()
+                        .filter(|(_, state)| *state == enabled)
+                        .map(|(id, _)| *id)
+                        .collect()
+                };
+                reply(Body::ProfileInquiryReply(profiles(true), profiles(false)))
+            }
+            Body::PropertyCapabilities(_, _, _) => reply(Body::PropertyCapabilitiesReply(1, 0, 0)),
+            Body::GetProperty(chunk) => self.get_property(message, chunk),
+            Body::Subscription(chunk) => {
+                reply(Body::SubscriptionReply(status_chunk(chunk.request_id, 405)))
+            }
+            _ => Vec::new(),
+        };
+
+        (replies, Vec::new())
+    }
+
+    fn own_discovery(&self) -> Discovery {
+        let mut categories = CATEGORY_PROTOCOL_NEGOTIATION | CATEGORY_PROFILE_CONFIGURATION;
+        if !self.options.properties.is_empty() {
+            categories |= CATEGORY_PROPERTY_EXCHANGE;
+        }
+
+        Discovery {
+            identity: self.options.identity.clone(),
+            categories,
+            max_sysex_size: self.options.max_sysex_size,
+            output_path: 0,
+        }
+    }
+
+    fn switch_profile(
+        &mut self,
+        message: &Message,
+        profile: ProfileId,
+        enabled: bool,
+    ) -> (Vec<Message>, Vec<Event>) {
+        match self.set_profile(profile, enabled) {
+            Some(report) => (vec![report], vec![Event::ProfileChanged(profile, enabled)]),
+            None => (vec![self.nak(message)], Vec::new()),
+        }
+    }
+
+    fn nak(&self, message: &Message) -> Message {
+        self.message(
+            message.device_id,
+            message.source,
+            Body::Nak(Status {
+                original: sub_id(&message.body),
+                status_code: 0,
+                status_data: 0,
+                details: [0; 5],
+                text: Vec::new(),
+            }),
+        )
+    }
+
+    fn get_property(&self, message: &Message, chunk: &PropertyChunk) -> Vec<Message> {
+        let reply = |chunk: PropertyChunk| {
+            self.message(
+                message.device_id,
+                message.source,
+                Body::GetPropertyReply(chunk),
+            )
+        };
+
+        let data = match resource(&chunk.header) {
+            Some(name) => self.property(&name),
+            None => None,
+        };
+        let data = match data {
+            Some(data) => data,
+            None => return vec![reply(status_chunk(chunk.request_id, 404))],
+        };
+
+        let header = status_header(200);
+        let max_sysex_size = match self.peers.get(&message.source) {
+            Some(peer) if peer.max_sysex_size > 0 => peer.max_sysex_size,
+            _ => DEFAULT_MAX_SYSEX_SIZE,
+        };
+        let chunk_size = (max_sysex_size as usize)
+            .saturating_sub(PROPERTY_CHUNK_OVERHEAD + header.len())
+            .clamp(1, 0x3FFF);
+
+        let chunks: Vec<&[u8]> = match data.is_empty() {
+            true => vec![data.as_slice()],
+            false => data.chunks(chunk_size).collect(),
+        };
+        let count = chunks.len() as u16;
+
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, data)| {
+                reply(PropertyChunk {
+                    request_id: chunk.request_id,
+                    header: if i == 0 { header.clone() } else { Vec::new() },
+                    chunks: count,
+                    chunk: i as u16 + 1,
+                    data: data.to_vec(),
+                })
+            })
+            .collect()
+    }
+
+    // Resources without data of their own are listed in a ResourceList, unless the device has given one
+    fn property(&self, name: &str) -> Option<Vec<u8>> {
+        let found = self
+            .options
+            .properties
+            .iter()
+            .find(|(resource, _)| resource == name)
+            .map(|(_, data)| data.clone());
+
+        match found {
+            None if name == "ResourceList" => {
+                let resources: Vec<String> = self
+                    .options
+                    .properties
+                    .iter()
+                    .map(|(resource, _)| format!("{{\"resource\":\"{}\"}}", resource))
+                    .collect();
+                Some(format!("[{}]", resources.join(",")).into_bytes())
+            }
+            found => found,
+        }
+    }
+
+    fn set_property_chunk(
+        &mut self,
+        message: &Message,
+        chunk: &PropertyChunk,
+    ) -> (Vec<Message>, Vec<Event>) {
+        let key = (message.source, chunk.request_id);
+        let now = Instant::now();
+        self.pending_sets
+            .retain(|_, pending| now.duration_since(pending.last_chunk_at) < PENDING_SET_TIMEOUT);
+
+        if !self.pending_sets.contains_key(&key) && self.pending_sets.len() >= MAX_PENDING_SETS {
+            let oldest = self
+                .pending_sets
+                .iter()
+                .min_by_key(|(_, pending)| pending.last_chunk_at)
+                .map(|(key, _)| *key);
+            if let Some(oldest) = oldest {
+                self.pending_sets.remove(&oldest);
+            }
+        }
+
+        let pending = self.pending_sets.entry(key).or_insert_with(|| PendingSet {
+            header: chunk.header.clone(),
+            data: Vec::new(),
+            last_chunk_at: now,
+        });
+        pending.data.extend_from_slice(&chunk.data);
+        pending.last_chunk_at = now;
+
+        if pending.data.len() > MAX_PROPERTY_SIZE {
+            self.pending_sets.remove(&key);
+            return (
+                vec![self.set_property_reply(message, chunk.request_id, 413)],
+                Vec::new(),
+            );
+        }
+        if chunk.chunk < chunk.chunks {
+            return (Vec::new(), Vec::new());
+        }
+
+        let PendingSet { header, data, .. } = match self.pending_sets.remove(&key) {
+            Some(pending) => pending,
+            None => return (Vec::new(), Vec::new()),
+        };
+        let name = resource(&header);
+        let status = if name.is_some() { 200 } else { 400 };
+        let reply = self.set_property_reply(message, chunk.request_id, status);
+
+        match name {
+            Some(name) => {
+                self.set_property(name.clone(), data.clone());
+                (vec![reply], vec![Event::PropertySet(name, data)])
+            }
+            None => (vec![reply], Vec::new()),
+        }
+    }
+
+    fn set_property_reply(&self, message: &Message, request_id: u8, status: u16) -> Message {
+        self.message(
+            message.device_id,
+            message.source,
+            Body::SetPropertyReply(status_chunk(request_id, status)),
+        )
+    }
+}
+
+fn status_header(status: u16) -> Vec<u8> {
+    format!("{{\"status\":{}}}", status).into_bytes()
+}
+
+// A reply with only a status in its header, and no data
+fn status_chunk(request_id: u8, status: u16) -> PropertyChunk {
+    PropertyChunk {
+        request_id,
+        header: status_header(status),
+        chunks: 1,
+        chunk: 1,
+        data: Vec::new(),
+    }
+}
+
+// The "resource" of a Property Exchange header, such as {"resource":"DeviceInfo"}. Headers are small JSON objects,
+// so the value is looked for directly rather than parsing the whole header.
+fn resource(header: &[u8]) -> Option<String> {
+    let header = std::str::from_utf8(header).ok()?;
+    let after_key = &header[header.find("\"resource\"")? + "\"resource\"".len()..];
+    let value = after_key.trim_start().strip_prefix(':')?.trim_start();
+    let value = value.strip_prefix('"')?;
+    Some(value[..value.find('"')?].to_string())
+}
@@ -0,0 +1,213 @@
+// ------------------------
+// ERRORS
+// ------------------------
+// Errors are returned to Elixir as {:error, {kind, message}}, where kind is one of the atoms
+// below and message is a human readable string. The exceptions are send errors, which are
+// returned as {:error, :invalid_data}, {:error, {:backend, message}} or
+// {:error, {:invalid_message, reason}}.
+// ------------------------
+
+use std::fmt;
+use std::sync::PoisonError;
+
+use midir::{ConnectError, ConnectErrorKind, InitError, PortInfoError, SendError};
+use rustler::{Encoder, Env, Term};
+
+use crate::atoms;
+use crate::ci::CiError;
+use crate::midi::FramingError;
+use crate::smf::SmfError;
+use crate::ump::UmpError;
+
+pub enum MidiexError {
+    // midir couldn't initialise a MidiInput or MidiOutput, usually as there is no MIDI driver (e.g. no ALSA sequencer)
+    NoDriverFound,
+    InvalidPort(String),
+    PortName,
+    Other(String),
+    PoisonedLock,
+    ConnectionClosed,
+    Unsupported(String),
+    #[cfg(target_os = "linux")]
+    Alsa(alsa::Error),
+    // midir rejected the bytes given to send
+    InvalidData(String),
+    // The backend failed to send the message
+    Backend(String),
+    // The message failed strict validation before being sent
+    InvalidMessage(FramingError),
+    InvalidSmf(SmfError),
+    Io(std::io::Error),
+    InvalidArgument(String),
+    // The player has finished, or been stopped, so can't be sent commands
+    PlayerStopped,
+    // No acknowledgement was received while sending a SysEx dump with a handshake
+    HandshakeTimeout,
+    InvalidUmp(UmpError),
+    InvalidCi(CiError),
+}
+
+impl MidiexError {
+    fn kind(&self) -> rustler::Atom {
+        match self {
+            MidiexError::NoDriverFound => atoms::no_driver_found(),
+            MidiexError::InvalidPort(_) => atoms::invalid_port(),
+            MidiexError::PortName => atoms::port_name(),
+            MidiexError::Other(_) => atoms::other(),
+            MidiexError::PoisonedLock => atoms::poisoned_lock(),
+            MidiexError::ConnectionClosed => atoms::connection_closed(),
+            MidiexError::Unsupported(_) => atoms::unsupported(),
+            #[cfg(target_os = "linux")]
+            MidiexError::Alsa(_) => atoms::alsa(),
+            MidiexError::InvalidData(_) => atoms::invalid_data(),
+            MidiexError::Backend(_) => atoms::backend(),
+            MidiexError::InvalidMessage(_) => atoms::invalid_message(),
+            MidiexError::InvalidSmf(_) => atoms::invalid_smf(),
+            MidiexError::Io(_) => atoms::io(),
+            MidiexError::InvalidArgument(_) => atoms::invalid_argument(),
+            MidiexError::PlayerStopped => atoms::player_stopped(),
+            MidiexError::HandshakeTimeout => atoms::handshake_timeout(),
+            MidiexError::InvalidUmp(_) => atoms::invalid_ump(),
+            MidiexError::InvalidCi(_) => atoms::invalid_ci(),
+        }
+    }
+}
+
+fn framing_reason(error: &FramingError) -> rustler::Atom {
+    match error {
+        FramingError::Empty => atoms::empty(),
+        FramingError::MissingStatus => atoms::missing_status(),
+        FramingError::UndefinedStatus => atoms::undefined_status(),
+        FramingError::UnexpectedEndOfExclusive => atoms::unexpected_end_of_exclusive(),
+        FramingError::WrongLength => atoms::wrong_length(),
+        FramingError::InvalidDataByte => atoms::invalid_data_byte(),
+        FramingError::UnterminatedSysex => atoms::unterminated_sysex(),
+    }
+}
+
+impl fmt::Display for MidiexError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiexError::NoDriverFound => InitError.fmt(f),
+            MidiexError::InvalidPort(msg) => msg.fmt(f),
+            MidiexError::PortName => PortInfoError::CannotRetrievePortName.fmt(f),
+            MidiexError::Other(msg) => msg.fmt(f),
+            MidiexError::PoisonedLock => {
+                "a lock was poisoned by a thread panicking while holding it".fmt(f)
+            }
+            MidiexError::ConnectionClosed => {
+                "no connection available, the connection may have been closed".fmt(f)
+            }
+            MidiexError::Unsupported(msg) => msg.fmt(f),
+            #[cfg(target_os = "linux")]
+            MidiexError::Alsa(error) => error.fmt(f),
+            MidiexError::InvalidData(msg) => msg.fmt(f),
+            MidiexError::Backend(msg) => msg.fmt(f),
+            MidiexError::InvalidMessage(error) => error.fmt(f),
+            MidiexError::InvalidSmf(error) => error.fmt(f),
+            MidiexError::Io(error) => error.fmt(f),
+            MidiexError::InvalidArgument(msg) => msg.fmt(f),
+            MidiexError::PlayerStopped => "the player has finished or been stopped".fmt(f),
+            MidiexError::HandshakeTimeout => {
+                "no acknowledgement was received within the handshake timeout".fmt(f)
+            }
+            MidiexError::InvalidUmp(error) => error.fmt(f),
+            MidiexError::InvalidCi(error) => error.fmt(f),
+        }
+    }
+}
+
+impl Encoder for MidiexError {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            MidiexError::InvalidData(_) => self.kind().encode(env),
+            MidiexError::InvalidMessage(error) => (self.kind(), framing_reason(error)).encode(env),
+            _ => (self.kind(), self.to_string()).encode(env),
+        }
+    }
+}
+
+// Returned from a NIF as {:error, {kind, message}} rather than raised
+impl From<MidiexError> for rustler::Error {
+    fn from(error: MidiexError) -> Self {
+        rustler::Error::Term(Box::new(error))
+    }
+}
+
+impl From<InitError> for MidiexError {
+    fn from(_error: InitError) -> Self {
+        MidiexError::NoDriverFound
+    }
+}
+
+impl<T> From<ConnectError<T>> for MidiexError {
+    fn from(error: ConnectError<T>) -> Self {
+        match error.kind() {
+            ConnectErrorKind::InvalidPort => MidiexError::InvalidPort(error.to_string()),
+            ConnectErrorKind::Other(msg) => MidiexError::Other(msg.to_string()),
+        }
+    }
+}
+
+impl From<PortInfoError> for MidiexError {
+    fn from(error: PortInfoError) -> Self {
+        match error {
+            PortInfoError::CannotRetrievePortName => MidiexError::PortName,
+            PortInfoError::InvalidPort | PortInfoError::PortNumberOutOfRange => {
+                MidiexError::InvalidPort(error.to_string())
+            }
+        }
+    }
+}
+
+impl From<SendError> for MidiexError {
+    fn from(error: SendError) -> Self {
+        match error {
+            SendError::InvalidData(msg) => MidiexError::InvalidData(msg.to_string()),
+            SendError::Other(msg) => MidiexError::Backend(msg.to_string()),
+        }
+    }
+}
+
+impl From<FramingError> for MidiexError {
+    fn from(error: FramingError) -> Self {
+        MidiexError::InvalidMessage(error)
+    }
+}
+
+impl From<SmfError> for MidiexError {
+    fn from(error: SmfError) -> Self {
+        MidiexError::InvalidSmf(error)
+    }
+}
+
+impl From<UmpError> for MidiexError {
+    fn from(error: UmpError) -> Self {
+        MidiexError::InvalidUmp(error)
+    }
+}
+
+impl From<CiError> for MidiexError {
+    fn from(error: CiError) -> Self {
+        MidiexError::InvalidCi(error)
+    }
+}
+
+impl From<std::io::Error> for MidiexError {
+    fn from(error: std::io::Error) -> Self {
+        MidiexError::Io(error)
+    }
+}
+
+impl<T> From<PoisonError<T>> for MidiexError {
+    fn from(_error: PoisonError<T>) -> Self {
+        MidiexError::PoisonedLock
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl From<alsa::Error> for MidiexError {
+    fn from(error: alsa::Error) -> Self {
+        MidiexError::Alsa(error)
+    }
+}
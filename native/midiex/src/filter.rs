@@ -0,0 +1,168 @@
+// ---------------------------------------
+// INPUT FILTERING
+// ---------------------------------------
+// A subscription's filter is checked in the midir callback, so
+// messages it rejects are dropped before they're sent to Elixir.
+// ---------------------------------------
+
+use std::ops::RangeInclusive;
+
+use midir::Ignore;
+
+// The kinds of MIDI 1.0 message a filter can allow or deny, named as in midi::Message. Channel mode messages are
+// control changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    NoteOff,
+    NoteOn,
+    PolyAftertouch,
+    ControlChange,
+    ProgramChange,
+    ChannelAftertouch,
+    PitchBend,
+    Sysex,
+    MtcQuarterFrame,
+    SongPosition,
+    SongSelect,
+    TuneRequest,
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+}
+
+impl Kind {
+    pub fn of(status: u8) -> Option<Kind> {
+        let kind = match status {
+            0x80..=0x8F => Kind::NoteOff,
+            0x90..=0x9F => Kind::NoteOn,
+            0xA0..=0xAF => Kind::PolyAftertouch,
+            0xB0..=0xBF => Kind::ControlChange,
+            0xC0..=0xCF => Kind::ProgramChange,
+            0xD0..=0xDF => Kind::ChannelAftertouch,
+            0xE0..=0xEF => Kind::PitchBend,
+            0xF0 => Kind::Sysex,
+            0xF1 => Kind::MtcQuarterFrame,
+            0xF2 => Kind::SongPosition,
+            0xF3 => Kind::SongSelect,
+            0xF6 => Kind::TuneRequest,
+            0xF8 => Kind::Clock,
+            0xFA => Kind::Start,
+            0xFB => Kind::Continue,
+            0xFC => Kind::Stop,
+            0xFE => Kind::ActiveSensing,
+            0xFF => Kind::Reset,
+            _ => return None,
+        };
+        Some(kind)
+    }
+
+    fn bit(self) -> u32 {
+        1 << self as u32
+    }
+}
+
+// A set of message kinds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Kinds(u32);
+
+impl Kinds {
+    pub fn contains(&self, kind: Kind) -> bool {
+        self.0 & kind.bit() != 0
+    }
+}
+
+impl FromIterator<Kind> for Kinds {
+    fn from_iter<I: IntoIterator<Item = Kind>>(kinds: I) -> Self {
+        Kinds(kinds.into_iter().fold(0, |bits, kind| bits | kind.bit()))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Filter {
+    // Messages for midir to drop before they reach the callback
+    pub ignore: Ignore,
+    // Bit n set means channel n is allowed. System messages aren't on a channel, so aren't affected.
+    pub channels: u16,
+    // If given, only these kinds are allowed
+    pub allow: Option<Kinds>,
+    pub deny: Kinds,
+    // Notes allowed in Note On, Note Off and Polyphonic Aftertouch messages
+    pub notes: RangeInclusive<u8>,
+    // Controllers allowed in Control Change messages
+    pub controls: RangeInclusive<u8>,
+    // Note Ons with a lower velocity are dropped, except for those with a velocity of 0, as these end a note
+    pub min_velocity: u8,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Self {
+            ignore: Ignore::None,
+            channels: 0xFFFF,
+            allow: None,
+            deny: Kinds::default(),
+            notes: 0..=127,
+            controls: 0..=127,
+            min_velocity: 0,
+        }
+    }
+}
+
+// Whether midir would drop the message before it reached an input callback, for inputs which aren't midir's own
+// (loopback devices and the subscribers to a virtual input)
+pub fn ignores(ignore: Ignore, message: &[u8]) -> bool {
+    let flags = ignore as u8;
+
+    match message.first() {
+        Some(0xF0) => flags & (Ignore::Sysex as u8) != 0,
+        Some(0xF1) | Some(0xF8) => flags & (Ignore::Time as u8) != 0,
+        Some(0xFE) => flags & (Ignore::ActiveSense as u8) != 0,
+        _ => false,
+    }
+}
+
+impl Filter {
+    pub fn accepts(&self, message: &[u8]) -> bool {
+        let status = match message.first() {
+            Some(status) => *status,
+            None => return false,
+        };
+
+        // Anything that isn't a known message (such as the rest of a SysEx split across callbacks) is left alone
+        let kind = match Kind::of(status) {
+            Some(kind) => kind,
+            None => return true,
+        };
+
+        if self.deny.contains(kind) || self.allow.is_some_and(|allow| !allow.contains(kind)) {
+            return false;
+        }
+
+        if status < 0xF0 && self.channels & (1 << (status & 0x0F)) == 0 {
+            return false;
+        }
+
+        let data = |i: usize| message.get(i).copied();
+
+        match kind {
+            Kind::NoteOff | Kind::PolyAftertouch => in_range(&self.notes, data(1)),
+            Kind::NoteOn => {
+                let velocity = data(2).unwrap_or(0);
+                in_range(&self.notes, data(1)) && (velocity == 0 || velocity >= self.min_velocity)
+            }
+            Kind::ControlChange => in_range(&self.controls, data(1)),
+            _ => true,
+        }
+    }
+}
+
+// A message missing the data byte has nothing to compare, so is let through
+fn in_range(range: &RangeInclusive<u8>, byte: Option<u8>) -> bool {
+    match byte {
+        Some(byte) => range.contains(&byte),
+        None => true,
+    }
+}
@@ -5,6 +5,28 @@ extern crate midir;
 #[macro_use]
 extern crate lazy_static;
 
+#[cfg(target_os = "linux")]
+mod alsa_seq;
+mod batch;
+mod ci;
+mod error;
+mod filter;
+mod loopback;
+mod midi;
+mod player;
+mod recorder;
+mod running_status;
+mod scheduler;
+mod smf;
+mod sysex;
+mod ump;
+mod virtual_input;
+
+use error::MidiexError;
+use player::{PlayerCommand, PlayerOptions, PlayerUpdate, Position};
+use scheduler::{Connection, OutPort, Scheduler, SharedOutConn};
+use sysex::{Assembled, Assembler, SysexOptions};
+
 #[cfg(all(target_os = "macos"))]
 use core_foundation::runloop::CFRunLoop;
 #[cfg(all(target_os = "macos"))]
@@ -12,41 +34,83 @@ use coremidi::Notification::{ObjectAdded, ObjectRemoved};
 #[cfg(all(target_os = "macos"))]
 use coremidi::{AddedRemovedInfo, Client, Notification, ObjectType};
 
-use std::ops::{Add, DerefMut};
+use std::ops::DerefMut;
 use std::result::Result;
-use std::sync::Mutex;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
 #[cfg(not(any(target_os = "windows")))]
-use midir::os::unix::{VirtualInput, VirtualOutput};
-use midir::{
-    Ignore, InitError, MidiInput, MidiInputPort, MidiOutput, MidiOutputConnection, MidiOutputPort,
+use midir::os::unix::VirtualInput;
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+use midir::os::unix::VirtualOutput;
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiInputPort, MidiOutput, MidiOutputPort};
+
+use rustler::types::binary::NewBinary;
+use rustler::types::tuple::{get_tuple, make_tuple};
+use rustler::{
+    Atom, Binary, Decoder, Encoder, Env, Error, LocalPid, NifMap, NifResult, NifStruct,
+    OwnedBinary, OwnedEnv, ResourceArc, Term,
 };
 
-use rustler::{Atom, Binary, Encoder, Env, Error, NifMap, NifStruct, OwnedEnv, ResourceArc, Term};
-
 // --------------
 // GLOBALS
 // --------------
 
-// IS THIS NEEDED ANYMORE?
-// This version uses threadlocal to create the MidiInput and MidiOutput objects
-thread_local!(static GLOBAL_MIDI_INPUT_RESULT: Result<MidiInput, InitError> = MidiInput::new("MIDIex"));
-thread_local!(static GLOBAL_MIDI_OUTPUT_RESULT: Result<MidiOutput, InitError> = MidiOutput::new("MIDIex"));
+// GLOBALS FOR CLIENT AND PORT NAMES
+// The names set with set_names, used by calls which don't give their own
+lazy_static! {
+    static ref GLOBAL_NAMES: Mutex<Names> = Mutex::new(Names::default());
+}
 
 // GLOBALS FOR INPUT PORTS BEING SUBSCRIBED TO
+// Each subscription owns the input connection listening to the port, so removing it from the list closes the connection.
 lazy_static! {
-    static ref GLOBAL_LISTEN_LIST: Mutex<Vec<MidiPort>> = Mutex::new(Vec::<MidiPort>::new());
+    static ref GLOBAL_LISTEN_LIST: Mutex<Vec<(MidiPort, InConnRef)>> =
+        Mutex::new(Vec::<(MidiPort, InConnRef)>::new());
 }
 
 // GLOBALS FOR VIRTUAL INPUTS
 lazy_static! {
-    static ref GLOBAL_VIRTUAL_LISTEN_LIST: Mutex<Vec<VirtualMidiPort>> =
-        Mutex::new(Vec::<VirtualMidiPort>::new());
+    static ref GLOBAL_VIRTUAL_LISTEN_LIST: Mutex<Vec<(VirtualMidiPort, InConnRef)>> =
+        Mutex::new(Vec::<(VirtualMidiPort, InConnRef)>::new());
 }
 lazy_static! {
     static ref GLOBAL_VIRTUAL_INPUT_COUNTER: Mutex<usize> = Mutex::new(0);
 }
 
+// GLOBALS FOR VIRTUAL OUTPUTS
+// Virtual outputs are created one at a time, where their port is found by looking for the one which is new
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+lazy_static! {
+    static ref GLOBAL_VIRTUAL_OUTPUT_LOCK: Mutex<()> = Mutex::new(());
+}
+
+// GLOBALS FOR BATCHED SUBSCRIPTIONS
+// Each batched subscription is given an id, which its batches are sent with in place of the port
+lazy_static! {
+    static ref GLOBAL_BATCH_COUNTER: Mutex<u64> = Mutex::new(0);
+}
+
+// GLOBALS FOR SYSEX DUMPS
+// Each dump being sent is given an id, so the progress messages it sends can be told apart from other dumps'
+lazy_static! {
+    static ref GLOBAL_SYSEX_DUMP_COUNTER: Mutex<u64> = Mutex::new(0);
+}
+
+// GLOBALS FOR MIDI-CI RESPONDERS
+// Each responder is given an id, so the messages it sends can be told apart from other responders'
+lazy_static! {
+    static ref GLOBAL_CI_RESPONDER_COUNTER: Mutex<u64> = Mutex::new(0);
+}
+
+// GLOBALS FOR SMF PLAYERS
+// Each player is given an id, so the messages it sends can be told apart from other players'
+lazy_static! {
+    static ref GLOBAL_PLAYER_COUNTER: Mutex<u64> = Mutex::new(0);
+}
+
 // --------------
 // ATOMS
 // --------------
@@ -66,100 +130,705 @@ pub mod atoms {
         message,
 
         added,
-        removed
+        removed,
+
+        // Error kinds, see error.rs
+        no_driver_found,
+        invalid_port,
+        port_name,
+        poisoned_lock,
+        connection_closed,
+        unsupported,
+        alsa,
+        invalid_data,
+        backend,
+        invalid_message,
+        invalid_smf,
+        io,
+
+        // Reasons a message fails strict validation, see midi.rs
+        empty,
+        missing_status,
+        undefined_status,
+        unexpected_end_of_exclusive,
+        wrong_length,
+        invalid_data_byte,
+        unterminated_sysex,
+
+        // Standard MIDI file events, see smf.rs
+        ppq,
+        smpte,
+        midi,
+        sysex,
+        escape,
+        tempo,
+        time_signature,
+        key_signature,
+        major,
+        minor,
+        track_name,
+        end_of_track,
+        meta,
+
+        // Standard MIDI file player, see player.rs
+        midiex_player,
+        tick,
+        ms,
+        position,
+        finished,
+        stopped,
+        invalid_argument,
+        player_stopped,
+
+        // Decoded MIDI messages, see midi.rs
+        note_off,
+        note_on,
+        poly_aftertouch,
+        control_change,
+        program_change,
+        channel_aftertouch,
+        pitch_bend,
+        all_sound_off,
+        reset_all_controllers,
+        local_control,
+        all_notes_off,
+        omni_off,
+        omni_on,
+        mono_on,
+        poly_on,
+        mtc_quarter_frame,
+        song_position,
+        song_select,
+        tune_request,
+        clock,
+        start,
+        continue_ = "continue",
+        stop,
+        active_sensing,
+        reset,
+
+        // Filter ignore flags, see filter.rs
+        time,
+        active_sense,
+
+        // Batched subscriptions, see batch.rs
+        midi_batch,
+
+        // Dropped SysExs, see sysex.rs
+        sysex_overflow,
+        sysex_timeout,
+
+        // Sending SysEx dumps, see sysex.rs
+        midiex_sysex,
+        progress,
+        done,
+        handshake_timeout,
+
+        // Universal MIDI Packets, see ump.rs
+        invalid_ump,
+
+        // MIDI Capability Inquiry, see ci.rs
+        invalid_ci,
+        midiex_ci,
+        struct_ = "__struct__",
+        ci_message = "Elixir.Midiex.CI.Message",
+        type_ = "type",
+        version,
+        device_id,
+        source,
+        destination,
+        body,
+        discovery,
+        discovery_reply,
+        endpoint_inquiry,
+        endpoint_reply,
+        invalidate_muid,
+        ack,
+        nak,
+        protocol_negotiation,
+        protocol_negotiation_reply,
+        set_protocol,
+        test_protocol,
+        test_protocol_reply,
+        protocol_confirmed,
+        profile_inquiry,
+        profile_inquiry_reply,
+        set_profile_on,
+        set_profile_off,
+        profile_enabled,
+        profile_disabled,
+        profile_added,
+        profile_removed,
+        profile_specific_data,
+        property_capabilities,
+        property_capabilities_reply,
+        get_property,
+        get_property_reply,
+        set_property,
+        set_property_reply,
+        subscription,
+        subscription_reply,
+        property_notify,
+        manufacturer,
+        family,
+        model,
+        categories,
+        max_sysex_size,
+        output_path,
+        function_block,
+        status,
+        data,
+        target,
+        original,
+        status_code,
+        status_data,
+        details,
+        text,
+        authority,
+        protocols,
+        protocol,
+        enabled,
+        disabled,
+        profile,
+        channels,
+        max_requests,
+        request_id,
+        header,
+        chunks,
+        chunk,
+        sub_id,
+        muid,
+        property,
+
+        // Port details, see port_info
+        kernel,
+        user,
+        read,
+        write,
+        subscribe_read,
+        subscribe_write,
+        duplex,
+        midi_generic,
+        hardware,
+        software,
+        synth,
+        application,
+        virtual_ = "virtual",
+
+        // Output connections, see OutConn
+        out_conn = "Elixir.Midiex.OutConn",
+        conn_ref,
+        midi_port,
+        name,
+        port_num,
+    }
+}
+
+// ------------------------
+// CLIENT AND PORT NAMES
+// ------------------------
+
+// The name of the client a connection or virtual port is made with, which other applications see, and the name of
+// the port a connection is made from. Either can be left out, for the name set with set_names or else the default.
+#[derive(NifMap, Clone, Default)]
+pub struct Names {
+    client_name: Option<String>,
+    port_name: Option<String>,
+}
+
+impl Names {
+    fn client_name(&self, default: &str) -> Result<String, MidiexError> {
+        Ok(match &self.client_name {
+            Some(client_name) => client_name.clone(),
+            None => GLOBAL_NAMES
+                .lock()?
+                .client_name
+                .clone()
+                .unwrap_or_else(|| default.to_string()),
+        })
+    }
+
+    fn port_name(&self, default: &str) -> Result<String, MidiexError> {
+        Ok(match &self.port_name {
+            Some(port_name) => port_name.clone(),
+            None => GLOBAL_NAMES
+                .lock()?
+                .port_name
+                .clone()
+                .unwrap_or_else(|| default.to_string()),
+        })
     }
 }
 
+// Connections and virtual ports made after this use these names, unless they're given their own
+#[rustler::nif]
+fn set_names(names: Names) -> Result<Atom, Error> {
+    *GLOBAL_NAMES.lock().map_err(MidiexError::from)? = names;
+    Ok(atoms::ok())
+}
+
+#[rustler::nif]
+fn get_names() -> Result<Names, Error> {
+    Ok(GLOBAL_NAMES.lock().map_err(MidiexError::from)?.clone())
+}
+
 // ----------
 // SUBSCRIBE
 // ----------
 
-#[rustler::nif]
+#[rustler::nif(schedule = "DirtyIo")]
 fn unsubscribe_all_ports() -> Result<Vec<MidiPort>, Error> {
-    GLOBAL_LISTEN_LIST.lock().unwrap().clear();
-    Ok(GLOBAL_LISTEN_LIST.lock().unwrap().to_vec())
+    let subscriptions: Vec<(MidiPort, InConnRef)> = GLOBAL_LISTEN_LIST
+        .lock()
+        .map_err(MidiexError::from)?
+        .drain(..)
+        .collect();
+    close_subscriptions(subscriptions);
+    Ok(subscribed_ports()?)
 }
 
-#[rustler::nif]
+#[rustler::nif(schedule = "DirtyIo")]
 fn unsubscribe_port(midi_port: MidiPort) -> Result<Vec<MidiPort>, Error> {
-    GLOBAL_LISTEN_LIST
-        .lock()
-        .unwrap()
-        .retain(|x| *x != midi_port);
-    Ok(GLOBAL_LISTEN_LIST.lock().unwrap().to_vec())
+    let subscriptions = take_subscriptions(|x| *x == midi_port)?;
+    close_subscriptions(subscriptions);
+    Ok(subscribed_ports()?)
 }
 
-#[rustler::nif]
+#[rustler::nif(schedule = "DirtyIo")]
 fn unsubscribe_port_by_index(port_num: usize) -> Result<Vec<MidiPort>, Error> {
-    GLOBAL_LISTEN_LIST
-        .lock()
-        .unwrap()
-        .retain(|x| x.num != port_num);
-    Ok(GLOBAL_LISTEN_LIST.lock().unwrap().to_vec())
+    let subscriptions = take_subscriptions(|x| x.num == port_num)?;
+    close_subscriptions(subscriptions);
+    Ok(subscribed_ports()?)
 }
 
 #[rustler::nif]
 fn get_subscribed_ports() -> Result<Vec<MidiPort>, Error> {
-    Ok(GLOBAL_LISTEN_LIST.lock().unwrap().to_vec())
+    Ok(subscribed_ports()?)
 }
 
+// If decode is true each message is also sent decoded, see midi::decode. Only messages accepted by the filter are
+// sent. If batch is given, as the maximum number of messages in a batch and the longest (in microseconds) a message
+// waits to be sent, messages are sent in batches instead and {:ok, id} is returned, see listen_to_port_batched.
+// SysEx split into chunks is reassembled, up to sysex's maximum size (in bytes) and timeout (in milliseconds).
 #[rustler::nif]
-pub fn subscribe(env: Env, midi_port: MidiPort) -> Atom {
-    // Add the whole port struct to a listeners Vec
+#[allow(clippy::too_many_arguments)]
+pub fn subscribe<'a>(
+    env: Env<'a>,
+    midi_port: MidiPort,
+    decode: bool,
+    note_off: bool,
+    filter: FilterOptions,
+    batch: Option<(usize, u64)>,
+    sysex: (usize, u64),
+    names: Names,
+) -> Result<Term<'a>, Error> {
+    let decode = decode.then_some(DecodeOptions { note_off });
+    let filter = filter::Filter::try_from(filter)?;
+    let sysex = sysex_options(sysex);
+
+    // The input connection is owned by the subscription added to the listeners Vec
+    let (in_conn_ref, reply) = match batch {
+        Some((max_messages, max_delay_us)) => {
+            if max_messages == 0 || max_messages > batch::MAX_MESSAGES {
+                return Err(MidiexError::InvalidArgument(format!(
+                    "max_messages must be between 1 and {}, not {}",
+                    batch::MAX_MESSAGES,
+                    max_messages
+                ))
+                .into());
+            }
+            let id = {
+                let mut counter = GLOBAL_BATCH_COUNTER.lock().map_err(MidiexError::from)?;
+                *counter += 1;
+                *counter
+            };
+            let options = batch::BatchOptions {
+                max_messages,
+                max_delay: Duration::from_micros(max_delay_us),
+            };
+            let in_conn_ref = listen_to_port_batched(
+                env.pid(),
+                midi_port.clone(),
+                filter,
+                sysex,
+                &names,
+                options,
+                id,
+            )?;
+            (in_conn_ref, (atoms::ok(), id).encode(env))
+        }
+        None => {
+            let in_conn_ref =
+                listen_to_port(env.pid(), midi_port.clone(), decode, filter, sysex, &names)?;
+            (in_conn_ref, atoms::ok().encode(env))
+        }
+    };
 
-    let mut g_list_lock = GLOBAL_LISTEN_LIST.lock().unwrap();
-    g_list_lock.push(midi_port.clone());
-    g_list_lock.sort_unstable_by_key(|midi_port| (midi_port.num));
-    g_list_lock.dedup();
+    GLOBAL_LISTEN_LIST
+        .lock()
+        .map_err(MidiexError::from)?
+        .push((midi_port, in_conn_ref));
 
-    let m_port_clone = midi_port.clone();
+    Ok(reply)
+}
 
-    let pid = env.pid();
+// Removes the subscriptions matching the predicate from the listeners Vec, returning them so they can be closed
+// without holding the lock.
+fn take_subscriptions<F>(predicate: F) -> Result<Vec<(MidiPort, InConnRef)>, MidiexError>
+where
+    F: Fn(&MidiPort) -> bool,
+{
+    let mut g_list_lock = GLOBAL_LISTEN_LIST.lock()?;
+    let (taken, kept) = g_list_lock
+        .drain(..)
+        .partition(|(midi_port, _)| predicate(midi_port));
+    *g_list_lock = kept;
+    Ok(taken)
+}
 
-    let mut owned_env = OwnedEnv::new();
+fn close_subscriptions<P>(subscriptions: Vec<(P, InConnRef)>) {
+    for (_port, in_conn_ref) in subscriptions {
+        in_conn_ref.close();
+    }
+}
 
-    std::thread::spawn(move || {
-        let mut midi_in = MidiInput::new("MIDIex input").expect("Midi input");
-        midi_in.ignore(Ignore::None);
+// A port subscribed to more than once is only listed once
+fn subscribed_ports() -> Result<Vec<MidiPort>, MidiexError> {
+    let mut ports: Vec<MidiPort> = GLOBAL_LISTEN_LIST
+        .lock()?
+        .iter()
+        .map(|(midi_port, _)| midi_port.clone())
+        .collect();
+    ports.sort_unstable_by_key(|midi_port| midi_port.num);
+    ports.dedup();
+    Ok(ports)
+}
+
+#[derive(Clone, Copy)]
+pub struct DecodeOptions {
+    note_off: bool,
+}
+
+// The filter: option of Midiex.subscribe/2, with every key present
+#[derive(NifMap)]
+pub struct FilterOptions {
+    ignore: Vec<Atom>,
+    channels: Option<Vec<u8>>,
+    allow: Option<Vec<Atom>>,
+    deny: Vec<Atom>,
+    notes: Option<(u8, u8)>,
+    controls: Option<(u8, u8)>,
+    min_velocity: u8,
+}
+
+impl TryFrom<FilterOptions> for filter::Filter {
+    type Error = MidiexError;
+
+    fn try_from(options: FilterOptions) -> Result<Self, Self::Error> {
+        let mut ignore = Ignore::None;
+        for flag in options.ignore {
+            ignore = ignore
+                | match flag {
+                    flag if flag == atoms::sysex() => Ignore::Sysex,
+                    flag if flag == atoms::time() => Ignore::Time,
+                    flag if flag == atoms::active_sense() => Ignore::ActiveSense,
+                    _ => {
+                        return Err(MidiexError::InvalidArgument(
+                            "ignore flags must be :sysex, :time or :active_sense".to_string(),
+                        ))
+                    }
+                };
+        }
 
-        let in_port = match &midi_port.port_ref.0 {
-            MidiexMidiPortRef::Input(in_port) => in_port,
-            MidiexMidiPortRef::Output(_out_port) => {
-                panic!("Midi Input Port Error: Problem getting midi input port reference.")
+        let channels = match options.channels {
+            Some(channels) if channels.iter().any(|channel| *channel > 15) => {
+                return Err(MidiexError::InvalidArgument(
+                    "channels must be between 0 and 15".to_string(),
+                ))
             }
+            Some(channels) => channels.iter().fold(0, |mask, channel| mask | 1 << channel),
+            None => 0xFFFF,
+        };
+
+        let kinds = |atoms: Vec<Atom>| -> Result<filter::Kinds, MidiexError> {
+            atoms.into_iter().map(message_kind).collect()
         };
+        let range = |range: Option<(u8, u8)>| range.map_or(0..=127, |(first, last)| first..=last);
+
+        Ok(filter::Filter {
+            ignore,
+            channels,
+            allow: options.allow.map(kinds).transpose()?,
+            deny: kinds(options.deny)?,
+            notes: range(options.notes),
+            controls: range(options.controls),
+            min_velocity: options.min_velocity,
+        })
+    }
+}
+
+fn message_kind(atom: Atom) -> Result<filter::Kind, MidiexError> {
+    use filter::Kind::*;
+
+    let kinds = [
+        (atoms::note_off(), NoteOff),
+        (atoms::note_on(), NoteOn),
+        (atoms::poly_aftertouch(), PolyAftertouch),
+        (atoms::control_change(), ControlChange),
+        (atoms::program_change(), ProgramChange),
+        (atoms::channel_aftertouch(), ChannelAftertouch),
+        (atoms::pitch_bend(), PitchBend),
+        (atoms::sysex(), Sysex),
+        (atoms::mtc_quarter_frame(), MtcQuarterFrame),
+        (atoms::song_position(), SongPosition),
+        (atoms::song_select(), SongSelect),
+        (atoms::tune_request(), TuneRequest),
+        (atoms::clock(), Clock),
+        (atoms::start(), Start),
+        (atoms::continue_(), Continue),
+        (atoms::stop(), Stop),
+        (atoms::active_sensing(), ActiveSensing),
+        (atoms::reset(), Reset),
+    ];
+
+    kinds
+        .iter()
+        .find(|(kind_atom, _)| *kind_atom == atom)
+        .map(|(_, kind)| *kind)
+        .ok_or_else(|| MidiexError::InvalidArgument(format!("unknown message type {:?}", atom)))
+}
+
+// Opens an input connection to the port, forwarding each message accepted by the filter to the pid as a
+// MidiMessage, decoded if decode is given.
+fn listen_to_port(
+    pid: LocalPid,
+    midi_port: MidiPort,
+    decode: Option<DecodeOptions>,
+    filter: filter::Filter,
+    sysex: SysexOptions,
+    names: &Names,
+) -> Result<InConnRef, MidiexError> {
+    let mut owned_env = OwnedEnv::new();
+    let message_pid = pid;
+    let message_port = midi_port.clone();
+
+    connect_to_port(
+        pid,
+        midi_port,
+        filter.ignore,
+        sysex,
+        names,
+        move |stamp, message| {
+            if !filter.accepts(message) {
+                return;
+            }
+
+            owned_env.send_and_clear(&message_pid, |the_env| {
+                MidiMessage {
+                    data: message.to_vec(),
+                    port: InputPort::Port(message_port.clone()),
+                    timestamp: stamp,
+                    decoded: decode
+                        .and_then(|options| midi::decode(message, options.note_off).ok()),
+                }
+                .encode(the_env)
+            });
+        },
+    )
+}
+
+// Opens an input connection to the port, sending the messages accepted by the filter to the pid in batches, as
+// {:midi_batch, id, [{timestamp, binary}]}, see batch.rs.
+fn listen_to_port_batched(
+    pid: LocalPid,
+    midi_port: MidiPort,
+    filter: filter::Filter,
+    sysex: SysexOptions,
+    names: &Names,
+    options: batch::BatchOptions,
+    id: u64,
+) -> Result<InConnRef, MidiexError> {
+    let mut owned_env = OwnedEnv::new();
+    let batch_pid = pid;
+
+    let batcher = batch::spawn(options, move |messages| {
+        owned_env.send_and_clear(&batch_pid, |the_env| {
+            let messages: Vec<(u64, Binary)> = messages
+                .iter()
+                .map(|(stamp, data)| (*stamp, encode_binary(the_env, data)))
+                .collect();
+            (atoms::midi_batch(), id, messages).encode(the_env)
+        });
+    });
+
+    // The batcher thread exits once the connection is closed, as this drops its Sender
+    connect_to_port(
+        pid,
+        midi_port,
+        filter.ignore,
+        sysex,
+        names,
+        move |stamp, message| {
+            if filter.accepts(message) {
+                let _ = batcher.send((stamp, message.to_vec()));
+            }
+        },
+    )
+}
+
+// Connects to an input port, calling callback with each message received (apart from those ignored) and its
+// timestamp, with SysEx reassembled, see reassembling. If the connection fails pid is sent the error, see
+// InConnRef::spawn.
+fn connect_to_port<F>(
+    pid: LocalPid,
+    midi_port: MidiPort,
+    ignore: Ignore,
+    sysex: SysexOptions,
+    names: &Names,
+    callback: F,
+) -> Result<InConnRef, MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    let mut callback = reassembling(pid, sysex, callback);
+
+    let in_port = match &midi_port.port_ref.0 {
+        MidiexMidiPortRef::Input(in_port) => in_port.clone(),
+        MidiexMidiPortRef::LoopbackInput(device) => {
+            return connect_to_loopback(pid, *device, false, ignore, callback)
+        }
+        MidiexMidiPortRef::Output(_) | MidiexMidiPortRef::LoopbackOutput(_) => {
+            return Err(MidiexError::InvalidPort(
+                "Midi Input Port Error: Problem getting midi input port reference.".to_string(),
+            ))
+        }
+    };
 
-        let _conn_in = midi_in
+    let client_name = names.client_name("MIDIex input")?;
+    let port_name = names.port_name("midir-read-input")?;
+
+    InConnRef::spawn(pid, move || {
+        let mut midi_in = MidiInput::new(&client_name)?;
+        midi_in.ignore(ignore);
+
+        midi_in
             .connect(
                 &in_port,
-                "midir-read-input",
-                move |stamp, message, _| {
-                    owned_env.send_and_clear(&pid, |the_env| {
-                        // message.encode(the_env)
-
-                        let m_port_clone = m_port_clone.clone();
-
-                        MidiMessage {
-                            data: message.to_vec(),
-                            port: m_port_clone,
-                            timestamp: stamp,
-                        }
-                        .encode(the_env)
-                    });
-                    ()
-                },
+                &port_name,
+                move |stamp, message, _| callback(stamp, message),
                 (),
             )
-            .unwrap();
+            .map(InputConnection::Midir)
+            .map_err(MidiexError::from)
+    })
+}
+
+// Listens to a loopback device's input port, as connect_to_port does for a port from the OS, see loopback.rs. If
+// remove_device is true the device is removed once the connection is closed.
+fn connect_to_loopback<F>(
+    pid: LocalPid,
+    device: u32,
+    remove_device: bool,
+    ignore: Ignore,
+    mut callback: F,
+) -> Result<InConnRef, MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    InConnRef::spawn(pid, move || {
+        loopback::listen(device, remove_device, move |stamp, message| {
+            if !filter::ignores(ignore, message) {
+                callback(stamp, message)
+            }
+        })
+        .map(InputConnection::Loopback)
+        .ok_or_else(|| {
+            MidiexError::InvalidPort("The loopback device has been removed.".to_string())
+        })
+    })
+}
+
+fn sysex_options((max_size, timeout_ms): (usize, u64)) -> SysexOptions {
+    SysexOptions {
+        max_size,
+        timeout: Duration::from_millis(timeout_ms),
+    }
+}
+
+// Wraps an input callback so it's called with whole messages, SysEx the backend has split into chunks being joined
+// back together. When a SysEx is dropped pid is sent {:error, :sysex_overflow}, {:error, :unterminated_sysex} or
+// {:error, :sysex_timeout}, the last from the watchdog thread, which stops watching once the returned callback is
+// dropped.
+fn reassembling<F>(
+    pid: LocalPid,
+    options: SysexOptions,
+    mut callback: F,
+) -> impl FnMut(u64, &[u8]) + Send + 'static
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    let assembler = Arc::new(Mutex::new(Assembler::new(options)));
+
+    if !options.timeout.is_zero() {
+        let mut owned_env = OwnedEnv::new();
+        let watchdog_pid = pid;
+
+        sysex::spawn_watchdog(Arc::downgrade(&assembler), move || {
+            owned_env.send_and_clear(&watchdog_pid, |the_env| {
+                (atoms::error(), atoms::sysex_timeout()).encode(the_env)
+            });
+        });
+    }
 
-        let mut still_listen = true;
-        while still_listen {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            still_listen = GLOBAL_LISTEN_LIST.lock().unwrap().contains(&midi_port);
+    let mut owned_env = OwnedEnv::new();
+
+    move |stamp, chunk| {
+        let assembled = match assembler.lock() {
+            Ok(mut assembler) => assembler.push(stamp, chunk, Instant::now()),
+            Err(_) => return,
+        };
+
+        for item in assembled {
+            let reason = match item {
+                Assembled::Message(stamp, message) => {
+                    callback(stamp, &message);
+                    continue;
+                }
+                Assembled::Overflow => atoms::sysex_overflow(),
+                Assembled::Unterminated => atoms::unterminated_sysex(),
+                Assembled::Timeout => atoms::sysex_timeout(),
+            };
+
+            owned_env.send_and_clear(&pid, |the_env| (atoms::error(), reason).encode(the_env));
         }
+    }
+}
 
-        _conn_in.close();
-    });
+// ------------------
+// INPUT CONNECTION
+// ------------------
+
+#[rustler::nif]
+fn connect_input(env: Env, midi_port: MidiPort, names: Names) -> Result<InConn, Error> {
+    let in_conn_ref = listen_to_port(
+        env.pid(),
+        midi_port.clone(),
+        None,
+        filter::Filter::default(),
+        SysexOptions::default(),
+        &names,
+    )?;
+
+    Ok(InConn {
+        conn_ref: ResourceArc::new(in_conn_ref),
+        name: midi_port.name,
+        port_num: midi_port.num,
+    })
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn close_in_conn(midi_in_conn: InConn) -> Atom {
+    midi_in_conn.conn_ref.close();
 
     atoms::ok()
 }
@@ -168,23 +837,56 @@ pub fn subscribe(env: Env, midi_port: MidiPort) -> Atom {
 // VIRTUAL INPUT
 // ------------------
 
-#[cfg(not(any(target_os = "windows")))]
+// Creates the virtual input port, which other applications can see and send to straight away. Up to buffer_size of
+// the messages it receives while nothing is subscribed to it are kept for the next subscriber, see virtual_input.rs.
 #[rustler::nif]
-fn create_virtual_input(port_name: String) -> Result<VirtualMidiPort, Error> {
-    let port_index = GLOBAL_VIRTUAL_INPUT_COUNTER.lock().unwrap().add(1);
+fn create_virtual_input(
+    env: Env,
+    port_name: String,
+    buffer_size: usize,
+    names: Names,
+) -> Result<VirtualMidiPort, Error> {
+    let dispatcher = virtual_input::Dispatcher::new(buffer_size);
+    let input_dispatcher = dispatcher.clone();
+
+    let conn = create_virtual_port(
+        env.pid(),
+        port_name.clone(),
+        &names,
+        move |stamp, message| input_dispatcher.dispatch(stamp, message),
+    )?;
+
     Ok(VirtualMidiPort {
         direction: atoms::input(),
-        name: port_name.clone(),
-        num: port_index,
+        name: port_name,
+        num: next_virtual_input_num()?,
+        port_ref: ResourceArc::new(VirtualInputRef { conn, dispatcher }),
     })
 }
 
-#[cfg(target_os = "windows")]
+fn next_virtual_input_num() -> Result<usize, MidiexError> {
+    let mut counter = GLOBAL_VIRTUAL_INPUT_COUNTER.lock()?;
+    *counter += 1;
+    Ok(*counter)
+}
+
+// Closes the virtual input port, along with any subscriptions to it
 #[rustler::nif]
-fn create_virtual_input(_port_name: String) -> Result<VirtualMidiPort, Error> {
-    Err(Error::RaiseTerm(Box::new(
-        "Virtual inputs are not supported on Windows.".to_string(),
-    )))
+fn close_virtual_input(virtual_midi_port: VirtualMidiPort) -> Result<Atom, Error> {
+    let subscriptions = {
+        let mut gv_list_lock = GLOBAL_VIRTUAL_LISTEN_LIST
+            .lock()
+            .map_err(MidiexError::from)?;
+        let (taken, kept) = gv_list_lock
+            .drain(..)
+            .partition(|(virt_port, _)| virt_port == &virtual_midi_port);
+        *gv_list_lock = kept;
+        taken
+    };
+    close_subscriptions(subscriptions);
+
+    virtual_midi_port.port_ref.conn.close();
+    Ok(atoms::ok())
 }
 
 #[cfg(not(any(target_os = "windows")))]
@@ -192,24 +894,36 @@ fn create_virtual_input(_port_name: String) -> Result<VirtualMidiPort, Error> {
 fn unsubscribe_virtual_port(
     virtual_midi_port: VirtualMidiPort,
 ) -> Result<Vec<VirtualMidiPort>, Error> {
-    GLOBAL_VIRTUAL_LISTEN_LIST
-        .lock()
-        .unwrap()
-        .retain(|virt_port| virt_port != &virtual_midi_port);
-    Ok(GLOBAL_VIRTUAL_LISTEN_LIST.lock().unwrap().to_vec())
+    let subscriptions = {
+        let mut gv_list_lock = GLOBAL_VIRTUAL_LISTEN_LIST
+            .lock()
+            .map_err(MidiexError::from)?;
+        let (taken, kept) = gv_list_lock
+            .drain(..)
+            .partition(|(virt_port, _)| virt_port == &virtual_midi_port);
+        *gv_list_lock = kept;
+        taken
+    };
+    close_subscriptions(subscriptions);
+    Ok(subscribed_virtual_ports()?)
 }
 
 #[cfg(not(any(target_os = "windows")))]
 #[rustler::nif]
 fn unsubscribe_all_virtual_ports() -> Result<Vec<VirtualMidiPort>, Error> {
-    GLOBAL_VIRTUAL_LISTEN_LIST.lock().unwrap().clear();
-    Ok(GLOBAL_VIRTUAL_LISTEN_LIST.lock().unwrap().to_vec())
+    let subscriptions: Vec<(VirtualMidiPort, InConnRef)> = GLOBAL_VIRTUAL_LISTEN_LIST
+        .lock()
+        .map_err(MidiexError::from)?
+        .drain(..)
+        .collect();
+    close_subscriptions(subscriptions);
+    Ok(subscribed_virtual_ports()?)
 }
 
 #[cfg(not(any(target_os = "windows")))]
 #[rustler::nif]
 fn get_subscribed_virtual_ports() -> Result<Vec<VirtualMidiPort>, Error> {
-    Ok(GLOBAL_VIRTUAL_LISTEN_LIST.lock().unwrap().to_vec())
+    Ok(subscribed_virtual_ports()?)
 }
 #[cfg(target_os = "windows")]
 #[rustler::nif]
@@ -217,53 +931,225 @@ fn get_subscribed_virtual_ports() -> Result<Vec<VirtualMidiPort>, Error> {
     Ok(Vec::new())
 }
 
+#[cfg(not(any(target_os = "windows")))]
+fn subscribed_virtual_ports() -> Result<Vec<VirtualMidiPort>, MidiexError> {
+    let mut ports: Vec<VirtualMidiPort> = GLOBAL_VIRTUAL_LISTEN_LIST
+        .lock()?
+        .iter()
+        .map(|(virt_port, _)| virt_port.clone())
+        .collect();
+    ports.sort_unstable_by_key(|virt_port| virt_port.num);
+    ports.dedup();
+    Ok(ports)
+}
+
 #[cfg(not(any(target_os = "windows")))]
 // This replaces all other create_virtual_input stuff
 #[rustler::nif]
-pub fn subscribe_virtual_input(env: Env, virtual_midi_port: VirtualMidiPort) -> Atom {
-    let mut gv_list_lock = GLOBAL_VIRTUAL_LISTEN_LIST.lock().unwrap();
-    gv_list_lock.push(virtual_midi_port.clone());
-    gv_list_lock.sort_unstable_by_key(|midi_port| (midi_port.num));
-    gv_list_lock.dedup();
+pub fn subscribe_virtual_input(
+    env: Env,
+    virtual_midi_port: VirtualMidiPort,
+    decode: bool,
+    note_off: bool,
+    filter: FilterOptions,
+    sysex: (usize, u64),
+) -> Result<Atom, Error> {
+    let decode = decode.then_some(DecodeOptions { note_off });
+    let filter = filter::Filter::try_from(filter)?;
+
+    let in_conn_ref = listen_to_virtual_port(
+        env.pid(),
+        &virtual_midi_port,
+        decode,
+        filter,
+        sysex_options(sysex),
+    )?;
 
-    let pid = env.pid();
+    GLOBAL_VIRTUAL_LISTEN_LIST
+        .lock()
+        .map_err(MidiexError::from)?
+        .push((virtual_midi_port, in_conn_ref));
+
+    Ok(atoms::ok())
+}
 
+// Subscribes to a virtual input, sending the messages accepted by the filter to the pid, as listen_to_port does
+fn listen_to_virtual_port(
+    pid: LocalPid,
+    virtual_midi_port: &VirtualMidiPort,
+    decode: Option<DecodeOptions>,
+    filter: filter::Filter,
+    sysex: SysexOptions,
+) -> Result<InConnRef, MidiexError> {
     let mut owned_env = OwnedEnv::new();
+    let message_pid = pid;
+    let message_port = InputPort::Virtual(virtual_midi_port.clone());
+
+    connect_to_virtual_port(
+        pid,
+        virtual_midi_port,
+        filter.ignore,
+        sysex,
+        move |stamp, message| {
+            if !filter.accepts(message) {
+                return;
+            }
 
-    std::thread::spawn(move || {
-        let mut midi_in = MidiInput::new("MIDIex input").expect("Midi input");
+            owned_env.send_and_clear(&message_pid, |the_env| {
+                MidiMessage {
+                    data: message.to_vec(),
+                    port: message_port.clone(),
+                    timestamp: stamp,
+                    decoded: decode
+                        .and_then(|options| midi::decode(message, options.note_off).ok()),
+                }
+                .encode(the_env)
+            });
+        },
+    )
+}
+
+// Subscribes to a virtual input port, calling callback with each message received (apart from those ignored) and its
+// timestamp, as connect_to_port does. The subscription lasts until the returned connection is closed.
+fn connect_to_virtual_port<F>(
+    pid: LocalPid,
+    virtual_midi_port: &VirtualMidiPort,
+    ignore: Ignore,
+    sysex: SysexOptions,
+    callback: F,
+) -> Result<InConnRef, MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    if !virtual_midi_port.port_ref.conn.is_open() {
+        return Err(MidiexError::ConnectionClosed);
+    }
+
+    let mut callback = reassembling(pid, sysex, callback);
+    let dispatcher = virtual_midi_port.port_ref.dispatcher.clone();
+
+    InConnRef::spawn(pid, move || {
+        let subscription = dispatcher.subscribe(Box::new(move |stamp, message| {
+            if !filter::ignores(ignore, message) {
+                callback(stamp, message)
+            }
+        }));
+        Ok(InputConnection::Virtual(subscription))
+    })
+}
+
+// Creates a virtual input port, calling callback with every message received and its timestamp. The port exists
+// until the returned connection is closed. Its client is named after names, its port port_name.
+fn create_virtual_port<F>(
+    pid: LocalPid,
+    port_name: String,
+    names: &Names,
+    callback: F,
+) -> Result<InConnRef, MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    // Other applications send to a virtual input, so it's a loopback device with only an output port
+    if loopback::is_enabled() {
+        let client_name = names.client_name("MIDIex input")?;
+        let device = loopback::add_device(port_name, client_name, false, true, true);
+        return connect_to_loopback(pid, device, true, Ignore::None, callback);
+    }
+
+    create_os_virtual_port(pid, port_name, names, callback)
+}
+
+#[cfg(not(any(target_os = "windows")))]
+fn create_os_virtual_port<F>(
+    pid: LocalPid,
+    port_name: String,
+    names: &Names,
+    mut callback: F,
+) -> Result<InConnRef, MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    let client_name = names.client_name("MIDIex input")?;
+
+    InConnRef::spawn(pid, move || {
+        let mut midi_in = MidiInput::new(&client_name)?;
         midi_in.ignore(Ignore::None);
 
-        let _conn_in = midi_in
+        midi_in
             .create_virtual(
-                &virtual_midi_port.name,
-                move |_stamp, message, _| {
-                    owned_env.send_and_clear(&pid, |the_env| message.encode(the_env));
-                    ()
-                },
+                &port_name,
+                move |stamp, message, _| callback(stamp, message),
                 (),
             )
-            .unwrap();
+            .map(InputConnection::Midir)
+            .map_err(MidiexError::from)
+    })
+}
 
-        let mut still_listen = true;
-        while still_listen {
-            std::thread::sleep(std::time::Duration::from_millis(100));
-            still_listen = GLOBAL_VIRTUAL_LISTEN_LIST
-                .lock()
-                .unwrap()
-                .contains(&virtual_midi_port);
+#[cfg(target_os = "windows")]
+fn create_os_virtual_port<F>(
+    _pid: LocalPid,
+    _port_name: String,
+    _names: &Names,
+    _callback: F,
+) -> Result<InConnRef, MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    Err(MidiexError::Unsupported(
+        "Virtual inputs are not supported on Windows.".to_string(),
+    ))
+}
+
+// An input to listen on: either a port, or a virtual input. Also the port a MidiMessage came from.
+#[derive(Clone)]
+pub enum InputPort {
+    Port(MidiPort),
+    Virtual(VirtualMidiPort),
+}
+
+impl<'a> Decoder<'a> for InputPort {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        match term.decode::<MidiPort>() {
+            Ok(midi_port) => Ok(InputPort::Port(midi_port)),
+            Err(_) => Ok(InputPort::Virtual(term.decode()?)),
         }
+    }
+}
 
-        _conn_in.close();
-    });
+impl Encoder for InputPort {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            InputPort::Port(midi_port) => midi_port.encode(env),
+            InputPort::Virtual(virtual_midi_port) => virtual_midi_port.encode(env),
+        }
+    }
+}
 
-    atoms::ok()
+fn connect_to_input<F>(
+    pid: LocalPid,
+    input: InputPort,
+    ignore: Ignore,
+    sysex: SysexOptions,
+    callback: F,
+) -> Result<InConnRef, MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    match input {
+        InputPort::Port(midi_port) => {
+            connect_to_port(pid, midi_port, ignore, sysex, &Names::default(), callback)
+        }
+        InputPort::Virtual(virtual_midi_port) => {
+            connect_to_virtual_port(pid, &virtual_midi_port, ignore, sysex, callback)
+        }
+    }
 }
 
 // ---------------------------------------
 // NOTIFICATIONS AND HOTPLUG
 // ---------------------------------------
-// Supported on MacOS only at the moment
+// Supported on MacOS and Linux (ALSA) at the moment
 // ---------------------------------------
 
 #[cfg(all(target_os = "macos"))]
@@ -272,52 +1158,108 @@ pub fn notifications(env: Env) -> Result<Atom, Error> {
     let pid = env.pid();
     let mut owned_env = OwnedEnv::new();
 
-    std::thread::spawn(move || {
-        let cb_fb = move |notification: &Notification| {
-            match notification {
-                ObjectAdded(info) => owned_env.send_and_clear(&pid, |the_env| {
-                    MidiNotification::new(atoms::added(), info).encode(the_env)
-                }),
-                ObjectRemoved(info) => owned_env.send_and_clear(&pid, |the_env| {
-                    MidiNotification::new(atoms::removed(), info).encode(the_env)
-                }),
-                _ => (),
-            };
+    let cb_fb = move |notification: &Notification| {
+        match notification {
+            ObjectAdded(info) => owned_env.send_and_clear(&pid, |the_env| {
+                MidiNotification::new(atoms::added(), info).encode(the_env)
+            }),
+            ObjectRemoved(info) => owned_env.send_and_clear(&pid, |the_env| {
+                MidiNotification::new(atoms::removed(), info).encode(the_env)
+            }),
+            _ => (),
         };
+    };
+
+    run_notifications_client(cb_fb)?;
+
+    Ok(atoms::ok())
+}
+
+// The CoreMIDI client is created on, and runs on, its own thread. Returns once the client has been created (or has
+// failed to be).
+#[cfg(all(target_os = "macos"))]
+fn run_notifications_client<F>(cb_fb: F) -> Result<(), MidiexError>
+where
+    F: FnMut(&Notification) + Send + 'static,
+{
+    let (ready_tx, ready_rx) = mpsc::sync_channel::<Result<(), MidiexError>>(1);
+    let client_name = Names::default().client_name("MIDIex notifications client")?;
+
+    std::thread::spawn(
+        move || match Client::new_with_notifications(&client_name, cb_fb) {
+            Ok(_client) => {
+                let _ = ready_tx.send(Ok(()));
+                CFRunLoop::run_current();
+            }
+            Err(status) => {
+                let _ = ready_tx.send(Err(MidiexError::Other(format!(
+                    "CoreMIDI notifications client could not be created (OSStatus {})",
+                    status
+                ))));
+            }
+        },
+    );
+
+    ready_rx.recv().unwrap_or_else(|_| {
+        Err(MidiexError::Other(
+            "CoreMIDI notifications thread exited unexpectedly.".to_string(),
+        ))
+    })
+}
+
+#[cfg(target_os = "linux")]
+#[rustler::nif]
+pub fn notifications(env: Env) -> Result<Atom, Error> {
+    let pid = env.pid();
+    let mut owned_env = OwnedEnv::new();
 
-        let _client = Client::new_with_notifications("MIDIex notifications client", cb_fb).unwrap();
-        CFRunLoop::run_current();
+    let client_name = Names::default().client_name("MIDIex notifications client")?;
+    let watcher = alsa_seq::AnnounceWatcher::open(&client_name).map_err(MidiexError::from)?;
+
+    std::thread::spawn(move || {
+        let _ = watcher.run(|announcement| {
+            for notification in MidiNotification::from_announcement(announcement) {
+                owned_env.send_and_clear(&pid, |the_env| notification.encode(the_env));
+            }
+        });
     });
 
     Ok(atoms::ok())
 }
 
-#[cfg(not(any(target_os = "macos")))]
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
 #[rustler::nif]
 pub fn notifications() -> Result<Atom, Error> {
-    Err(Error::RaiseTerm(Box::new(
-        "Notications are not yet enabled for this platform (currently MacOS only)".to_string(),
-    )))
+    Err(MidiexError::Unsupported(
+        "Notications are not yet enabled for this platform (currently MacOS and Linux only)"
+            .to_string(),
+    )
+    .into())
 }
 
 #[cfg(all(target_os = "macos"))]
 #[rustler::nif]
 pub fn hotplug() -> Result<Atom, Error> {
-    std::thread::spawn(move || {
-        let cb_fb = move |_notification: &Notification| {};
-        let _client = Client::new_with_notifications("MIDIex notifications client", cb_fb).unwrap();
-        CFRunLoop::run_current();
-    });
+    let cb_fb = move |_notification: &Notification| {};
+    run_notifications_client(cb_fb)?;
+
+    Ok(atoms::ok())
+}
 
+// ALSA lists the sequencer's current clients and ports every time they are queried, so there is nothing to enable
+#[cfg(target_os = "linux")]
+#[rustler::nif]
+pub fn hotplug() -> Result<Atom, Error> {
     Ok(atoms::ok())
 }
 
-#[cfg(not(any(target_os = "macos")))]
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
 #[rustler::nif]
 pub fn hotplug() -> Result<Atom, Error> {
-    Err(Error::RaiseTerm(Box::new(
-        "Hotplug is not yet enabled for this platform (currently MacOS only)".to_string(),
-    )))
+    Err(MidiexError::Unsupported(
+        "Hotplug is not yet enabled for this platform (currently MacOS and Linux only)".to_string(),
+    )
+    .into())
 }
 
 // ------------------
@@ -325,62 +1267,25 @@ pub fn hotplug() -> Result<Atom, Error> {
 // ------------------
 
 #[rustler::nif]
-fn connect(midi_port: MidiPort) -> Result<OutConn, Error> {
-    if midi_port.direction == atoms::output() {
-        // println!("OUTPUT");
-
-        let midi_output = MidiOutput::new("MIDIex").expect("Midi output");
-
-        // let mut port_ref = midi_port.port_ref.0;
-
-        if let MidiexMidiPortRef::Output(port) = &midi_port.port_ref.0 {
-            // println!("OUTPUT PORT");
-
-            let conn_out_result = midi_output.connect(&port, "MIDIex");
-
-            let mut _conn_out = match conn_out_result {
-                Ok(conn_out) => {
-                    // println!("CONNECTION MADE");
-
-                    return Ok(
-                        OutConn {
-                            conn_ref: ResourceArc::new(OutConnRef::new(conn_out)),
-                            // midi_port: midi_port,
-                            name: midi_port.name,
-                            port_num: midi_port.num,
-                        }
-                    )
-
-                },
-                Err(error) => panic!("Midi Output Connection Error: Problem getting midi output connection. Error: {:?}", error)
-            };
-        };
-    } else {
-        // println!("INPUT");
-
-        // let mut midi_input = MidiInput::new("MIDIex").expect("Midi output");
-
-        // let mut port_ref = midi_port.port_ref.0;
-
-        if let MidiexMidiPortRef::Input(_port) = &midi_port.port_ref.0 {
-            // println!("INPUT PORT");
-
-            // let mut conn_in_result = midi_input.connect(&port, "MIDIex");
-
-            // let mut conn_in = match conn_in_result {
-            //     Ok(conn_in) => { println!("CONNECTION MADE"); conn_in},
-            //     Err(error) => panic!("Midi Input Connection Error: Problem getting midi input connection. Error: {:?}", error)
-            // };
-
-            return Err(Error::RaiseTerm(Box::new(
+fn connect(midi_port: MidiPort, names: Names) -> Result<OutConn, Error> {
+    let conn_out = match &midi_port.port_ref.0 {
+        MidiexMidiPortRef::Output(port) => connect_os_output(port, &midi_port, &names)?,
+        MidiexMidiPortRef::LoopbackOutput(device) => Connection::Loopback(*device),
+        MidiexMidiPortRef::Input(_) | MidiexMidiPortRef::LoopbackInput(_) => {
+            return Err(MidiexError::InvalidPort(
                 "Input connection rather than output.".to_string(),
-            )));
-        };
-    }
+            )
+            .into())
+        }
+    };
 
-    Err(Error::RaiseTerm(Box::new(
-        "No output connection".to_string(),
-    )))
+    Ok(OutConn {
+        conn_ref: ResourceArc::new(OutConnRef::new(conn_out)),
+        name: midi_port.name.clone(),
+        port_num: midi_port.num,
+        midi_port,
+        is_virtual: false,
+    })
 }
 
 // ------------------------
@@ -388,92 +1293,1747 @@ fn connect(midi_port: MidiPort) -> Result<OutConn, Error> {
 // ------------------------
 
 #[rustler::nif]
-fn close_out_conn(midi_out_conn: OutConn) -> Atom {
+fn close_out_conn(midi_out_conn: OutConn) -> Result<Atom, Error> {
+    // Dropping the scheduler stops its thread, along with anything still queued
     midi_out_conn
         .conn_ref
-        .0
+        .scheduler
+        .lock()
+        .map_err(MidiexError::from)?
+        .take();
+
+    midi_out_conn
+        .conn_ref
+        .conn
         .lock()
-        .expect("lock should not be poisoned")
+        .map_err(MidiexError::from)?
         .take()
-        .expect("there should be a connection")
+        .ok_or(MidiexError::ConnectionClosed)?
         .close();
 
-    atoms::ok()
+    Ok(atoms::ok())
 }
 
 // ------------------------
 // VIRTUAL OUPUT
 // ------------------------
 
-#[cfg(not(any(target_os = "windows")))]
 #[rustler::nif]
-fn create_virtual_output_conn(name: String) -> Result<OutConn, Error> {
-    let midi_output = MidiOutput::new("MIDIex").expect("Midi output");
-    let mut midi_input = MidiInput::new("MIDIex").expect("Midi input");
-    midi_input.ignore(Ignore::None);
+fn create_virtual_output_conn(name: String, names: Names) -> Result<OutConn, Error> {
+    let (conn, midi_port) = create_virtual_output(&name, &names)?;
+
+    Ok(OutConn {
+        conn_ref: ResourceArc::new(OutConnRef::new(conn)),
+        name,
+        port_num: midi_port.num,
+        midi_port,
+        is_virtual: true,
+    })
+}
+
+// On Linux the connection is from our own ALSA port, so a SysEx can be sent a chunk at a time
+#[cfg(target_os = "linux")]
+fn connect_os_output(
+    _port: &MidiOutputPort,
+    midi_port: &MidiPort,
+    names: &Names,
+) -> Result<Connection, MidiexError> {
+    let dest = match midi_port.native_id {
+        NativeId::Alsa(client, port) => alsa::seq::Addr { client, port },
+        _ => {
+            return Err(MidiexError::InvalidPort(format!(
+                "{} has no ALSA sequencer address.",
+                midi_port.name
+            )))
+        }
+    };
+    let conn = alsa_seq::Output::connect(
+        &names.client_name("MIDIex")?,
+        &names.port_name("MIDIex")?,
+        dest,
+    )?;
+    Ok(Connection::Alsa(conn))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_os_output(
+    port: &MidiOutputPort,
+    _midi_port: &MidiPort,
+    names: &Names,
+) -> Result<Connection, MidiexError> {
+    let midi_output = MidiOutput::new(&names.client_name("MIDIex")?)?;
+    let conn_out = midi_output.connect(port, &names.port_name("MIDIex")?)?;
+    Ok(Connection::Midir(conn_out))
+}
+
+// Creates a virtual output port called name, returning the connection sending from it and the port other
+// applications see. Its client is named after names.
+fn create_virtual_output(name: &str, names: &Names) -> Result<(Connection, MidiPort), MidiexError> {
+    // Other applications listen to a virtual output, so it's a loopback device with only an input port
+    if loopback::is_enabled() {
+        let client_name = names.client_name("MIDIex")?;
+        let device = loopback::add_device(name.to_string(), client_name, true, false, true);
+        let id = format!("loopback:{}", device);
+        let midi_port = find_virtual_output_port(|port| port.id == id)?;
+        return Ok((Connection::LoopbackVirtual(device), midi_port));
+    }
+
+    create_os_virtual_output(name, names)
+}
+
+// The port is found by its ALSA address, so it can't be mistaken for another client's port
+#[cfg(target_os = "linux")]
+fn create_os_virtual_output(
+    name: &str,
+    names: &Names,
+) -> Result<(Connection, MidiPort), MidiexError> {
+    let conn = alsa_seq::Output::create(&names.client_name("MIDIex")?, name)?;
+    let midi_port = find_alsa_virtual_output_port(&conn)?;
+
+    Ok((Connection::Alsa(conn), midi_port))
+}
+
+#[cfg(target_os = "linux")]
+fn find_alsa_virtual_output_port(conn: &alsa_seq::Output) -> Result<MidiPort, MidiexError> {
+    let addr = conn.addr();
+    let native_id = NativeId::Alsa(addr.client, addr.port);
+    find_virtual_output_port(|port| port.native_id == native_id)
+}
+
+// midir doesn't tell us the new port's id, so it's the port which wasn't listed before, preferring one with its name
+#[cfg(not(any(target_os = "windows", target_os = "linux")))]
+fn create_os_virtual_output(
+    name: &str,
+    names: &Names,
+) -> Result<(Connection, MidiPort), MidiexError> {
+    let _lock = GLOBAL_VIRTUAL_OUTPUT_LOCK.lock()?;
+
+    let listed: Vec<String> = all_ports(&Names::default())?
+        .into_iter()
+        .map(|port| port.id)
+        .collect();
+    let conn = MidiOutput::new(&names.client_name("MIDIex")?)?.create_virtual(name)?;
+    let midi_port =
+        find_virtual_output_port(|port| !listed.contains(&port.id) && port.name == name)
+            .or_else(|_| find_virtual_output_port(|port| !listed.contains(&port.id)))?;
+
+    Ok((Connection::Midir(conn), midi_port))
+}
+
+#[cfg(target_os = "windows")]
+fn create_os_virtual_output(
+    _name: &str,
+    _names: &Names,
+) -> Result<(Connection, MidiPort), MidiexError> {
+    Err(MidiexError::Unsupported("Virtual outputs are not supported on Windows.".to_string()))
+}
+
+// Even though it's an output, because it's a virtual port it is listed as an input when querying the OS for ports
+fn find_virtual_output_port<F>(is_ours: F) -> Result<MidiPort, MidiexError>
+where
+    F: Fn(&MidiPort) -> bool,
+{
+    all_ports(&Names::default())?
+        .into_iter()
+        .find(|port| port.direction == atoms::input() && is_ours(port))
+        .ok_or_else(|| {
+            MidiexError::InvalidPort(
+                "The virtual output isn't listed as an input port.".to_string(),
+            )
+        })
+}
+
+// ------------------------
+// VIRTUAL DEVICE
+// ------------------------
+
+// Creates a virtual input and output which other applications see as one device, on Linux as one ALSA client with
+// two ports. pid is sent the messages the input receives, as a subscriber to it is, see subscribe_virtual_input.
+#[rustler::nif]
+fn create_virtual_device(
+    pid: LocalPid,
+    name: String,
+    decode: bool,
+    note_off: bool,
+    filter: FilterOptions,
+    sysex: (usize, u64),
+    names: Names,
+) -> Result<VirtualDevice, Error> {
+    let decode = decode.then_some(DecodeOptions { note_off });
+    let filter = filter::Filter::try_from(filter)?;
+
+    let dispatcher = virtual_input::Dispatcher::new(0);
+    let input_dispatcher = dispatcher.clone();
+
+    let (in_conn, out_conn, midi_port) =
+        create_device_ports(pid, &name, &names, move |stamp, message| {
+            input_dispatcher.dispatch(stamp, message)
+        })?;
+
+    let input = VirtualMidiPort {
+        direction: atoms::input(),
+        name: name.clone(),
+        num: next_virtual_input_num()?,
+        port_ref: ResourceArc::new(VirtualInputRef {
+            conn: in_conn,
+            dispatcher,
+        }),
+    };
+
+    let in_conn_ref = listen_to_virtual_port(pid, &input, decode, filter, sysex_options(sysex))?;
+    GLOBAL_VIRTUAL_LISTEN_LIST
+        .lock()
+        .map_err(MidiexError::from)?
+        .push((input.clone(), in_conn_ref));
+
+    Ok(VirtualDevice {
+        name: name.clone(),
+        input,
+        output: OutConn {
+            conn_ref: ResourceArc::new(OutConnRef::new(out_conn)),
+            name,
+            port_num: midi_port.num,
+            midi_port,
+            is_virtual: true,
+        },
+    })
+}
+
+// The device's virtual input, calling callback with every message received and its timestamp, its virtual output,
+// and the port other applications see the output as. Unless it's given a client name, the device's client is named
+// after it.
+#[cfg(target_os = "linux")]
+fn create_device_ports<F>(
+    pid: LocalPid,
+    name: &str,
+    names: &Names,
+    mut callback: F,
+) -> Result<(InConnRef, Connection, MidiPort), MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    if loopback::is_enabled() {
+        return create_paired_ports(pid, name, names, callback);
+    }
+
+    let client_name = names.client_name(name)?;
+    let (input, output) = alsa_seq::create_virtual_device(&client_name, name, move |message| {
+        callback(scheduler::now_us(), message)
+    })?;
+    let in_conn = InConnRef::spawn(pid, move || Ok(InputConnection::AlsaVirtual(input)))?;
+    let midi_port = find_alsa_virtual_output_port(&output)?;
+
+    Ok((in_conn, Connection::Alsa(output), midi_port))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn create_device_ports<F>(
+    pid: LocalPid,
+    name: &str,
+    names: &Names,
+    callback: F,
+) -> Result<(InConnRef, Connection, MidiPort), MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    create_paired_ports(pid, name, names, callback)
+}
+
+// Where the backend can't make them one device, a virtual input and a virtual output with the same name. Loopback
+// devices are made in pairs too, as a loopback device's input and output ports are connected to each other.
+fn create_paired_ports<F>(
+    pid: LocalPid,
+    name: &str,
+    names: &Names,
+    callback: F,
+) -> Result<(InConnRef, Connection, MidiPort), MidiexError>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    let in_conn = create_virtual_port(pid, name.to_string(), names, callback)?;
+    let (out_conn, midi_port) = create_virtual_output(name, names)?;
+
+    Ok((in_conn, out_conn, midi_port))
+}
+
+#[derive(NifStruct)]
+#[module = "Midiex.VirtualDevice"]
+pub struct VirtualDevice {
+    name: String,
+    input: VirtualMidiPort,
+    output: OutConn,
+}
+
+// ------------------------
+// SENDING MIDI MESSAGES
+// ------------------------
+
+#[rustler::nif(schedule = "DirtyCpu")]
+fn send_msg(midi_out_conn: OutConn, message: Binary) -> Result<OutConn, Error> {
+    send_to_conn(&midi_out_conn, &message)?;
+    Ok(midi_out_conn)
+}
+
+// As send_msg, but first checks the message is a single, complete MIDI 1.0 message
+#[rustler::nif(schedule = "DirtyCpu")]
+fn send_msg_strict(midi_out_conn: OutConn, message: Binary) -> Result<OutConn, Error> {
+    midi::validate(&message).map_err(MidiexError::from)?;
+    send_to_conn(&midi_out_conn, &message)?;
+    Ok(midi_out_conn)
+}
+
+// Turns running status on or off for the connection, see running_status::Encoder
+#[rustler::nif]
+fn set_running_status(
+    midi_out_conn: OutConn,
+    enabled: bool,
+    refresh_ms: u64,
+) -> Result<OutConn, Error> {
+    let encoder = enabled.then(|| running_status::Encoder::new(Duration::from_millis(refresh_ms)));
+
+    let set = midi_out_conn
+        .conn_ref
+        .conn
+        .lock()
+        .map_err(MidiexError::from)?
+        .as_mut()
+        .ok_or(MidiexError::ConnectionClosed)?
+        .set_running_status(encoder);
+
+    if !set {
+        return Err(MidiexError::Unsupported(
+            "Running status is only supported on Linux and the loopback backend.".to_string(),
+        )
+        .into());
+    }
+    Ok(midi_out_conn)
+}
+
+fn send_to_conn(midi_out_conn: &OutConn, message: &[u8]) -> Result<(), MidiexError> {
+    let mut binding = midi_out_conn.conn_ref.conn.lock()?;
+
+    match binding.deref_mut() {
+        Some(conn) => Ok(conn.send(message)?),
+        None => Err(MidiexError::ConnectionClosed),
+    }
+}
+
+// ------------------------
+// RUNNING STATUS STREAMS
+// ------------------------
+
+#[derive(NifStruct)]
+#[module = "Midiex.StreamDecoder"]
+pub struct StreamDecoder {
+    decoder_ref: ResourceArc<StreamDecoderRef>,
+}
+
+pub struct StreamDecoderRef(pub Mutex<running_status::StreamDecoder>);
+
+#[rustler::nif]
+fn new_stream_decoder() -> StreamDecoder {
+    StreamDecoder {
+        decoder_ref: ResourceArc::new(StreamDecoderRef(Mutex::new(
+            running_status::StreamDecoder::new(),
+        ))),
+    }
+}
+
+// Returns the complete messages in the next bytes of a stream, see running_status::StreamDecoder
+#[rustler::nif]
+fn stream_decode<'a>(
+    env: Env<'a>,
+    decoder: StreamDecoder,
+    bytes: Binary,
+) -> Result<Vec<Binary<'a>>, Error> {
+    let messages = decoder
+        .decoder_ref
+        .0
+        .lock()
+        .map_err(MidiexError::from)?
+        .decode(&bytes);
+
+    Ok(messages
+        .iter()
+        .map(|message| to_binary(env, message))
+        .collect::<Result<_, _>>()?)
+}
+
+// ------------------------
+// SCHEDULED MIDI MESSAGES
+// ------------------------
+
+// Microseconds on the clock used by send_at
+#[rustler::nif]
+fn now_us() -> u64 {
+    scheduler::now_us()
+}
+
+// Queues each {timestamp_us, message} to be sent by the connection's scheduler thread at timestamp_us (see now_us).
+// Messages with a timestamp in the past are sent straight away.
+#[rustler::nif]
+fn send_at(midi_out_conn: OutConn, messages: Vec<(u64, Binary)>) -> Result<OutConn, Error> {
+    let msgs: Vec<(Instant, Vec<u8>)> = messages
+        .iter()
+        .map(|(timestamp_us, message)| {
+            (
+                scheduler::instant_from_us(*timestamp_us),
+                message.as_slice().to_vec(),
+            )
+        })
+        .collect();
+
+    midi_out_conn.conn_ref.schedule(msgs)?;
+
+    Ok(midi_out_conn)
+}
+
+// Sends everything queued on the connection now, returning once it's been sent
+#[rustler::nif(schedule = "DirtyCpu")]
+fn flush(midi_out_conn: OutConn) -> Result<OutConn, Error> {
+    midi_out_conn
+        .conn_ref
+        .with_scheduler(|scheduler| scheduler.flush())?;
+
+    Ok(midi_out_conn)
+}
+
+// Drops everything queued on the connection without sending it
+#[rustler::nif(schedule = "DirtyCpu")]
+fn cancel_all(midi_out_conn: OutConn) -> Result<OutConn, Error> {
+    midi_out_conn
+        .conn_ref
+        .with_scheduler(|scheduler| scheduler.cancel_all())?;
+
+    Ok(midi_out_conn)
+}
+
+#[rustler::nif]
+fn queue_len(midi_out_conn: OutConn) -> Result<usize, Error> {
+    let len = midi_out_conn
+        .conn_ref
+        .with_scheduler(|scheduler| scheduler.queue_len())?;
+
+    Ok(len.unwrap_or(0))
+}
+
+// ------------------------
+// SYSEX DUMPS
+// ------------------------
+
+// Sends a dump of one or more SysExs in chunks, paced on its own thread, returning {:ok, id}. The pid is sent
+// {:midiex_sysex, id, update} after each chunk, and when it's done or has failed, see sysex::spawn_transmit. If a
+// handshake is given, as the input port to listen on, the start of the acknowledging SysEx and a timeout in
+// milliseconds, each packet has to be acknowledged before the next is sent.
+#[rustler::nif]
+fn send_sysex(
+    env: Env,
+    midi_out_conn: OutConn,
+    dump: Binary,
+    chunk_size: usize,
+    inter_chunk_delay_us: u64,
+    handshake: Option<(MidiPort, Binary, u64)>,
+) -> Result<(Atom, u64), Error> {
+    let packets = sysex::packets(&dump).map_err(MidiexError::from)?;
+
+    if midi_out_conn
+        .conn_ref
+        .conn
+        .lock()
+        .map_err(MidiexError::from)?
+        .is_none()
+    {
+        return Err(MidiexError::ConnectionClosed.into());
+    }
+
+    let pid = env.pid();
+
+    // The paired input connection is closed once the dump is done, or has failed
+    let (handshake, mut ack_conn) = match handshake {
+        Some((midi_port, ack, timeout_ms)) => {
+            let (received_tx, received_rx) = mpsc::channel::<Vec<u8>>();
+            let conn = connect_to_port(
+                pid,
+                midi_port,
+                Ignore::None,
+                SysexOptions::default(),
+                &Names::default(),
+                move |_stamp, message| {
+                    if message.first() == Some(&midi::SYSEX_START) {
+                        let _ = received_tx.send(message.to_vec());
+                    }
+                },
+            )?;
+            let handshake = sysex::Handshake {
+                received: received_rx,
+                ack: ack.to_vec(),
+                timeout: Duration::from_millis(timeout_ms),
+            };
+            (Some(handshake), Some(conn))
+        }
+        None => (None, None),
+    };
+
+    let id = {
+        let mut counter = GLOBAL_SYSEX_DUMP_COUNTER
+            .lock()
+            .map_err(MidiexError::from)?;
+        *counter += 1;
+        *counter
+    };
+
+    let options = sysex::TransmitOptions {
+        chunk_size,
+        inter_chunk_delay: Duration::from_micros(inter_chunk_delay_us),
+        handshake,
+    };
+    let mut owned_env = OwnedEnv::new();
+
+    sysex::spawn_transmit(
+        midi_out_conn.conn_ref.conn.clone(),
+        packets,
+        options,
+        move |update| {
+            if !matches!(update, sysex::TransmitUpdate::Progress(_, _)) {
+                if let Some(conn) = ack_conn.take() {
+                    conn.close();
+                }
+            }
+
+            owned_env.send_and_clear(&pid, |the_env| {
+                let update = match update {
+                    sysex::TransmitUpdate::Progress(sent, total) => {
+                        (atoms::progress(), sent, total).encode(the_env)
+                    }
+                    sysex::TransmitUpdate::Done => atoms::done().encode(the_env),
+                    sysex::TransmitUpdate::Failed(error) => (atoms::error(), error).encode(the_env),
+                };
+                (atoms::midiex_sysex(), id, update).encode(the_env)
+            });
+        },
+    );
+
+    Ok((atoms::ok(), id))
+}
+
+// ------------------------
+// STANDARD MIDI FILES
+// ------------------------
+
+#[derive(NifStruct)]
+#[module = "Midiex.Smf"]
+pub struct SmfFile {
+    format: u16,
+    division: smf::Division,
+    tracks: Vec<Vec<(u32, smf::SmfEvent)>>,
+}
+
+impl From<smf::Smf> for SmfFile {
+    fn from(smf: smf::Smf) -> Self {
+        Self {
+            format: smf.format,
+            division: smf.division,
+            tracks: smf.tracks,
+        }
+    }
+}
+
+impl From<SmfFile> for smf::Smf {
+    fn from(smf_file: SmfFile) -> Self {
+        Self {
+            format: smf_file.format,
+            division: smf_file.division,
+            tracks: smf_file.tracks,
+        }
+    }
+}
+
+// Takes either the contents of a standard MIDI file, or a path to one
+#[rustler::nif(schedule = "DirtyIo")]
+fn read_smf(path_or_binary: Binary) -> Result<SmfFile, Error> {
+    let smf = if smf::is_smf(&path_or_binary) {
+        smf::parse(&path_or_binary)
+    } else {
+        let path = String::from_utf8_lossy(&path_or_binary).into_owned();
+        let bytes = std::fs::read(path).map_err(MidiexError::from)?;
+        smf::parse(&bytes)
+    };
+
+    Ok(smf.map_err(MidiexError::from)?.into())
+}
+
+// Returns the contents of a format 0 or 1 standard MIDI file, see smf::write
+#[rustler::nif(schedule = "DirtyCpu")]
+fn write_smf<'a>(
+    env: Env<'a>,
+    smf_file: SmfFile,
+    tempo_map: Vec<(u32, u32)>,
+    running_status: bool,
+) -> Result<Binary<'a>, Error> {
+    let bytes =
+        smf::write(smf_file.into(), &tempo_map, running_status).map_err(MidiexError::from)?;
+
+    Ok(to_binary(env, &bytes)?)
+}
+
+impl Encoder for smf::Division {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            smf::Division::Ppq(ticks) => (atoms::ppq(), ticks).encode(env),
+            smf::Division::Smpte(fps, ticks) => (atoms::smpte(), fps, ticks).encode(env),
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for smf::Division {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let elems = get_tuple(term)?;
+        let tag: Atom = elems.first().ok_or(Error::BadArg)?.decode()?;
+
+        match elems.len() {
+            2 if tag == atoms::ppq() => Ok(smf::Division::Ppq(elems[1].decode()?)),
+            3 if tag == atoms::smpte() => {
+                Ok(smf::Division::Smpte(elems[1].decode()?, elems[2].decode()?))
+            }
+            _ => Err(Error::BadArg),
+        }
+    }
+}
+
+impl Encoder for smf::SmfEvent {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            smf::SmfEvent::Midi(msg) => (atoms::midi(), encode_binary(env, msg)).encode(env),
+            smf::SmfEvent::Sysex(msg) => (atoms::sysex(), encode_binary(env, msg)).encode(env),
+            smf::SmfEvent::Escape(msg) => (atoms::escape(), encode_binary(env, msg)).encode(env),
+            smf::SmfEvent::Tempo(us_per_quarter) => (atoms::tempo(), us_per_quarter).encode(env),
+            smf::SmfEvent::TimeSignature {
+                numerator,
+                denominator,
+                clocks_per_click,
+                thirty_seconds_per_quarter,
+            } => (
+                atoms::time_signature(),
+                numerator,
+                denominator,
+                clocks_per_click,
+                thirty_seconds_per_quarter,
+            )
+                .encode(env),
+            smf::SmfEvent::KeySignature {
+                sharps_flats,
+                minor,
+            } => {
+                let mode = if *minor {
+                    atoms::minor()
+                } else {
+                    atoms::major()
+                };
+                (atoms::key_signature(), sharps_flats, mode).encode(env)
+            }
+            smf::SmfEvent::TrackName(name) => (atoms::track_name(), name).encode(env),
+            smf::SmfEvent::EndOfTrack => atoms::end_of_track().encode(env),
+            smf::SmfEvent::Meta(meta_type, data) => {
+                (atoms::meta(), meta_type, encode_binary(env, data)).encode(env)
+            }
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for smf::SmfEvent {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if let Ok(tag) = term.decode::<Atom>() {
+            return match tag == atoms::end_of_track() {
+                true => Ok(smf::SmfEvent::EndOfTrack),
+                false => Err(Error::BadArg),
+            };
+        }
+
+        let elems = get_tuple(term)?;
+        let tag: Atom = elems.first().ok_or(Error::BadArg)?.decode()?;
+        let bytes = |i: usize| -> NifResult<Vec<u8>> { Ok(elems[i].decode::<Binary>()?.to_vec()) };
+
+        match elems.len() {
+            2 if tag == atoms::midi() => Ok(smf::SmfEvent::Midi(bytes(1)?)),
+            2 if tag == atoms::sysex() => Ok(smf::SmfEvent::Sysex(bytes(1)?)),
+            2 if tag == atoms::escape() => Ok(smf::SmfEvent::Escape(bytes(1)?)),
+            2 if tag == atoms::tempo() => Ok(smf::SmfEvent::Tempo(elems[1].decode()?)),
+            2 if tag == atoms::track_name() => Ok(smf::SmfEvent::TrackName(elems[1].decode()?)),
+            5 if tag == atoms::time_signature() => Ok(smf::SmfEvent::TimeSignature {
+                numerator: elems[1].decode()?,
+                denominator: elems[2].decode()?,
+                clocks_per_click: elems[3].decode()?,
+                thirty_seconds_per_quarter: elems[4].decode()?,
+            }),
+            3 if tag == atoms::key_signature() => Ok(smf::SmfEvent::KeySignature {
+                sharps_flats: elems[1].decode()?,
+                minor: elems[2].decode::<Atom>()? == atoms::minor(),
+            }),
+            3 if tag == atoms::meta() => Ok(smf::SmfEvent::Meta(elems[1].decode()?, bytes(2)?)),
+            _ => Err(Error::BadArg),
+        }
+    }
+}
+
+// ------------------------
+// STANDARD MIDI FILE PLAYER
+// ------------------------
+
+#[derive(NifStruct)]
+#[module = "Midiex.Player"]
+pub struct Player {
+    player_ref: ResourceArc<PlayerRef>,
+    id: u64,
+}
+
+// Dropping the sender (when the resource is garbage collected) stops playback, as stop does
+pub struct PlayerRef(pub Mutex<Sender<PlayerCommand>>);
+
+// Plays smf_file to the connection on a new thread. The calling process is sent {:midiex_player, id, update}
+// messages, where update is {:position, tick, ms} (every position_interval_ms, if not 0), :finished or :stopped.
+#[rustler::nif]
+fn play_smf(
+    env: Env,
+    midi_out_conn: OutConn,
+    smf_file: SmfFile,
+    tempo_scale: f64,
+    position_interval_ms: u64,
+    start: Option<Position>,
+    loop_range: Option<(Position, Position)>,
+) -> Result<Player, Error> {
+    if tempo_scale <= 0.0 || !tempo_scale.is_finite() {
+        return Err(MidiexError::InvalidArgument(format!(
+            "the tempo scale must be greater than 0, not {}",
+            tempo_scale
+        ))
+        .into());
+    }
+    if midi_out_conn
+        .conn_ref
+        .conn
+        .lock()
+        .map_err(MidiexError::from)?
+        .is_none()
+    {
+        return Err(MidiexError::ConnectionClosed.into());
+    }
+
+    let id = {
+        let mut counter = GLOBAL_PLAYER_COUNTER.lock().map_err(MidiexError::from)?;
+        *counter += 1;
+        *counter
+    };
+
+    let pid = env.pid();
+    let mut owned_env = OwnedEnv::new();
+
+    let options = PlayerOptions {
+        tempo_scale,
+        position_interval: match position_interval_ms {
+            0 => None,
+            ms => Some(std::time::Duration::from_millis(ms)),
+        },
+        start,
+        loop_range,
+    };
+
+    let tx = player::spawn(
+        midi_out_conn.conn_ref.conn.clone(),
+        smf_file.into(),
+        options,
+        move |update| {
+            owned_env.send_and_clear(&pid, |the_env| {
+                let update = match update {
+                    PlayerUpdate::Position(tick, ms) => {
+                        (atoms::position(), tick, ms).encode(the_env)
+                    }
+                    PlayerUpdate::Finished => atoms::finished().encode(the_env),
+                    PlayerUpdate::Stopped => atoms::stopped().encode(the_env),
+                };
+                (atoms::midiex_player(), id, update).encode(the_env)
+            })
+        },
+    );
+
+    Ok(Player {
+        player_ref: ResourceArc::new(PlayerRef(Mutex::new(tx))),
+        id,
+    })
+}
+
+fn send_player_command(player: Player, command: PlayerCommand) -> Result<Player, Error> {
+    player
+        .player_ref
+        .0
+        .lock()
+        .map_err(MidiexError::from)?
+        .send(command)
+        .map_err(|_| MidiexError::PlayerStopped)?;
+
+    Ok(player)
+}
+
+#[rustler::nif]
+fn player_pause(player: Player) -> Result<Player, Error> {
+    send_player_command(player, PlayerCommand::Pause)
+}
+
+#[rustler::nif]
+fn player_resume(player: Player) -> Result<Player, Error> {
+    send_player_command(player, PlayerCommand::Resume)
+}
+
+#[rustler::nif]
+fn player_seek(player: Player, position: Position) -> Result<Player, Error> {
+    send_player_command(player, PlayerCommand::Seek(position))
+}
+
+#[rustler::nif]
+fn player_set_tempo_scale(player: Player, tempo_scale: f64) -> Result<Player, Error> {
+    if tempo_scale <= 0.0 || !tempo_scale.is_finite() {
+        return Err(MidiexError::InvalidArgument(format!(
+            "the tempo scale must be greater than 0, not {}",
+            tempo_scale
+        ))
+        .into());
+    }
+    send_player_command(player, PlayerCommand::SetTempoScale(tempo_scale))
+}
+
+#[rustler::nif]
+fn player_loop(player: Player, loop_range: Option<(Position, Position)>) -> Result<Player, Error> {
+    send_player_command(player, PlayerCommand::SetLoop(loop_range))
+}
+
+#[rustler::nif]
+fn player_stop(player: Player) -> Result<Player, Error> {
+    send_player_command(player, PlayerCommand::Stop)
+}
+
+impl<'a> Decoder<'a> for Position {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let (unit, value): (Atom, u64) = term.decode()?;
+
+        if unit == atoms::tick() {
+            Ok(Position::Tick(value))
+        } else if unit == atoms::ms() {
+            Ok(Position::Ms(value))
+        } else {
+            Err(Error::BadArg)
+        }
+    }
+}
+
+// ------------------------
+// RECORDER
+// ------------------------
+
+#[derive(NifStruct)]
+#[module = "Midiex.Recorder"]
+pub struct Recorder {
+    recorder_ref: ResourceArc<RecorderRef>,
+    name: String,
+    port_num: usize,
+}
+
+pub struct RecorderRef {
+    conn: InConnRef,
+    take: Arc<Mutex<recorder::Take>>,
+}
+
+// Starts recording messages received on the input port into a buffer, until stop_recording is called. Nothing is
+// sent to the calling process while recording, unless the connection fails.
+#[rustler::nif]
+#[allow(clippy::too_many_arguments)]
+fn start_recording(
+    env: Env,
+    midi_port: MidiPort,
+    tempo: u32,
+    ppq: u16,
+    count_in: Option<Position>,
+    punch_in: Option<Position>,
+    punch_out: Option<Position>,
+    quantize: u32,
+) -> Result<Recorder, Error> {
+    if tempo == 0 || ppq == 0 || ppq & 0x8000 != 0 {
+        return Err(MidiexError::InvalidArgument(format!(
+            "the tempo must be greater than 0 and the PPQ between 1 and 32767, not {} and {}",
+            tempo, ppq
+        ))
+        .into());
+    }
+
+    let take = Arc::new(Mutex::new(recorder::Take::new(recorder::RecorderOptions {
+        tempo,
+        ppq,
+        count_in,
+        punch_in,
+        punch_out,
+        quantize,
+    })));
+    let recording_take = take.clone();
+
+    let conn = connect_to_port(
+        env.pid(),
+        midi_port.clone(),
+        Ignore::None,
+        SysexOptions::default(),
+        &Names::default(),
+        move |stamp, message| {
+            if let Ok(mut take) = recording_take.lock() {
+                take.push(stamp, message);
+            }
+        },
+    )?;
+
+    Ok(Recorder {
+        recorder_ref: ResourceArc::new(RecorderRef { conn, take }),
+        name: midi_port.name,
+        port_num: midi_port.num,
+    })
+}
+
+// Stops recording, returning the take as a %Midiex.Smf{} or, if as_binary is true, as a standard MIDI file binary.
+// Calling it again returns the same take.
+#[rustler::nif(schedule = "DirtyIo")]
+fn stop_recording<'a>(
+    env: Env<'a>,
+    recorder: Recorder,
+    as_binary: bool,
+) -> Result<Term<'a>, Error> {
+    recorder.recorder_ref.conn.close();
+
+    let smf = recorder
+        .recorder_ref
+        .take
+        .lock()
+        .map_err(MidiexError::from)?
+        .to_smf();
+
+    if as_binary {
+        let bytes = smf::write(smf, &[], true).map_err(MidiexError::from)?;
+        Ok(to_binary(env, &bytes)?.encode(env))
+    } else {
+        Ok(SmfFile::from(smf).encode(env))
+    }
+}
+
+// Copies bytes into a new binary, to be returned from a NIF
+fn to_binary<'a>(env: Env<'a>, bytes: &[u8]) -> Result<Binary<'a>, MidiexError> {
+    let mut binary = OwnedBinary::new(bytes.len())
+        .ok_or_else(|| MidiexError::Other("could not allocate a binary".to_string()))?;
+    binary.as_mut_slice().copy_from_slice(bytes);
+    Ok(binary.release(env))
+}
+
+// As to_binary, for encoders, which can't return an error. The binary is made on the env's own heap rather than
+// allocated separately.
+fn encode_binary<'a>(env: Env<'a>, bytes: &[u8]) -> Binary<'a> {
+    let mut binary = NewBinary::new(env, bytes.len());
+    binary.as_mut_slice().copy_from_slice(bytes);
+    binary.into()
+}
+
+// =================
+// MIDI Message
+// =================
+#[derive(NifStruct)]
+#[module = "Midiex.MidiMessage"]
+pub struct MidiMessage {
+    port: InputPort,
+    data: Vec<u8>,
+    timestamp: u64,
+    decoded: Option<midi::Message>,
+}
+
+// Decodes a single MIDI 1.0 message, see midi::decode. Named decode_msg so it doesn't clash with decode arguments.
+#[rustler::nif(name = "decode")]
+fn decode_msg(message: Binary, note_off: bool) -> Result<midi::Message, Error> {
+    Ok(midi::decode(&message, note_off).map_err(MidiexError::from)?)
+}
+
+impl Encoder for midi::Message {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        use midi::Message::*;
+
+        // Messages without data are 1 element tuples, e.g. {:clock}
+        let tag_only = |tag: Atom| make_tuple(env, &[tag.encode(env)]);
+
+        match self {
+            NoteOff(channel, note, velocity) => {
+                (atoms::note_off(), channel, note, velocity).encode(env)
+            }
+            NoteOn(channel, note, velocity) => {
+                (atoms::note_on(), channel, note, velocity).encode(env)
+            }
+            PolyAftertouch(channel, note, pressure) => {
+                (atoms::poly_aftertouch(), channel, note, pressure).encode(env)
+            }
+            ControlChange(channel, control, value) => {
+                (atoms::control_change(), channel, control, value).encode(env)
+            }
+            ProgramChange(channel, program) => {
+                (atoms::program_change(), channel, program).encode(env)
+            }
+            ChannelAftertouch(channel, pressure) => {
+                (atoms::channel_aftertouch(), channel, pressure).encode(env)
+            }
+            PitchBend(channel, bend) => (atoms::pitch_bend(), channel, bend).encode(env),
+            AllSoundOff(channel) => (atoms::all_sound_off(), channel).encode(env),
+            ResetAllControllers(channel) => (atoms::reset_all_controllers(), channel).encode(env),
+            LocalControl(channel, on) => (atoms::local_control(), channel, on).encode(env),
+            AllNotesOff(channel) => (atoms::all_notes_off(), channel).encode(env),
+            OmniOff(channel) => (atoms::omni_off(), channel).encode(env),
+            OmniOn(channel) => (atoms::omni_on(), channel).encode(env),
+            MonoOn(channel, channels) => (atoms::mono_on(), channel, channels).encode(env),
+            PolyOn(channel) => (atoms::poly_on(), channel).encode(env),
+            Sysex(id, payload) => {
+                let binary = |bytes: &[u8]| encode_binary(env, bytes);
+                (atoms::sysex(), binary(id), binary(payload)).encode(env)
+            }
+            MtcQuarterFrame(piece, value) => (atoms::mtc_quarter_frame(), piece, value).encode(env),
+            SongPosition(beats) => (atoms::song_position(), beats).encode(env),
+            SongSelect(song) => (atoms::song_select(), song).encode(env),
+            TuneRequest => tag_only(atoms::tune_request()),
+            Clock => tag_only(atoms::clock()),
+            Start => tag_only(atoms::start()),
+            Continue => tag_only(atoms::continue_()),
+            Stop => tag_only(atoms::stop()),
+            ActiveSensing => tag_only(atoms::active_sensing()),
+            Reset => tag_only(atoms::reset()),
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for midi::Message {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        use midi::Message::*;
+
+        let elems = get_tuple(term)?;
+        let tag: Atom = elems.first().ok_or(Error::BadArg)?.decode()?;
+        let byte = |i: usize| -> NifResult<u8> { elems[i].decode() };
+        let bytes = |i: usize| -> NifResult<Vec<u8>> { Ok(elems[i].decode::<Binary>()?.to_vec()) };
+
+        match elems.len() {
+            4 if tag == atoms::note_off() => Ok(NoteOff(byte(1)?, byte(2)?, byte(3)?)),
+            4 if tag == atoms::note_on() => Ok(NoteOn(byte(1)?, byte(2)?, byte(3)?)),
+            4 if tag == atoms::poly_aftertouch() => {
+                Ok(PolyAftertouch(byte(1)?, byte(2)?, byte(3)?))
+            }
+            4 if tag == atoms::control_change() => Ok(ControlChange(byte(1)?, byte(2)?, byte(3)?)),
+            3 if tag == atoms::program_change() => Ok(ProgramChange(byte(1)?, byte(2)?)),
+            3 if tag == atoms::channel_aftertouch() => Ok(ChannelAftertouch(byte(1)?, byte(2)?)),
+            3 if tag == atoms::pitch_bend() => Ok(PitchBend(byte(1)?, elems[2].decode()?)),
+            2 if tag == atoms::all_sound_off() => Ok(AllSoundOff(byte(1)?)),
+            2 if tag == atoms::reset_all_controllers() => Ok(ResetAllControllers(byte(1)?)),
+            3 if tag == atoms::local_control() => Ok(LocalControl(byte(1)?, elems[2].decode()?)),
+            2 if tag == atoms::all_notes_off() => Ok(AllNotesOff(byte(1)?)),
+            2 if tag == atoms::omni_off() => Ok(OmniOff(byte(1)?)),
+            2 if tag == atoms::omni_on() => Ok(OmniOn(byte(1)?)),
+            3 if tag == atoms::mono_on() => Ok(MonoOn(byte(1)?, byte(2)?)),
+            2 if tag == atoms::poly_on() => Ok(PolyOn(byte(1)?)),
+            3 if tag == atoms::sysex() => Ok(Sysex(bytes(1)?, bytes(2)?)),
+            3 if tag == atoms::mtc_quarter_frame() => Ok(MtcQuarterFrame(byte(1)?, byte(2)?)),
+            2 if tag == atoms::song_position() => Ok(SongPosition(elems[1].decode()?)),
+            2 if tag == atoms::song_select() => Ok(SongSelect(byte(1)?)),
+            1 if tag == atoms::tune_request() => Ok(TuneRequest),
+            1 if tag == atoms::clock() => Ok(Clock),
+            1 if tag == atoms::start() => Ok(Start),
+            1 if tag == atoms::continue_() => Ok(Continue),
+            1 if tag == atoms::stop() => Ok(Stop),
+            1 if tag == atoms::active_sensing() => Ok(ActiveSensing),
+            1 if tag == atoms::reset() => Ok(Reset),
+            _ => Err(Error::BadArg),
+        }
+    }
+}
+
+// =================
+// Universal MIDI Packets
+// =================
+
+// Converts a single MIDI 1.0 message to the words of one or more UMPs, see ump::from_midi1
+#[rustler::nif]
+fn ump_from_midi1(message: Binary, group: u8, midi2: bool) -> Result<Vec<u32>, Error> {
+    let protocol = match midi2 {
+        true => ump::Protocol::Midi2,
+        false => ump::Protocol::Midi1,
+    };
+    Ok(ump::from_midi1(&message, group, protocol).map_err(MidiexError::from)?)
+}
+
+// Converts UMP words to the MIDI 1.0 messages they carry, see ump::to_midi1
+#[rustler::nif]
+fn ump_to_midi1<'a>(env: Env<'a>, words: Vec<u32>) -> Result<Vec<Binary<'a>>, Error> {
+    let messages = ump::to_midi1(&words).map_err(MidiexError::from)?;
+    Ok(messages
+        .iter()
+        .map(|message| to_binary(env, message))
+        .collect::<Result<_, _>>()?)
+}
+
+#[rustler::nif]
+fn ump_packets(words: Vec<u32>) -> Result<Vec<Vec<u32>>, Error> {
+    let packets = ump::packets(&words).map_err(MidiexError::from)?;
+    Ok(packets.into_iter().map(|packet| packet.to_vec()).collect())
+}
+
+#[rustler::nif]
+fn ump_scale_up(value: u32, src_bits: u32, dst_bits: u32) -> Result<u32, Error> {
+    check_scale_bits(src_bits, dst_bits)?;
+    Ok(ump::scale_up(value, src_bits, dst_bits))
+}
+
+#[rustler::nif]
+fn ump_scale_down(value: u32, src_bits: u32, dst_bits: u32) -> Result<u32, Error> {
+    check_scale_bits(dst_bits, src_bits)?;
+    Ok(ump::scale_down(value, src_bits, dst_bits))
+}
+
+// Checks values can be scaled between narrow_bits and wide_bits
+fn check_scale_bits(narrow_bits: u32, wide_bits: u32) -> Result<(), MidiexError> {
+    match (1..=32).contains(&narrow_bits) && (narrow_bits..=32).contains(&wide_bits) {
+        true => Ok(()),
+        false => Err(MidiexError::InvalidArgument(format!(
+            "can't scale between {} and {} bits",
+            narrow_bits, wide_bits
+        ))),
+    }
+}
+
+// =================
+// MIDI Capability Inquiry
+// =================
+
+#[rustler::nif]
+fn ci_parse(sysex: Binary) -> Result<ci::Message, Error> {
+    Ok(ci::parse(&sysex).map_err(MidiexError::from)?)
+}
+
+#[rustler::nif]
+fn ci_build<'a>(env: Env<'a>, message: ci::Message) -> Result<Binary<'a>, Error> {
+    let bytes = ci::build(&message).map_err(MidiexError::from)?;
+    Ok(to_binary(env, &bytes)?)
+}
+
+#[rustler::nif]
+fn ci_new_muid() -> u32 {
+    ci::new_muid()
+}
+
+#[derive(NifStruct)]
+#[module = "Midiex.CI.Responder"]
+pub struct CiResponder {
+    responder_ref: ResourceArc<CiResponderRef>,
+    id: u64,
+    muid: u32,
+}
+
+// The responder is shared with the input callback, which answers the messages it receives on the output connection
+pub struct CiResponderRef {
+    conn: InConnRef,
+    out_conn: SharedOutConn,
+    responder: Arc<Mutex<ci::Responder>>,
+}
+
+// The opts of Midiex.CI.start_responder/3, with every key present
+#[derive(NifMap)]
+pub struct CiResponderOptions<'a> {
+    muid: u32,
+    manufacturer: Binary<'a>,
+    family: u16,
+    model: u16,
+    version: Binary<'a>,
+    max_sysex_size: u32,
+    product_instance_id: Binary<'a>,
+    profiles: Vec<(Binary<'a>, bool)>,
+    properties: Vec<(String, Binary<'a>)>,
+    auto_reply: bool,
+}
+
+impl<'a> TryFrom<CiResponderOptions<'a>> for ci::ResponderOptions {
+    type Error = MidiexError;
+
+    fn try_from(options: CiResponderOptions<'a>) -> Result<Self, Self::Error> {
+        Ok(ci::ResponderOptions {
+            muid: options.muid,
+            identity: ci::Identity {
+                manufacturer: fixed_bytes(&options.manufacturer)?,
+                family: options.family,
+                model: options.model,
+                version: fixed_bytes(&options.version)?,
+            },
+            max_sysex_size: options.max_sysex_size,
+            product_instance_id: options.product_instance_id.to_vec(),
+            profiles: options
+                .profiles
+                .iter()
+                .map(|(profile, enabled)| Ok((fixed_bytes(profile)?, *enabled)))
+                .collect::<Result<_, MidiexError>>()?,
+            properties: options
+                .properties
+                .into_iter()
+                .map(|(resource, data)| (resource, data.to_vec()))
+                .collect(),
+            auto_reply: options.auto_reply,
+        })
+    }
+}
+
+// Listens for MIDI-CI messages on the input, answering them on the output connection if auto_reply is set. The
+// calling process is sent {:midiex_ci, id, event} for each message addressed to the responder's MUID (or broadcast),
+// and when another device turns a profile on or off or sets a property, see ci::Event.
+#[rustler::nif]
+fn ci_start_responder(
+    env: Env,
+    midi_out_conn: OutConn,
+    input: InputPort,
+    options: CiResponderOptions,
+) -> Result<CiResponder, Error> {
+    let options = ci::ResponderOptions::try_from(options)?;
+    let muid = options.muid;
+    if muid >= ci::BROADCAST_MUID {
+        return Err(MidiexError::InvalidArgument(format!(
+            "the MUID must be below 0x0FFFFFFF, not {:#X}",
+            muid
+        ))
+        .into());
+    }
+
+    let id = {
+        let mut counter = GLOBAL_CI_RESPONDER_COUNTER
+            .lock()
+            .map_err(MidiexError::from)?;
+        *counter += 1;
+        *counter
+    };
+
+    let responder = Arc::new(Mutex::new(ci::Responder::new(options)));
+    let handling = responder.clone();
+    let out_conn = midi_out_conn.conn_ref.conn.clone();
+    let replying_conn = out_conn.clone();
+
+    let pid = env.pid();
+    let event_pid = pid;
+    let mut owned_env = OwnedEnv::new();
+
+    // Messages which aren't MIDI-CI, or can't be parsed, are dropped
+    let conn = connect_to_input(
+        pid,
+        input,
+        Ignore::None,
+        SysexOptions::default(),
+        move |_stamp, message| {
+            if !ci::is_ci(message) {
+                return;
+            }
+            let message = match ci::parse(message) {
+                Ok(message) => message,
+                Err(_) => return,
+            };
+            let (replies, events) = match handling.lock() {
+                Ok(mut responder) => responder.handle(message),
+                Err(_) => return,
+            };
+
+            for reply in replies {
+                if let Err(error) = send_ci(&replying_conn, &reply) {
+                    owned_env.send_and_clear(&event_pid, |the_env| {
+                        (atoms::midiex_ci(), id, (atoms::error(), error)).encode(the_env)
+                    });
+                }
+            }
+            for event in events {
+                owned_env.send_and_clear(&event_pid, |the_env| {
+                    (atoms::midiex_ci(), id, event).encode(the_env)
+                });
+            }
+        },
+    )?;
+
+    Ok(CiResponder {
+        responder_ref: ResourceArc::new(CiResponderRef {
+            conn,
+            out_conn,
+            responder,
+        }),
+        id,
+        muid,
+    })
+}
+
+// Sends a message from the responder, its source being the responder's MUID
+#[rustler::nif]
+fn ci_send(responder: CiResponder, message: ci::Message) -> Result<CiResponder, Error> {
+    let message = ci::Message {
+        source: responder.muid,
+        ..message
+    };
+    send_ci(&responder.responder_ref.out_conn, &message)?;
+    Ok(responder)
+}
+
+// Broadcasts a Discovery message, so the devices which reply are added to the responder's peers
+#[rustler::nif]
+fn ci_discover(responder: CiResponder) -> Result<CiResponder, Error> {
+    let message = responder
+        .responder_ref
+        .responder
+        .lock()
+        .map_err(MidiexError::from)?
+        .discovery();
+    send_ci(&responder.responder_ref.out_conn, &message)?;
+    Ok(responder)
+}
+
+#[rustler::nif]
+fn ci_peers(responder: CiResponder) -> Result<Vec<ci::Peer>, Error> {
+    Ok(responder
+        .responder_ref
+        .responder
+        .lock()
+        .map_err(MidiexError::from)?
+        .peers())
+}
+
+#[rustler::nif]
+fn ci_set_property(
+    responder: CiResponder,
+    resource: String,
+    data: Binary,
+) -> Result<CiResponder, Error> {
+    responder
+        .responder_ref
+        .responder
+        .lock()
+        .map_err(MidiexError::from)?
+        .set_property(resource, data.to_vec());
+    Ok(responder)
+}
+
+// Turns one of the responder's profiles on or off, broadcasting a Profile Enabled or Disabled Report
+#[rustler::nif]
+fn ci_set_profile(
+    responder: CiResponder,
+    profile: Binary,
+    enabled: bool,
+) -> Result<CiResponder, Error> {
+    let report = responder
+        .responder_ref
+        .responder
+        .lock()
+        .map_err(MidiexError::from)?
+        .set_profile(fixed_bytes(&profile)?, enabled)
+        .ok_or_else(|| {
+            MidiexError::InvalidArgument(format!(
+                "the responder has no profile {:?}",
+                profile.as_slice()
+            ))
+        })?;
+    send_ci(&responder.responder_ref.out_conn, &report)?;
+    Ok(responder)
+}
+
+// Broadcasts an Invalidate MUID message for the responder, then closes its input connection
+#[rustler::nif]
+fn ci_stop_responder(responder: CiResponder) -> Result<Atom, Error> {
+    let message = responder
+        .responder_ref
+        .responder
+        .lock()
+        .map_err(MidiexError::from)?
+        .invalidate();
+    let sent = send_ci(&responder.responder_ref.out_conn, &message);
 
-    let conn_out = midi_output
-        .create_virtual(&name)
-        .expect("Midi MidiOutputConnection");
+    responder.responder_ref.conn.close();
+    sent?;
+
+    Ok(atoms::ok())
+}
 
-    // Even though we've created an output port, beacause it's a virtual port it is listed as an 'input' when querying the OS for available devices.
-    let port_index = midi_input.port_count();
+fn send_ci(out_conn: &SharedOutConn, message: &ci::Message) -> Result<(), MidiexError> {
+    let bytes = ci::build(message)?;
 
-    // Just in case added port_ref back into OutConn
-    // let new_port: MidiInputPort = midi_input.ports().into_iter().rev().next().unwrap();
+    out_conn
+        .lock()?
+        .as_mut()
+        .ok_or(MidiexError::ConnectionClosed)?
+        .send(&bytes)?;
 
-    return Ok(OutConn {
-        conn_ref: ResourceArc::new(OutConnRef::new(conn_out)),
-        name: name,
-        port_num: port_index - 1,
-        // Just in case port_ref is added back in:
-        // midi_port: MidiPort{
-        //     direction: atoms::output(),
-        //     name: name,
-        //     num: port_index,
-        //     port_ref: ResourceArc::new(FlexiPort::new(MidiexMidiPortRef::Output(MidiOutputPort::clone(&new_port))))
-        // }
-    });
+    Ok(())
 }
 
-#[cfg(target_os = "windows")]
-#[rustler::nif]
-fn create_virtual_output_conn(_name: String) -> Result<OutConn, Error> {
-    Err(Error::RaiseTerm(Box::new(
-        "Virtual outputs are not supported on Windows.".to_string(),
-    )))
+fn fixed_bytes<const N: usize>(bytes: &[u8]) -> Result<[u8; N], MidiexError> {
+    bytes.try_into().map_err(|_| {
+        MidiexError::InvalidArgument(format!("expected {} bytes, not {}", N, bytes.len()))
+    })
 }
 
-// ------------------------
-// SENDING MIDI MESSAGES
-// ------------------------
+fn make_map<'a>(env: Env<'a>, fields: &[(Atom, Term<'a>)]) -> Term<'a> {
+    fields
+        .iter()
+        .fold(rustler::types::map::map_new(env), |map, (key, value)| {
+            map.map_put(key.encode(env), *value).unwrap_or(map)
+        })
+}
 
-#[rustler::nif(schedule = "DirtyCpu")]
-fn send_msg(midi_out_conn: OutConn, message: Binary) -> Result<OutConn, Error> {
-    {
-        let mut binding = midi_out_conn.conn_ref.0.lock().unwrap();
-        let out_conn = binding.deref_mut();
+impl Encoder for ci::Message {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let (message_type, body) = encode_ci_body(env, &self.body);
+
+        make_map(
+            env,
+            &[
+                (atoms::struct_(), atoms::ci_message().encode(env)),
+                (atoms::type_(), message_type.encode(env)),
+                (atoms::version(), self.version.encode(env)),
+                (atoms::device_id(), self.device_id.encode(env)),
+                (atoms::source(), self.source.encode(env)),
+                (atoms::destination(), self.destination.encode(env)),
+                (atoms::body(), make_map(env, &body)),
+            ],
+        )
+    }
+}
 
-        let _res = match out_conn {
-                Some(conn) => conn.send(&message),
-                None => return Err(Error::RaiseTerm(Box::new(
-                    "No output connection available to send message to. Connection may have been closed.".to_string(),
-                ))),
-            };
+// The body of a message is a map, whose keys depend on the message's type
+fn encode_ci_body<'a>(env: Env<'a>, body: &ci::Body) -> (Atom, Vec<(Atom, Term<'a>)>) {
+    use ci::Body::*;
+
+    let binary = |bytes: &[u8]| encode_binary(env, bytes).encode(env);
+    let binaries = |list: &[[u8; 5]]| {
+        list.iter()
+            .map(|bytes| encode_binary(env, bytes))
+            .collect::<Vec<Binary>>()
+            .encode(env)
+    };
+    let discovery = |discovery: &ci::Discovery| {
+        vec![
+            (
+                atoms::manufacturer(),
+                binary(&discovery.identity.manufacturer),
+            ),
+            (atoms::family(), discovery.identity.family.encode(env)),
+            (atoms::model(), discovery.identity.model.encode(env)),
+            (atoms::version(), binary(&discovery.identity.version)),
+            (atoms::categories(), discovery.categories.encode(env)),
+            (
+                atoms::max_sysex_size(),
+                discovery.max_sysex_size.encode(env),
+            ),
+            (atoms::output_path(), discovery.output_path.encode(env)),
+        ]
+    };
+    let status = |status: &ci::Status| {
+        vec![
+            (atoms::original(), status.original.encode(env)),
+            (atoms::status_code(), status.status_code.encode(env)),
+            (atoms::status_data(), status.status_data.encode(env)),
+            (atoms::details(), binary(&status.details)),
+            (atoms::text(), binary(&status.text)),
+        ]
+    };
+    let chunk = |chunk: &ci::PropertyChunk| {
+        vec![
+            (atoms::request_id(), chunk.request_id.encode(env)),
+            (atoms::header(), binary(&chunk.header)),
+            (atoms::chunks(), chunk.chunks.encode(env)),
+            (atoms::chunk(), chunk.chunk.encode(env)),
+            (atoms::data(), binary(&chunk.data)),
+        ]
+    };
+    let profile = |profile: &ci::ProfileId| (atoms::profile(), binary(profile));
+    let channels = |channels: &u16| (atoms::channels(), channels.encode(env));
+    let authority = |authority: &u8| (atoms::authority(), authority.encode(env));
+    let capabilities = |max_requests: &u8, major: &u8, minor: &u8| {
+        vec![
+            (atoms::max_requests(), max_requests.encode(env)),
+            (atoms::major(), major.encode(env)),
+            (atoms::minor(), minor.encode(env)),
+        ]
+    };
+
+    match body {
+        Discovery(fields) => (atoms::discovery(), discovery(fields)),
+        DiscoveryReply(fields, function_block) => {
+            let mut fields = discovery(fields);
+            fields.push((atoms::function_block(), function_block.encode(env)));
+            (atoms::discovery_reply(), fields)
+        }
+        EndpointInquiry(code) => (
+            atoms::endpoint_inquiry(),
+            vec![(atoms::status(), code.encode(env))],
+        ),
+        EndpointReply(code, data) => (
+            atoms::endpoint_reply(),
+            vec![
+                (atoms::status(), code.encode(env)),
+                (atoms::data(), binary(data)),
+            ],
+        ),
+        InvalidateMuid(target) => (
+            atoms::invalidate_muid(),
+            vec![(atoms::target(), target.encode(env))],
+        ),
+        Ack(fields) => (atoms::ack(), status(fields)),
+        Nak(fields) => (atoms::nak(), status(fields)),
+        ProtocolNegotiation(level, protocols) => (
+            atoms::protocol_negotiation(),
+            vec![authority(level), (atoms::protocols(), binaries(protocols))],
+        ),
+        ProtocolNegotiationReply(level, protocols) => (
+            atoms::protocol_negotiation_reply(),
+            vec![authority(level), (atoms::protocols(), binaries(protocols))],
+        ),
+        SetProtocol(level, protocol) => (
+            atoms::set_protocol(),
+            vec![authority(level), (atoms::protocol(), binary(protocol))],
+        ),
+        TestProtocol(level, data) => (
+            atoms::test_protocol(),
+            vec![authority(level), (atoms::data(), binary(data))],
+        ),
+        TestProtocolReply(level, data) => (
+            atoms::test_protocol_reply(),
+            vec![authority(level), (atoms::data(), binary(data))],
+        ),
+        ProtocolConfirmed(level) => (atoms::protocol_confirmed(), vec![authority(level)]),
+        ProfileInquiry => (atoms::profile_inquiry(), Vec::new()),
+        ProfileInquiryReply(enabled, disabled) => (
+            atoms::profile_inquiry_reply(),
+            vec![
+                (atoms::enabled(), binaries(enabled)),
+                (atoms::disabled(), binaries(disabled)),
+            ],
+        ),
+        SetProfileOn(id, count) => (atoms::set_profile_on(), vec![profile(id), channels(count)]),
+        SetProfileOff(id) => (atoms::set_profile_off(), vec![profile(id)]),
+        ProfileEnabled(id, count) => (atoms::profile_enabled(), vec![profile(id), channels(count)]),
+        ProfileDisabled(id, count) => (
+            atoms::profile_disabled(),
+            vec![profile(id), channels(count)],
+        ),
+        ProfileAdded(id) => (atoms::profile_added(), vec![profile(id)]),
+        ProfileRemoved(id) => (atoms::profile_removed(), vec![profile(id)]),
+        ProfileSpecificData(id, data) => (
+            atoms::profile_specific_data(),
+            vec![profile(id), (atoms::data(), binary(data))],
+        ),
+        PropertyCapabilities(max_requests, major, minor) => (
+            atoms::property_capabilities(),
+            capabilities(max_requests, major, minor),
+        ),
+        PropertyCapabilitiesReply(max_requests, major, minor) => (
+            atoms::property_capabilities_reply(),
+            capabilities(max_requests, major, minor),
+        ),
+        GetProperty(fields) => (atoms::get_property(), chunk(fields)),
+        GetPropertyReply(fields) => (atoms::get_property_reply(), chunk(fields)),
+        SetProperty(fields) => (atoms::set_property(), chunk(fields)),
+        SetPropertyReply(fields) => (atoms::set_property_reply(), chunk(fields)),
+        Subscription(fields) => (atoms::subscription(), chunk(fields)),
+        SubscriptionReply(fields) => (atoms::subscription_reply(), chunk(fields)),
+        PropertyNotify(fields) => (atoms::property_notify(), chunk(fields)),
+        Other(sub_id, data) => (
+            atoms::other(),
+            vec![
+                (atoms::sub_id(), sub_id.encode(env)),
+                (atoms::data(), binary(data)),
+            ],
+        ),
     }
+}
 
-    Ok(midi_out_conn)
+impl<'a> Decoder<'a> for ci::Message {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let message_type: Atom = ci_field(term, atoms::type_())?.decode()?;
+
+        Ok(ci::Message {
+            version: ci_field(term, atoms::version())?.decode()?,
+            device_id: ci_field(term, atoms::device_id())?.decode()?,
+            source: ci_field(term, atoms::source())?.decode()?,
+            destination: ci_field(term, atoms::destination())?.decode()?,
+            body: decode_ci_body(message_type, ci_field(term, atoms::body())?)?,
+        })
+    }
 }
 
-// =================
-// MIDI Message
-// =================
-#[derive(NifStruct)]
-#[module = "Midiex.MidiMessage"]
-pub struct MidiMessage {
-    port: MidiPort,
-    data: Vec<u8>,
-    timestamp: u64,
+fn ci_field<'a>(map: Term<'a>, key: Atom) -> NifResult<Term<'a>> {
+    map.map_get(key.encode(map.get_env()))
+}
+
+// Keys left out of a body are taken to be 0, or empty
+fn decode_ci_body(message_type: Atom, body: Term) -> NifResult<ci::Body> {
+    use ci::Body::*;
+
+    let int = |key: Atom| -> NifResult<u32> {
+        match ci_field(body, key) {
+            Ok(value) => value.decode(),
+            Err(_) => Ok(0),
+        }
+    };
+    let byte = |key: Atom| -> NifResult<u8> { u8::try_from(int(key)?).map_err(|_| Error::BadArg) };
+    let u14 = |key: Atom| -> NifResult<u16> { u16::try_from(int(key)?).map_err(|_| Error::BadArg) };
+    let bytes = |key: Atom| -> NifResult<Vec<u8>> {
+        match ci_field(body, key) {
+            Ok(value) => Ok(value.decode::<Binary>()?.to_vec()),
+            Err(_) => Ok(Vec::new()),
+        }
+    };
+    let ids = |key: Atom| -> NifResult<Vec<[u8; 5]>> {
+        let list: Vec<Binary> = match ci_field(body, key) {
+            Ok(value) => value.decode()?,
+            Err(_) => Vec::new(),
+        };
+        Ok(list
+            .iter()
+            .map(|id| fixed_bytes(id.as_slice()))
+            .collect::<Result<_, MidiexError>>()?)
+    };
+    let id = |key: Atom| -> NifResult<[u8; 5]> { Ok(fixed_bytes(&bytes(key)?)?) };
+    let discovery = || -> NifResult<ci::Discovery> {
+        Ok(ci::Discovery {
+            identity: ci::Identity {
+                manufacturer: fixed_bytes(&bytes(atoms::manufacturer())?)?,
+                family: u14(atoms::family())?,
+                model: u14(atoms::model())?,
+                version: fixed_bytes(&bytes(atoms::version())?)?,
+            },
+            categories: byte(atoms::categories())?,
+            max_sysex_size: int(atoms::max_sysex_size())?,
+            output_path: byte(atoms::output_path())?,
+        })
+    };
+    let status = || -> NifResult<ci::Status> {
+        let details = bytes(atoms::details())?;
+        Ok(ci::Status {
+            original: byte(atoms::original())?,
+            status_code: byte(atoms::status_code())?,
+            status_data: byte(atoms::status_data())?,
+            details: match details.is_empty() {
+                true => [0; 5],
+                false => fixed_bytes(&details)?,
+            },
+            text: bytes(atoms::text())?,
+        })
+    };
+    let chunk = || -> NifResult<ci::PropertyChunk> {
+        Ok(ci::PropertyChunk {
+            request_id: byte(atoms::request_id())?,
+            header: bytes(atoms::header())?,
+            chunks: u14(atoms::chunks())?,
+            chunk: u14(atoms::chunk())?,
+            data: bytes(atoms::data())?,
+        })
+    };
+    let capabilities = || -> NifResult<(u8, u8, u8)> {
+        Ok((
+            byte(atoms::max_requests())?,
+            byte(atoms::major())?,
+            byte(atoms::minor())?,
+        ))
+    };
+    let authority = || byte(atoms::authority());
+
+    let body = match message_type {
+        tag if tag == atoms::discovery() => Discovery(discovery()?),
+        tag if tag == atoms::discovery_reply() => {
+            DiscoveryReply(discovery()?, byte(atoms::function_block())?)
+        }
+        tag if tag == atoms::endpoint_inquiry() => EndpointInquiry(byte(atoms::status())?),
+        tag if tag == atoms::endpoint_reply() => {
+            EndpointReply(byte(atoms::status())?, bytes(atoms::data())?)
+        }
+        tag if tag == atoms::invalidate_muid() => InvalidateMuid(int(atoms::target())?),
+        tag if tag == atoms::ack() => Ack(status()?),
+        tag if tag == atoms::nak() => Nak(status()?),
+        tag if tag == atoms::protocol_negotiation() => {
+            ProtocolNegotiation(authority()?, ids(atoms::protocols())?)
+        }
+        tag if tag == atoms::protocol_negotiation_reply() => {
+            ProtocolNegotiationReply(authority()?, ids(atoms::protocols())?)
+        }
+        tag if tag == atoms::set_protocol() => SetProtocol(authority()?, id(atoms::protocol())?),
+        tag if tag == atoms::test_protocol() => TestProtocol(authority()?, bytes(atoms::data())?),
+        tag if tag == atoms::test_protocol_reply() => {
+            TestProtocolReply(authority()?, bytes(atoms::data())?)
+        }
+        tag if tag == atoms::protocol_confirmed() => ProtocolConfirmed(authority()?),
+        tag if tag == atoms::profile_inquiry() => ProfileInquiry,
+        tag if tag == atoms::profile_inquiry_reply() => {
+            ProfileInquiryReply(ids(atoms::enabled())?, ids(atoms::disabled())?)
+        }
+        tag if tag == atoms::set_profile_on() => {
+            SetProfileOn(id(atoms::profile())?, u14(atoms::channels())?)
+        }
+        tag if tag == atoms::set_profile_off() => SetProfileOff(id(atoms::profile())?),
+        tag if tag == atoms::profile_enabled() => {
+            ProfileEnabled(id(atoms::profile())?, u14(atoms::channels())?)
+        }
+        tag if tag == atoms::profile_disabled() => {
+            ProfileDisabled(id(atoms::profile())?, u14(atoms::channels())?)
+        }
+        tag if tag == atoms::profile_added() => ProfileAdded(id(atoms::profile())?),
+        tag if tag == atoms::profile_removed() => ProfileRemoved(id(atoms::profile())?),
+        tag if tag == atoms::profile_specific_data() => {
+            ProfileSpecificData(id(atoms::profile())?, bytes(atoms::data())?)
+        }
+        tag if tag == atoms::property_capabilities() => {
+            let (max_requests, major, minor) = capabilities()?;
+            PropertyCapabilities(max_requests, major, minor)
+        }
+        tag if tag == atoms::property_capabilities_reply() => {
+            let (max_requests, major, minor) = capabilities()?;
+            PropertyCapabilitiesReply(max_requests, major, minor)
+        }
+        tag if tag == atoms::get_property() => GetProperty(chunk()?),
+        tag if tag == atoms::get_property_reply() => GetPropertyReply(chunk()?),
+        tag if tag == atoms::set_property() => SetProperty(chunk()?),
+        tag if tag == atoms::set_property_reply() => SetPropertyReply(chunk()?),
+        tag if tag == atoms::subscription() => Subscription(chunk()?),
+        tag if tag == atoms::subscription_reply() => SubscriptionReply(chunk()?),
+        tag if tag == atoms::property_notify() => PropertyNotify(chunk()?),
+        tag if tag == atoms::other() => Other(byte(atoms::sub_id())?, bytes(atoms::data())?),
+        _ => return Err(Error::BadArg),
+    };
+
+    Ok(body)
+}
+
+impl Encoder for ci::Event {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            ci::Event::Received(message) => message.encode(env),
+            ci::Event::ProfileChanged(profile, enabled) => {
+                (atoms::profile(), encode_binary(env, profile), enabled).encode(env)
+            }
+            ci::Event::PropertySet(resource, data) => {
+                (atoms::property(), resource, encode_binary(env, data)).encode(env)
+            }
+        }
+    }
+}
+
+impl Encoder for ci::Peer {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        make_map(
+            env,
+            &[
+                (atoms::muid(), self.muid.encode(env)),
+                (
+                    atoms::manufacturer(),
+                    encode_binary(env, &self.identity.manufacturer).encode(env),
+                ),
+                (atoms::family(), self.identity.family.encode(env)),
+                (atoms::model(), self.identity.model.encode(env)),
+                (
+                    atoms::version(),
+                    encode_binary(env, &self.identity.version).encode(env),
+                ),
+                (atoms::max_sysex_size(), self.max_sysex_size.encode(env)),
+            ],
+        )
+    }
 }
 
 // =================
@@ -526,6 +3086,58 @@ impl MidiNotification {
     }
 }
 
+#[cfg(target_os = "linux")]
+impl MidiNotification {
+    // A client is reported as a device. A port is reported once for each direction it supports, as MacOS does with
+    // separate source and destination objects. The native_id of a port packs its ALSA client:port address.
+    fn from_announcement(announcement: alsa_seq::Announcement) -> Vec<Self> {
+        use alsa::seq::PortCap;
+        use alsa_seq::Announcement::*;
+
+        let (notification_type, seq_port) = match announcement {
+            ClientAdded(client, name) => return vec![Self::for_client(atoms::added(), client, name)],
+            ClientRemoved(client, name) => {
+                return vec![Self::for_client(atoms::removed(), client, name)]
+            }
+            PortAdded(seq_port) => (atoms::added(), seq_port),
+            PortRemoved(seq_port) => (atoms::removed(), seq_port),
+        };
+
+        let mut directions = Vec::new();
+        if seq_port.capability.contains(PortCap::READ) {
+            directions.push(atoms::input());
+        }
+        if seq_port.capability.contains(PortCap::WRITE) {
+            directions.push(atoms::output());
+        }
+
+        directions
+            .into_iter()
+            .map(|direction| Self {
+                notification_type,
+                parent_name: seq_port.client_name.clone(),
+                parent_id: seq_port.addr.client as u32,
+                parent_type: atoms::device(),
+                name: seq_port.name.clone(),
+                native_id: alsa_seq::addr_to_native_id(seq_port.addr),
+                direction,
+            })
+            .collect()
+    }
+
+    fn for_client(notification_type: Atom, client: i32, name: String) -> Self {
+        Self {
+            notification_type,
+            parent_name: "".to_string(),
+            parent_id: 0,
+            parent_type: atoms::other(),
+            name,
+            native_id: client as u32,
+            direction: atoms::device(),
+        }
+    }
+}
+
 #[cfg(all(target_os = "macos"))]
 fn midi_obj_type_to_atom(object_type: ObjectType) -> Atom {
     match object_type {
@@ -545,23 +3157,180 @@ fn midi_obj_type_to_atom(object_type: ObjectType) -> Atom {
 // MIDI Connection
 // ===============
 
-#[derive(NifStruct)]
-#[module = "Midiex.OutConn"]
 pub struct OutConn {
     conn_ref: ResourceArc<OutConnRef>,
-    // midi_port: MidiPort,
+    midi_port: MidiPort,
     name: String,
     port_num: usize,
+    // Whether it's a virtual output of ours, for other applications to connect to, rather than a connection to a port
+    is_virtual: bool,
+}
+
+// Written out rather than derived, as virtual, the key in Elixir, is reserved in Rust
+impl Encoder for OutConn {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        make_map(
+            env,
+            &[
+                (atoms::struct_(), atoms::out_conn().encode(env)),
+                (atoms::conn_ref(), self.conn_ref.encode(env)),
+                (atoms::midi_port(), self.midi_port.encode(env)),
+                (atoms::name(), self.name.encode(env)),
+                (atoms::port_num(), self.port_num.encode(env)),
+                (atoms::virtual_(), self.is_virtual.encode(env)),
+            ],
+        )
+    }
+}
+
+impl<'a> Decoder<'a> for OutConn {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        let field = |key: Atom| term.map_get(key.encode(term.get_env()));
+
+        if field(atoms::struct_())?.decode::<Atom>()? != atoms::out_conn() {
+            return Err(Error::BadArg);
+        }
+
+        Ok(OutConn {
+            conn_ref: field(atoms::conn_ref())?.decode()?,
+            midi_port: field(atoms::midi_port())?.decode()?,
+            name: field(atoms::name())?.decode()?,
+            port_num: field(atoms::port_num())?.decode()?,
+            is_virtual: field(atoms::virtual_())?.decode()?,
+        })
+    }
 }
 
 // WRAP IN AN OPTION AS WELL SO THE CONN CAN BE DESTROYED LATER
 // Use of Option mean ownership of the connection can be taken with .take() and then .closed() can be called.
+// The connection is shared (Arc) with the connection's scheduler thread, which is only started the first time a
+// message is scheduled with send_at.
 
-pub struct OutConnRef(pub Mutex<Option<MidiOutputConnection>>);
+pub struct OutConnRef {
+    pub conn: SharedOutConn,
+    pub scheduler: Mutex<Option<Scheduler>>,
+}
 
 impl OutConnRef {
-    pub fn new(data: MidiOutputConnection) -> Self {
-        Self(Mutex::new(Some(data)))
+    pub fn new(data: Connection) -> Self {
+        Self {
+            conn: Arc::new(Mutex::new(Some(OutPort::new(data)))),
+            scheduler: Mutex::new(None),
+        }
+    }
+
+    pub fn schedule(&self, msgs: Vec<(Instant, Vec<u8>)>) -> Result<(), MidiexError> {
+        if self.conn.lock()?.is_none() {
+            return Err(MidiexError::ConnectionClosed);
+        }
+
+        self.scheduler
+            .lock()?
+            .get_or_insert_with(|| Scheduler::spawn(self.conn.clone()))
+            .schedule(msgs);
+
+        Ok(())
+    }
+
+    // Calls f with the scheduler, if one has been started
+    pub fn with_scheduler<T>(&self, f: impl FnOnce(&Scheduler) -> T) -> Result<Option<T>, MidiexError> {
+        Ok(self.scheduler.lock()?.as_ref().map(f))
+    }
+}
+
+#[derive(NifStruct)]
+#[module = "Midiex.InConn"]
+pub struct InConn {
+    conn_ref: ResourceArc<InConnRef>,
+    name: String,
+    port_num: usize,
+}
+
+// An input connection from the MIDI backend, or to a loopback device, see loopback.rs
+pub enum InputConnection {
+    Midir(MidiInputConnection<()>),
+    Loopback(loopback::Listener),
+    // A subscription to a virtual input, which has a connection of its own, see virtual_input.rs
+    Virtual(virtual_input::Subscription),
+    // A virtual device's input port, see alsa_seq::create_virtual_device
+    #[cfg(target_os = "linux")]
+    AlsaVirtual(alsa_seq::VirtualInput),
+}
+
+impl InputConnection {
+    fn close(self) {
+        match self {
+            InputConnection::Midir(conn) => {
+                conn.close();
+            }
+            InputConnection::Loopback(listener) => listener.close(),
+            InputConnection::Virtual(subscription) => subscription.close(),
+            #[cfg(target_os = "linux")]
+            InputConnection::AlsaVirtual(input) => input.close(),
+        }
+    }
+}
+
+// midir's MidiInputConnection is created on, and stays on, its own worker thread. The InConnRef holds the sending half
+// of a channel to that thread: closing the connection (or the resource being garbage collected, which drops the sender)
+// wakes the worker, which then closes the MidiInputConnection.
+
+pub struct InConnRef(pub Mutex<Option<InConnWorker>>);
+
+pub struct InConnWorker {
+    close_tx: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl InConnRef {
+    // Runs open_conn on a new worker thread, returning once the connection has been made (or has failed). If it fails,
+    // pid is also sent {:error, {kind, message}}, so a process listening for messages hears about it.
+    pub fn spawn<F>(pid: LocalPid, open_conn: F) -> Result<Self, MidiexError>
+    where
+        F: FnOnce() -> Result<InputConnection, MidiexError> + Send + 'static,
+    {
+        let (ready_tx, ready_rx) = mpsc::sync_channel::<Result<(), MidiexError>>(1);
+        let (close_tx, close_rx) = mpsc::channel::<()>();
+
+        let handle = std::thread::spawn(move || {
+            let conn_in = match open_conn() {
+                Ok(conn_in) => conn_in,
+                Err(error) => {
+                    let mut owned_env = OwnedEnv::new();
+                    owned_env.send_and_clear(&pid, |the_env| (atoms::error(), &error).encode(the_env));
+                    let _ = ready_tx.send(Err(error));
+                    return;
+                }
+            };
+            let _ = ready_tx.send(Ok(()));
+
+            // Blocks until told to close, or until the sender has been dropped
+            let _ = close_rx.recv();
+
+            conn_in.close();
+        });
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(Self(Mutex::new(Some(InConnWorker { close_tx, handle })))),
+            Ok(Err(error)) => Err(error),
+            Err(_) => Err(MidiexError::Other(
+                "Midi input connection thread exited unexpectedly.".to_string(),
+            )),
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.0.lock().unwrap_or_else(|e| e.into_inner()).is_some()
+    }
+
+    // Closes the connection, returning once the worker thread has finished with it
+    pub fn close(&self) {
+        let worker = self.0.lock().unwrap_or_else(|e| e.into_inner()).take();
+
+        if let Some(worker) = worker {
+            let _ = worker.close_tx.send(());
+            let _ = worker.handle.join();
+        }
     }
 }
 
@@ -571,6 +3340,9 @@ impl OutConnRef {
 pub enum MidiexMidiPortRef {
     Input(MidiInputPort),
     Output(MidiOutputPort),
+    // The id of a loopback device, see loopback.rs
+    LoopbackInput(u32),
+    LoopbackOutput(u32),
 }
 
 pub struct FlexiPort(pub MidiexMidiPortRef);
@@ -587,12 +3359,47 @@ pub struct MidiPort {
     direction: Atom,
     name: String,
     num: usize,
+    id: String,
+    native_id: NativeId,
     port_ref: ResourceArc<FlexiPort>,
 }
 
+// Unlike num, the id doesn't change as other ports come and go, so a port is still equal to itself after the ports
+// have been listed again
 impl PartialEq for MidiPort {
     fn eq(&self, other: &Self) -> bool {
-        (self.name == other.name) && (self.direction == other.direction) && (self.num == other.num)
+        (self.direction == other.direction) && (self.id == other.id)
+    }
+}
+
+// The backend's own id for a port: the ALSA sequencer client and port on Linux, or the endpoint's unique id on
+// macOS. Encoded as {client, port}, an integer, or nil if the backend has no id for its ports (e.g. on Windows).
+#[derive(Clone, Debug, PartialEq)]
+pub enum NativeId {
+    Alsa(i32, i32),
+    CoreMidi(i64),
+    None,
+}
+
+impl Encoder for NativeId {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match self {
+            NativeId::Alsa(client, port) => (client, port).encode(env),
+            NativeId::CoreMidi(unique_id) => unique_id.encode(env),
+            NativeId::None => rustler::types::atom::nil().encode(env),
+        }
+    }
+}
+
+impl<'a> Decoder<'a> for NativeId {
+    fn decode(term: Term<'a>) -> NifResult<Self> {
+        if let Ok((client, port)) = term.decode::<(i32, i32)>() {
+            return Ok(NativeId::Alsa(client, port));
+        }
+        if let Ok(unique_id) = term.decode::<i64>() {
+            return Ok(NativeId::CoreMidi(unique_id));
+        }
+        Ok(NativeId::None)
     }
 }
 
@@ -602,6 +3409,14 @@ pub struct VirtualMidiPort {
     direction: Atom,
     name: String,
     num: usize,
+    port_ref: ResourceArc<VirtualInputRef>,
+}
+
+// The virtual input's own connection, which owns the port, and the dispatcher passing the messages it receives on
+// to the port's subscribers
+pub struct VirtualInputRef {
+    conn: InConnRef,
+    dispatcher: Arc<virtual_input::Dispatcher>,
 }
 
 impl PartialEq for VirtualMidiPort {
@@ -634,21 +3449,320 @@ impl MidiexMidiOutputRef {
 }
 
 // ------------------------
-// LIST PORTS
+// LOOPBACK BACKEND
+// ------------------------
+
+#[rustler::nif]
+fn loopback_enable(names: Vec<String>) -> Atom {
+    loopback::enable(names);
+    atoms::ok()
+}
+
+#[rustler::nif]
+fn loopback_disable() -> Atom {
+    loopback::disable();
+    atoms::ok()
+}
+
+#[rustler::nif]
+fn loopback_enabled() -> bool {
+    loopback::is_enabled()
+}
+
+#[rustler::nif]
+fn loopback_add_device(name: String, input: bool, output: bool) -> Result<Atom, Error> {
+    if !loopback::is_enabled() {
+        return Err(
+            MidiexError::Unsupported("The loopback backend isn't enabled.".to_string()).into(),
+        );
+    }
+
+    loopback::add_device(name.clone(), name, input, output, false);
+    Ok(atoms::ok())
+}
+
+#[rustler::nif]
+fn loopback_remove_device(name: String) -> Result<Atom, Error> {
+    match loopback::remove_devices(&name) {
+        0 => Err(MidiexError::InvalidPort(format!("No loopback device named {}.", name)).into()),
+        _ => Ok(atoms::ok()),
+    }
+}
+
+#[rustler::nif]
+fn loopback_set_clock(us: u64) -> Atom {
+    loopback::set_clock(us);
+    atoms::ok()
+}
+
+#[rustler::nif]
+fn loopback_advance_clock(us: u64) -> u64 {
+    loopback::advance_clock(us)
+}
+
+#[rustler::nif]
+fn loopback_clock() -> u64 {
+    loopback::clock()
+}
+
+// The ports of the loopback devices, numbered as the OS's ports are: inputs and outputs each from 0
+fn loopback_ports() -> Vec<MidiPort> {
+    let inputs = loopback::ports(loopback::Direction::Input)
+        .into_iter()
+        .enumerate()
+        .map(|(num, (device, name))| MidiPort {
+            direction: atoms::input(),
+            name,
+            num,
+            id: format!("loopback:{}", device),
+            native_id: NativeId::None,
+            port_ref: ResourceArc::new(FlexiPort::new(MidiexMidiPortRef::LoopbackInput(device))),
+        });
+
+    let outputs = loopback::ports(loopback::Direction::Output)
+        .into_iter()
+        .enumerate()
+        .map(|(num, (device, name))| MidiPort {
+            direction: atoms::output(),
+            name,
+            num,
+            id: format!("loopback:{}", device),
+            native_id: NativeId::None,
+            port_ref: ResourceArc::new(FlexiPort::new(MidiexMidiPortRef::LoopbackOutput(device))),
+        });
+
+    inputs.chain(outputs).collect()
+}
+
 // ------------------------
+// PORT IDS
+// ------------------------
+
+// A stable id for the port, made from its native id (or its name if it doesn't have one), e.g. "alsa:14:0"
+fn port_id(name: &str, index: usize, direction: Atom) -> (String, NativeId) {
+    let native_id = native_port_id(name, index, direction);
+
+    let id = match &native_id {
+        NativeId::Alsa(client, port) => format!("alsa:{}:{}", client, port),
+        NativeId::CoreMidi(unique_id) => format!("coremidi:{}", unique_id),
+        NativeId::None => name.to_string(),
+    };
+    (id, native_id)
+}
+
+// midir ends the name of an ALSA port with its address, e.g. "Midi Through:Midi Through Port-0 14:0"
+#[cfg(target_os = "linux")]
+fn native_port_id(name: &str, _index: usize, _direction: Atom) -> NativeId {
+    name.rsplit_once(' ')
+        .and_then(|(_, addr)| addr.split_once(':'))
+        .and_then(|(client, port)| Some(NativeId::Alsa(client.parse().ok()?, port.parse().ok()?)))
+        .unwrap_or(NativeId::None)
+}
+
+// midir lists CoreMIDI's sources and destinations in order, so the port's index is also the endpoint's
+#[cfg(target_os = "macos")]
+fn native_port_id(_name: &str, index: usize, direction: Atom) -> NativeId {
+    let unique_id = match direction == atoms::input() {
+        true => coremidi::Source::from_index(index).and_then(|source| source.unique_id()),
+        false => coremidi::Destination::from_index(index).and_then(|dest| dest.unique_id()),
+    };
+
+    unique_id
+        .map(|unique_id| NativeId::CoreMidi(unique_id as i64))
+        .unwrap_or(NativeId::None)
+}
 
-// List all the ports, taking midi_io as input
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn native_port_id(_name: &str, _index: usize, _direction: Atom) -> NativeId {
+    NativeId::None
+}
+
+// Finds the port with the id in a fresh listing of the ports, returning nil if it's gone
 #[rustler::nif(schedule = "DirtyCpu")]
-fn list_ports() -> Result<Vec<MidiPort>, Error> {
-    let mut vec_of_devices: Vec<MidiPort> = Vec::new();
+fn find_port_by_id(id: String, direction: Atom) -> Result<Option<MidiPort>, Error> {
+    Ok(all_ports(&Names::default())?
+        .into_iter()
+        .find(|port| port.direction == direction && port.id == id))
+}
+
+// ------------------------
+// PORT DETAILS
+// ------------------------
+
+#[derive(NifMap)]
+pub struct PortInfo {
+    port: MidiPort,
+    client_name: String,
+    port_name: String,
+    manufacturer: Option<String>,
+    client_type: Atom,
+    card: Option<i32>,
+    capabilities: Vec<Atom>,
+    types: Vec<Atom>,
+    midi_channels: i32,
+    own: bool,
+}
 
-    GLOBAL_MIDI_INPUT_RESULT.with(|midi_input_result| {
-        let midi_input = match midi_input_result {
-            Ok(midi_device) => midi_device,
-            Err(error) => panic!("Problem getting midi input devices. Error: {:?}", error),
+// Details of each port from the backend: the ALSA sequencer on Linux. Loopback devices are described as
+// applications, which they stand in for.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn port_info(ports: Vec<MidiPort>) -> Result<Vec<PortInfo>, Error> {
+    let mut infos = Vec::with_capacity(ports.len());
+    let mut backend_ports = Vec::new();
+
+    for port in ports {
+        let device = match &port.port_ref.0 {
+            MidiexMidiPortRef::LoopbackInput(device)
+            | MidiexMidiPortRef::LoopbackOutput(device) => Some(*device),
+            _ => None,
         };
 
-        // println!("\nMidi input ports: {:?}\n\r", midi_input.port_count());
+        match device {
+            Some(device) => infos.push(Some(loopback_port_info(port, device))),
+            None => {
+                infos.push(None);
+                backend_ports.push(port);
+            }
+        }
+    }
+
+    // The backend's ports fill the gaps left for them, in order
+    let mut backend_infos = backend_port_info(backend_ports)?.into_iter();
+
+    Ok(infos
+        .into_iter()
+        .filter_map(|info| info.or_else(|| backend_infos.next()))
+        .collect())
+}
+
+fn loopback_port_info(port: MidiPort, device: u32) -> PortInfo {
+    let capability = match port.direction == atoms::input() {
+        true => atoms::read(),
+        false => atoms::write(),
+    };
+
+    PortInfo {
+        client_name: loopback::client_name(device).unwrap_or_else(|| port.name.clone()),
+        port_name: port.name.clone(),
+        manufacturer: None,
+        client_type: atoms::user(),
+        card: None,
+        capabilities: vec![capability],
+        types: vec![
+            atoms::midi_generic(),
+            atoms::software(),
+            atoms::application(),
+        ],
+        midi_channels: 16,
+        own: loopback::is_owned(device),
+        port,
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn backend_port_info(ports: Vec<MidiPort>) -> Result<Vec<PortInfo>, MidiexError> {
+    use alsa::seq::{Addr, PortCap, PortType};
+
+    let addrs = ports
+        .iter()
+        .map(|port| match port.native_id {
+            NativeId::Alsa(client, port_num) => Ok(Addr {
+                client,
+                port: port_num,
+            }),
+            _ => Err(MidiexError::InvalidPort(format!(
+                "{} has no ALSA sequencer address.",
+                port.name
+            ))),
+        })
+        .collect::<Result<Vec<Addr>, MidiexError>>()?;
+
+    let capabilities = [
+        (PortCap::READ, atoms::read()),
+        (PortCap::WRITE, atoms::write()),
+        (PortCap::SUBS_READ, atoms::subscribe_read()),
+        (PortCap::SUBS_WRITE, atoms::subscribe_write()),
+        (PortCap::DUPLEX, atoms::duplex()),
+    ];
+    let types = [
+        (PortType::MIDI_GENERIC, atoms::midi_generic()),
+        (PortType::HARDWARE, atoms::hardware()),
+        (PortType::SOFTWARE, atoms::software()),
+        (PortType::SYNTH, atoms::synth()),
+        (PortType::APPLICATION, atoms::application()),
+    ];
+    let pid = std::process::id() as i32;
+
+    alsa_seq::port_details(&addrs)?
+        .into_iter()
+        .zip(ports)
+        .map(|(details, port)| {
+            let details = details.ok_or_else(|| {
+                MidiexError::InvalidPort(format!("{} is no longer available.", port.name))
+            })?;
+
+            let mut port_types: Vec<Atom> = types
+                .iter()
+                .filter(|(flag, _)| details.port_type.contains(*flag))
+                .map(|(_, atom)| *atom)
+                .collect();
+
+            // ALSA has no flag for it, but a port an application has made for itself isn't backed by a device
+            if !details.kernel_client && details.port_type.contains(PortType::APPLICATION) {
+                port_types.push(atoms::virtual_());
+            }
+
+            Ok(PortInfo {
+                client_name: details.client_name,
+                port_name: details.port_name,
+                manufacturer: details.manufacturer,
+                client_type: match details.kernel_client {
+                    true => atoms::kernel(),
+                    false => atoms::user(),
+                },
+                card: details.card,
+                capabilities: capabilities
+                    .iter()
+                    .filter(|(flag, _)| details.capability.contains(*flag))
+                    .map(|(_, atom)| *atom)
+                    .collect(),
+                types: port_types,
+                midi_channels: details.midi_channels,
+                own: details.pid == Some(pid),
+                port,
+            })
+        })
+        .collect()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn backend_port_info(ports: Vec<MidiPort>) -> Result<Vec<PortInfo>, MidiexError> {
+    match ports.is_empty() {
+        true => Ok(Vec::new()),
+        false => Err(MidiexError::Unsupported(
+            "Port details are not yet available for this platform (currently Linux only)."
+                .to_string(),
+        )),
+    }
+}
+
+// ------------------------
+// LIST PORTS
+// ------------------------
+
+// List all the ports, taking midi_io as input. They're listed by a client named after names.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn list_ports(names: Names) -> Result<Vec<MidiPort>, Error> {
+    Ok(all_ports(&names)?)
+}
+
+fn all_ports(names: &Names) -> Result<Vec<MidiPort>, MidiexError> {
+    if loopback::is_enabled() {
+        return Ok(loopback_ports());
+    }
+
+    with_listing_clients(names, |midi_input, midi_output| {
+        let mut vec_of_devices: Vec<MidiPort> = Vec::new();
 
         for (i, p) in midi_input.ports().iter().enumerate() {
             let port_name = if let Ok(port_name) = midi_input.port_name(&p) {
@@ -657,24 +3771,19 @@ fn list_ports() -> Result<Vec<MidiPort>, Error> {
                 "No device name given".to_string()
             };
 
+            let (id, native_id) = port_id(&port_name, i, atoms::input());
+
             vec_of_devices.push(MidiPort {
                 direction: atoms::input(),
                 name: port_name,
                 num: i,
+                id,
+                native_id,
                 port_ref: ResourceArc::new(FlexiPort::new(MidiexMidiPortRef::Input(
                     MidiInputPort::clone(p),
                 ))),
             });
         }
-    });
-
-    GLOBAL_MIDI_OUTPUT_RESULT.with(|midi_output_result| {
-        let midi_output = match midi_output_result {
-            Ok(midi_device) => midi_device,
-            Err(error) => panic!("Problem getting midi output devices. Error: {:?}", error),
-        };
-
-        // println!("Midi output ports: {:?}\n\r", midi_output.port_count());
 
         for (i, p) in midi_output.ports().iter().enumerate() {
             let port_name = if let Ok(port_name) = midi_output.port_name(&p) {
@@ -683,18 +3792,35 @@ fn list_ports() -> Result<Vec<MidiPort>, Error> {
                 "No device name given".to_string()
             };
 
+            let (id, native_id) = port_id(&port_name, i, atoms::output());
+
             vec_of_devices.push(MidiPort {
                 direction: atoms::output(),
                 name: port_name,
                 num: i,
+                id,
+                native_id,
                 port_ref: ResourceArc::new(FlexiPort::new(MidiexMidiPortRef::Output(
                     MidiOutputPort::clone(p),
                 ))),
             });
         }
-    });
 
-    return Ok(vec_of_devices);
+        Ok(vec_of_devices)
+    })
+}
+
+// Calls f with clients for listing ports. They're made for the call, rather than kept on each of the scheduler's
+// threads, so they're never left open among the clients this VM owns.
+fn with_listing_clients<T, F>(names: &Names, f: F) -> Result<T, MidiexError>
+where
+    F: FnOnce(&MidiInput, &MidiOutput) -> Result<T, MidiexError>,
+{
+    let client_name = names.client_name("MIDIex")?;
+    let midi_input = MidiInput::new(&client_name)?;
+    let midi_output = MidiOutput::new(&client_name)?;
+
+    f(&midi_input, &midi_output)
 }
 
 // ------------------------
@@ -703,31 +3829,94 @@ fn list_ports() -> Result<Vec<MidiPort>, Error> {
 
 #[rustler::nif(schedule = "DirtyCpu")]
 fn count_ports() -> Result<NumPorts, Error> {
-    let mut num_input_ports = 0;
-    let mut num_output_ports = 0;
+    if loopback::is_enabled() {
+        return Ok(NumPorts {
+            input: loopback::ports(loopback::Direction::Input).len(),
+            output: loopback::ports(loopback::Direction::Output).len(),
+        });
+    }
 
-    GLOBAL_MIDI_INPUT_RESULT.with(|midi_input_result| {
-        let midi_input = match midi_input_result {
-            Ok(midi_device) => midi_device,
-            Err(error) => panic!("Problem getting midi input devices. Error: {:?}", error),
-        };
+    Ok(with_listing_clients(
+        &Names::default(),
+        |midi_input, midi_output| {
+            Ok(NumPorts {
+                input: midi_input.port_count(),
+                output: midi_output.port_count(),
+            })
+        },
+    )?)
+}
 
-        num_input_ports = midi_input.port_count();
-    });
+// ------------------------
+// OWNED CLIENTS
+// ------------------------
 
-    GLOBAL_MIDI_OUTPUT_RESULT.with(|midi_output_result| {
-        let midi_output = match midi_output_result {
-            Ok(midi_device) => midi_device,
-            Err(error) => panic!("Problem getting midi output devices. Error: {:?}", error),
-        };
+#[derive(NifMap)]
+pub struct OwnedClient {
+    client_name: String,
+    // The client's number on the ALSA sequencer
+    client: Option<i32>,
+    ports: Vec<OwnedPort>,
+}
 
-        num_output_ports = midi_output.port_count();
-    });
+#[derive(NifMap)]
+pub struct OwnedPort {
+    port_name: String,
+    id: String,
+    // The ids of the ports it's connected to
+    connections: Vec<String>,
+}
 
-    return Ok(NumPorts {
-        input: num_input_ports,
-        output: num_output_ports,
-    });
+// The clients this VM has open, with their ports and what each is connected to: on Linux, its clients on the ALSA
+// sequencer. While the loopback backend is enabled, the virtual ports made
+// on it, which have no connections listed as nothing connects to a loopback device through a port.
+#[rustler::nif(schedule = "DirtyCpu")]
+fn owned_clients() -> Result<Vec<OwnedClient>, Error> {
+    if loopback::is_enabled() {
+        return Ok(loopback::owned_devices()
+            .into_iter()
+            .map(|(device, client_name, name)| OwnedClient {
+                client_name,
+                client: None,
+                ports: vec![OwnedPort {
+                    port_name: name,
+                    id: format!("loopback:{}", device),
+                    connections: Vec::new(),
+                }],
+            })
+            .collect());
+    }
+
+    Ok(backend_owned_clients()?)
+}
+
+#[cfg(target_os = "linux")]
+fn backend_owned_clients() -> Result<Vec<OwnedClient>, MidiexError> {
+    let alsa_id = |addr: alsa::seq::Addr| format!("alsa:{}:{}", addr.client, addr.port);
+
+    Ok(alsa_seq::owned_clients()?
+        .into_iter()
+        .map(|client| OwnedClient {
+            client_name: client.name,
+            client: Some(client.client),
+            ports: client
+                .ports
+                .into_iter()
+                .map(|port| OwnedPort {
+                    port_name: port.name,
+                    id: alsa_id(port.addr),
+                    connections: port.connections.into_iter().map(alsa_id).collect(),
+                })
+                .collect(),
+        })
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn backend_owned_clients() -> Result<Vec<OwnedClient>, MidiexError> {
+    Err(MidiexError::Unsupported(
+        "Owned clients are not yet listed for this platform (currently Linux only).".to_string(),
+    ))
 }
 
 // ------------------------
@@ -745,6 +3934,20 @@ fn on_load(env: Env, _info: Term) -> bool {
 
     // MIDI connection to a MIDI port
     rustler::resource!(OutConnRef, env);
+    rustler::resource!(InConnRef, env);
+    rustler::resource!(VirtualInputRef, env);
+
+    // Standard MIDI file player
+    rustler::resource!(PlayerRef, env);
+
+    // Recorder
+    rustler::resource!(RecorderRef, env);
+
+    // Running status
+    rustler::resource!(StreamDecoderRef, env);
+
+    // MIDI-CI responder
+    rustler::resource!(CiResponderRef, env);
 
     // MIDI notification
     rustler::resource!(MidiNotification, env);
@@ -760,15 +3963,61 @@ rustler::init!(
     [
         count_ports,
         list_ports,
+        set_names,
+        get_names,
+        owned_clients,
+        find_port_by_id,
+        port_info,
         connect,
         close_out_conn,
         send_msg,
+        send_msg_strict,
+        set_running_status,
+        new_stream_decoder,
+        stream_decode,
+        decode_msg,
+        ump_from_midi1,
+        ump_to_midi1,
+        ump_packets,
+        ump_scale_up,
+        ump_scale_down,
+        ci_parse,
+        ci_build,
+        ci_new_muid,
+        ci_start_responder,
+        ci_send,
+        ci_discover,
+        ci_peers,
+        ci_set_property,
+        ci_set_profile,
+        ci_stop_responder,
+        now_us,
+        send_at,
+        flush,
+        cancel_all,
+        queue_len,
+        send_sysex,
+        read_smf,
+        write_smf,
+        play_smf,
+        player_pause,
+        player_resume,
+        player_seek,
+        player_set_tempo_scale,
+        player_loop,
+        player_stop,
+        start_recording,
+        stop_recording,
+        connect_input,
+        close_in_conn,
         subscribe,
         unsubscribe_all_ports,
         unsubscribe_port,
         unsubscribe_port_by_index,
         create_virtual_output_conn,
         create_virtual_input,
+        close_virtual_input,
+        create_virtual_device,
         #[cfg(not(any(target_os = "windows")))]
         subscribe_virtual_input,
         #[cfg(not(any(target_os = "windows")))]
@@ -778,7 +4027,15 @@ rustler::init!(
         get_subscribed_ports,
         get_subscribed_virtual_ports,
         notifications,
-        hotplug
+        hotplug,
+        loopback_enable,
+        loopback_disable,
+        loopback_enabled,
+        loopback_add_device,
+        loopback_remove_device,
+        loopback_set_clock,
+        loopback_advance_clock,
+        loopback_clock
     ],
     load = on_load
 );
@@ -0,0 +1,270 @@
+// ---------------------------------------
+// LOOPBACK BACKEND
+// ---------------------------------------
+// An in-process stand-in for the OS's MIDI backend, so everything
+// can be tested without sound hardware or a MIDI driver. While it's
+// enabled, the ports listed are those of loopback devices: a message
+// sent to a device's output port is received on its input port, as
+// with a loopback cable. Virtual ports are loopback devices with only
+// one side.
+//
+// Each listener is called on its own thread, in the order messages
+// were sent, with the time on the loopback clock as the timestamp.
+// The clock only moves when it's set, so timestamps are the same
+// every time a test is run.
+// ---------------------------------------
+
+use std::sync::mpsc::{self, Sender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+
+use midir::SendError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Input,
+    Output,
+}
+
+// Where a listener is sent each message's timestamp and data
+type ListenerTx = Sender<(u64, Vec<u8>)>;
+
+struct Device {
+    id: u32,
+    name: String,
+    // The name of the client its ports belong to, as set for our virtual ports
+    client_name: String,
+    // Whether the device has an input port (receiving what's sent to it) and an output port (taking messages)
+    input: bool,
+    output: bool,
+    // Whether the device is one of our virtual ports, rather than standing in for another device or application
+    owned: bool,
+    listeners: Vec<(u64, ListenerTx)>,
+}
+
+impl Device {
+    fn has(&self, direction: Direction) -> bool {
+        match direction {
+            Direction::Input => self.input,
+            Direction::Output => self.output,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    enabled: bool,
+    devices: Vec<Device>,
+    next_device: u32,
+    next_listener: u64,
+    clock_us: u64,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+fn registry() -> std::sync::MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub fn is_enabled() -> bool {
+    registry().enabled
+}
+
+// Switches to the loopback backend, replacing any loopback devices with ones with these names, each with an input
+// and an output port. The clock is reset to 0.
+pub fn enable(names: Vec<String>) {
+    let mut registry = registry();
+    registry.enabled = true;
+    registry.devices.clear();
+    registry.clock_us = 0;
+
+    for name in names {
+        add(&mut registry, name.clone(), name, true, true, false);
+    }
+}
+
+// Switches back to the OS's backend, removing every loopback device. Connections to them stop sending and receiving.
+pub fn disable() {
+    let mut registry = registry();
+    registry.enabled = false;
+    registry.devices.clear();
+}
+
+// Adds a device, returning its id
+pub fn add_device(
+    name: String,
+    client_name: String,
+    input: bool,
+    output: bool,
+    owned: bool,
+) -> u32 {
+    add(&mut registry(), name, client_name, input, output, owned)
+}
+
+fn add(
+    registry: &mut Registry,
+    name: String,
+    client_name: String,
+    input: bool,
+    output: bool,
+    owned: bool,
+) -> u32 {
+    registry.next_device += 1;
+    let id = registry.next_device;
+
+    registry.devices.push(Device {
+        id,
+        name,
+        client_name,
+        input,
+        output,
+        owned,
+        listeners: Vec::new(),
+    });
+    id
+}
+
+// Removes the devices with this name, returning how many there were
+pub fn remove_devices(name: &str) -> usize {
+    let mut registry = registry();
+    let len = registry.devices.len();
+    registry.devices.retain(|device| device.name != name);
+    len - registry.devices.len()
+}
+
+pub fn remove_device(id: u32) {
+    registry().devices.retain(|device| device.id != id);
+}
+
+// The id and name of each device with a port in this direction, in the order they were added
+pub fn ports(direction: Direction) -> Vec<(u32, String)> {
+    registry()
+        .devices
+        .iter()
+        .filter(|device| device.has(direction))
+        .map(|device| (device.id, device.name.clone()))
+        .collect()
+}
+
+pub fn is_owned(id: u32) -> bool {
+    registry()
+        .devices
+        .iter()
+        .any(|device| device.id == id && device.owned)
+}
+
+pub fn client_name(id: u32) -> Option<String> {
+    registry()
+        .devices
+        .iter()
+        .find(|device| device.id == id)
+        .map(|device| device.client_name.clone())
+}
+
+// The id, client name and name of each of our virtual ports, in the order they were added
+pub fn owned_devices() -> Vec<(u32, String, String)> {
+    registry()
+        .devices
+        .iter()
+        .filter(|device| device.owned)
+        .map(|device| (device.id, device.client_name.clone(), device.name.clone()))
+        .collect()
+}
+
+pub fn set_clock(us: u64) {
+    registry().clock_us = us;
+}
+
+pub fn advance_clock(us: u64) -> u64 {
+    let mut registry = registry();
+    registry.clock_us = registry.clock_us.saturating_add(us);
+    registry.clock_us
+}
+
+pub fn clock() -> u64 {
+    registry().clock_us
+}
+
+// Sends a message to the device's output port, so it's received by everything listening to its input port
+pub fn send(id: u32, message: &[u8]) -> Result<(), SendError> {
+    let registry = registry();
+    let stamp = registry.clock_us;
+
+    let device = registry
+        .devices
+        .iter()
+        .find(|device| device.id == id)
+        .ok_or(SendError::Other("the loopback device has been removed"))?;
+
+    for (_, listener) in &device.listeners {
+        let _ = listener.send((stamp, message.to_vec()));
+    }
+    Ok(())
+}
+
+// Listens to the device's input port, calling callback on a new thread with each message's timestamp and data.
+// Returns None if there's no such device. If remove_device is true the device is removed once the listener is
+// closed, as a virtual port is.
+pub fn listen<F>(id: u32, remove_device: bool, mut callback: F) -> Option<Listener>
+where
+    F: FnMut(u64, &[u8]) + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel::<(u64, Vec<u8>)>();
+
+    let listener_id = {
+        let mut registry = registry();
+        registry.next_listener += 1;
+        let listener_id = registry.next_listener;
+
+        registry
+            .devices
+            .iter_mut()
+            .find(|device| device.id == id)?
+            .listeners
+            .push((listener_id, tx));
+        listener_id
+    };
+
+    // The thread exits once the listener is closed, or the device removed, as that drops the sender
+    let handle = std::thread::spawn(move || {
+        for (stamp, message) in rx {
+            callback(stamp, &message);
+        }
+    });
+
+    Some(Listener {
+        device: id,
+        id: listener_id,
+        remove_device,
+        handle,
+    })
+}
+
+pub struct Listener {
+    device: u32,
+    id: u64,
+    remove_device: bool,
+    handle: JoinHandle<()>,
+}
+
+impl Listener {
+    // Stops listening, returning once the messages already sent to the listener have been passed to its callback
+    pub fn close(self) {
+        {
+            let mut registry = registry();
+            if self.remove_device {
+                registry.devices.retain(|device| device.id != self.device);
+            } else if let Some(device) = registry
+                .devices
+                .iter_mut()
+                .find(|device| device.id == self.device)
+            {
+                device.listeners.retain(|(id, _)| *id != self.id);
+            }
+        }
+
+        let _ = self.handle.join();
+    }
+}
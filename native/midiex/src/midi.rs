@@ -0,0 +1,220 @@
+// ---------------------------------------
+// MIDI 1.0 MESSAGE FRAMING
+// ---------------------------------------
+// Checks a binary is a single, complete MIDI 1.0 message before it's
+// handed to the backend (used by strict sending), and decodes one
+// into a typed Message.
+// ---------------------------------------
+
+use std::fmt;
+
+pub const SYSEX_START: u8 = 0xF0;
+pub const SYSEX_END: u8 = 0xF7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FramingError {
+    Empty,
+    // The first byte is a data byte (< 0x80) rather than a status byte
+    MissingStatus,
+    // 0xF4, 0xF5, 0xF9 and 0xFD aren't defined by MIDI 1.0
+    UndefinedStatus,
+    // An End of Exclusive (0xF7) without a SysEx to end
+    UnexpectedEndOfExclusive,
+    // Too few or too many data bytes for the status byte
+    WrongLength,
+    // A status byte where a data byte was expected
+    InvalidDataByte,
+    // A SysEx not ending in 0xF7
+    UnterminatedSysex,
+}
+
+impl fmt::Display for FramingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FramingError::Empty => "the message is empty".fmt(f),
+            FramingError::MissingStatus => "the message doesn't start with a status byte".fmt(f),
+            FramingError::UndefinedStatus => "the status byte isn't defined by MIDI 1.0".fmt(f),
+            FramingError::UnexpectedEndOfExclusive => {
+                "end of exclusive (0xF7) without a system exclusive message".fmt(f)
+            }
+            FramingError::WrongLength => {
+                "the number of data bytes doesn't match the status byte".fmt(f)
+            }
+            FramingError::InvalidDataByte => "a data byte is 0x80 or above".fmt(f),
+            FramingError::UnterminatedSysex => {
+                "the system exclusive message isn't terminated by 0xF7".fmt(f)
+            }
+        }
+    }
+}
+
+// The total length of a message (including its status byte), or None if the status starts a SysEx or is undefined
+pub fn message_len(status: u8) -> Option<usize> {
+    match status {
+        0x80..=0xBF | 0xE0..=0xEF => Some(3),
+        0xC0..=0xDF => Some(2),
+        0xF1 | 0xF3 => Some(2),
+        0xF2 => Some(3),
+        0xF6 | 0xF8 | 0xFA..=0xFC | 0xFE | 0xFF => Some(1),
+        _ => None,
+    }
+}
+
+pub fn is_realtime(byte: u8) -> bool {
+    matches!(byte, 0xF8 | 0xFA..=0xFC | 0xFE | 0xFF)
+}
+
+// Validates that message is exactly one MIDI 1.0 message
+pub fn validate(message: &[u8]) -> Result<(), FramingError> {
+    let status = *message.first().ok_or(FramingError::Empty)?;
+
+    match status {
+        0x00..=0x7F => Err(FramingError::MissingStatus),
+        SYSEX_START => validate_sysex(message),
+        SYSEX_END => Err(FramingError::UnexpectedEndOfExclusive),
+        _ => {
+            let len = message_len(status).ok_or(FramingError::UndefinedStatus)?;
+
+            if message[1..].iter().any(|byte| *byte >= 0x80) {
+                Err(FramingError::InvalidDataByte)
+            } else if message.len() != len {
+                Err(FramingError::WrongLength)
+            } else {
+                Ok(())
+            }
+        }
+    }
+}
+
+fn validate_sysex(message: &[u8]) -> Result<(), FramingError> {
+    let (last, body) = match message[1..].split_last() {
+        Some((last, body)) => (*last, body),
+        None => return Err(FramingError::UnterminatedSysex),
+    };
+
+    // Real-time messages are allowed to be interleaved with a SysEx's data
+    for byte in body {
+        if *byte >= 0x80 && !is_realtime(*byte) {
+            return Err(if *byte == SYSEX_END {
+                FramingError::WrongLength
+            } else {
+                FramingError::InvalidDataByte
+            });
+        }
+    }
+
+    match last {
+        SYSEX_END => Ok(()),
+        byte if byte >= 0x80 && !is_realtime(byte) => Err(FramingError::InvalidDataByte),
+        _ => Err(FramingError::UnterminatedSysex),
+    }
+}
+
+// A decoded MIDI 1.0 message. Channels are 0 to 15.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    // Channel voice
+    NoteOff(u8, u8, u8),
+    NoteOn(u8, u8, u8),
+    PolyAftertouch(u8, u8, u8),
+    ControlChange(u8, u8, u8),
+    ProgramChange(u8, u8),
+    ChannelAftertouch(u8, u8),
+    // The 14 bit bend, 8192 being the centre
+    PitchBend(u8, u16),
+
+    // Channel mode (control changes 120 to 127)
+    AllSoundOff(u8),
+    ResetAllControllers(u8),
+    LocalControl(u8, bool),
+    AllNotesOff(u8),
+    OmniOff(u8),
+    OmniOn(u8),
+    // The number of channels, 0 meaning as many as there are voices
+    MonoOn(u8, u8),
+    PolyOn(u8),
+
+    // System common. A SysEx's manufacturer ID is 1 byte, or 3 when it starts with 0, and its payload is the
+    // bytes between the ID and the End of Exclusive.
+    Sysex(Vec<u8>, Vec<u8>),
+    MtcQuarterFrame(u8, u8),
+    SongPosition(u16),
+    SongSelect(u8),
+    TuneRequest,
+
+    // System real-time
+    Clock,
+    Start,
+    Continue,
+    Stop,
+    ActiveSensing,
+    Reset,
+}
+
+// Decodes a single MIDI 1.0 message. If note_off is true, a Note On with a velocity of 0 is decoded as a Note Off
+// with a velocity of 64, as the two are meant to be treated the same.
+pub fn decode(message: &[u8], note_off: bool) -> Result<Message, FramingError> {
+    validate(message)?;
+
+    let status = message[0];
+    let channel = status & 0x0F;
+    let data = |i: usize| message[i];
+
+    let decoded = match status {
+        0x80..=0x8F => Message::NoteOff(channel, data(1), data(2)),
+        0x90..=0x9F if note_off && data(2) == 0 => Message::NoteOff(channel, data(1), 64),
+        0x90..=0x9F => Message::NoteOn(channel, data(1), data(2)),
+        0xA0..=0xAF => Message::PolyAftertouch(channel, data(1), data(2)),
+        0xB0..=0xBF => decode_control_change(channel, data(1), data(2)),
+        0xC0..=0xCF => Message::ProgramChange(channel, data(1)),
+        0xD0..=0xDF => Message::ChannelAftertouch(channel, data(1)),
+        0xE0..=0xEF => Message::PitchBend(channel, data(1) as u16 | (data(2) as u16) << 7),
+        SYSEX_START => decode_sysex(message),
+        0xF1 => Message::MtcQuarterFrame(data(1) >> 4, data(1) & 0x0F),
+        0xF2 => Message::SongPosition(data(1) as u16 | (data(2) as u16) << 7),
+        0xF3 => Message::SongSelect(data(1)),
+        0xF6 => Message::TuneRequest,
+        0xF8 => Message::Clock,
+        0xFA => Message::Start,
+        0xFB => Message::Continue,
+        0xFC => Message::Stop,
+        0xFE => Message::ActiveSensing,
+        0xFF => Message::Reset,
+        // validate() has already rejected everything else
+        _ => return Err(FramingError::UndefinedStatus),
+    };
+
+    Ok(decoded)
+}
+
+fn decode_control_change(channel: u8, control: u8, value: u8) -> Message {
+    match control {
+        120 => Message::AllSoundOff(channel),
+        121 => Message::ResetAllControllers(channel),
+        122 => Message::LocalControl(channel, value >= 64),
+        123 => Message::AllNotesOff(channel),
+        124 => Message::OmniOff(channel),
+        125 => Message::OmniOn(channel),
+        126 => Message::MonoOn(channel, value),
+        127 => Message::PolyOn(channel),
+        _ => Message::ControlChange(channel, control, value),
+    }
+}
+
+fn decode_sysex(message: &[u8]) -> Message {
+    // Real-time messages interleaved with the SysEx aren't part of it
+    let body: Vec<u8> = message[1..message.len() - 1]
+        .iter()
+        .copied()
+        .filter(|byte| !is_realtime(*byte))
+        .collect();
+
+    let id_len = match body.first() {
+        Some(0) => body.len().min(3),
+        Some(_) => 1,
+        None => 0,
+    };
+    let (id, payload) = body.split_at(id_len);
+
+    Message::Sysex(id.to_vec(), payload.to_vec())
+}
@@ -0,0 +1,400 @@
+// ---------------------------------------
+// STANDARD MIDI FILE PLAYER
+// ---------------------------------------
+// Plays a standard MIDI file to an output connection on its own
+// thread, converting ticks to time with the file's tempo map.
+// Playback is controlled by sending the thread PlayerCommands.
+// ---------------------------------------
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::time::{Duration, Instant};
+
+use crate::scheduler::{self, SharedOutConn};
+use crate::smf::{Division, Smf, SmfEvent};
+
+// As in the scheduler, within this long of the next event the thread spins rather than sleeps
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+// The tempo until the file sets one: 120 BPM
+const DEFAULT_TEMPO: u32 = 500_000;
+
+const CONTROL_CHANGE: u8 = 0xB0;
+const ALL_NOTES_OFF: u8 = 123;
+
+#[derive(Clone, Copy)]
+pub enum Position {
+    Tick(u64),
+    Ms(u64),
+}
+
+pub enum PlayerCommand {
+    Pause,
+    Resume,
+    Seek(Position),
+    SetTempoScale(f64),
+    SetLoop(Option<(Position, Position)>),
+    Stop,
+}
+
+pub enum PlayerUpdate {
+    // The current position as ticks and milliseconds into the file
+    Position(u64, u64),
+    Finished,
+    Stopped,
+}
+
+pub struct PlayerOptions {
+    pub tempo_scale: f64,
+    // How often to send position updates, or None to not send them
+    pub position_interval: Option<Duration>,
+    pub start: Option<Position>,
+    pub loop_range: Option<(Position, Position)>,
+}
+
+// Converts between ticks and microseconds into the file
+struct TempoMap {
+    // Each segment is the tick it starts at, the microseconds into the file it starts at and its microseconds per tick
+    segments: Vec<(u64, f64, f64)>,
+}
+
+impl TempoMap {
+    fn new(division: &Division, tempo_changes: &[(u64, u32)]) -> Self {
+        let ppq = match division {
+            Division::Ppq(ticks) => (*ticks).max(1) as f64,
+            Division::Smpte(fps, ticks) => {
+                // 29 is 30 drop frame, 29.97 frames per second. Tempo changes don't apply to SMPTE time.
+                let fps = if *fps == 29 { 29.97 } else { *fps as f64 };
+                let us_per_tick = 1_000_000.0 / (fps * (*ticks).max(1) as f64);
+                return Self {
+                    segments: vec![(0, 0.0, us_per_tick)],
+                };
+            }
+        };
+
+        let mut segments = vec![(0, 0.0, DEFAULT_TEMPO as f64 / ppq)];
+
+        for (tick, tempo) in tempo_changes {
+            let us = Self::segment_tick_to_us(segments[segments.len() - 1], *tick);
+            let us_per_tick = *tempo as f64 / ppq;
+
+            // A tempo change at the same tick as the previous one replaces it
+            match segments.last_mut() {
+                Some(last) if last.0 == *tick => last.2 = us_per_tick,
+                _ => segments.push((*tick, us, us_per_tick)),
+            }
+        }
+
+        Self { segments }
+    }
+
+    fn segment_tick_to_us(segment: (u64, f64, f64), tick: u64) -> f64 {
+        let (start_tick, start_us, us_per_tick) = segment;
+        start_us + (tick.saturating_sub(start_tick)) as f64 * us_per_tick
+    }
+
+    fn tick_to_us(&self, tick: u64) -> f64 {
+        let i = self
+            .segments
+            .partition_point(|(start, _, _)| *start <= tick);
+        Self::segment_tick_to_us(self.segments[i.saturating_sub(1)], tick)
+    }
+
+    fn us_to_tick(&self, us: f64) -> u64 {
+        let i = self.segments.partition_point(|(_, start, _)| *start <= us);
+        let (start_tick, start_us, us_per_tick) = self.segments[i.saturating_sub(1)];
+        start_tick + ((us - start_us).max(0.0) / us_per_tick) as u64
+    }
+
+    fn position_to_us(&self, position: Position) -> f64 {
+        match position {
+            Position::Tick(tick) => self.tick_to_us(tick),
+            Position::Ms(ms) => ms as f64 * 1000.0,
+        }
+    }
+}
+
+// The events to play, as the microseconds into the file they're at and the bytes to send, along with the file's
+// tempo map. Tracks are played together, apart from format 2 files where each track (pattern) follows the last.
+fn timeline(smf: Smf) -> (Vec<(f64, Vec<u8>)>, TempoMap, f64) {
+    let mut events: Vec<(u64, usize, Vec<u8>)> = Vec::new();
+    let mut tempo_changes: Vec<(u64, u32)> = Vec::new();
+    let mut end_tick: u64 = 0;
+
+    for (i, track) in smf.tracks.into_iter().enumerate() {
+        let mut tick = if smf.format == 2 { end_tick } else { 0 };
+
+        for (delta, event) in track {
+            tick += delta as u64;
+
+            match event {
+                SmfEvent::Midi(msg) | SmfEvent::Sysex(msg) | SmfEvent::Escape(msg) => {
+                    events.push((tick, i, msg))
+                }
+                SmfEvent::Tempo(tempo) => tempo_changes.push((tick, tempo)),
+                _ => (),
+            }
+        }
+
+        end_tick = end_tick.max(tick);
+    }
+
+    events.sort_by_key(|(tick, i, _)| (*tick, *i));
+    tempo_changes.sort_by_key(|(tick, _)| *tick);
+
+    let tempo_map = TempoMap::new(&smf.division, &tempo_changes);
+    let end_us = tempo_map.tick_to_us(end_tick);
+    let events = events
+        .into_iter()
+        .map(|(tick, _, msg)| (tempo_map.tick_to_us(tick), msg))
+        .collect();
+
+    (events, tempo_map, end_us)
+}
+
+// Starts playing smf on a new thread, returning the sender used to control it
+pub fn spawn<F>(
+    conn: SharedOutConn,
+    smf: Smf,
+    options: PlayerOptions,
+    on_update: F,
+) -> Sender<PlayerCommand>
+where
+    F: FnMut(PlayerUpdate) + Send + 'static,
+{
+    let (tx, rx) = mpsc::channel::<PlayerCommand>();
+    let (events, tempo_map, end_us) = timeline(smf);
+
+    std::thread::spawn(move || {
+        let mut player = Player {
+            conn,
+            events,
+            tempo_map,
+            end_us,
+            next: 0,
+            anchor_wall: Instant::now(),
+            anchor_us: 0.0,
+            tempo_scale: options.tempo_scale,
+            paused: false,
+            loop_range: None,
+            position_interval: options.position_interval,
+            next_position_at: Instant::now(),
+            on_update,
+        };
+
+        player.set_loop(options.loop_range);
+        if let Some(start) = options.start {
+            player.seek(start);
+        }
+        // The receiver has been dropped by the time the final update is sent, so commands sent after it fail
+        if let Some(update) = player.run(rx) {
+            (player.on_update)(update);
+        }
+    });
+
+    tx
+}
+
+struct Player<F: FnMut(PlayerUpdate)> {
+    conn: SharedOutConn,
+    events: Vec<(f64, Vec<u8>)>,
+    tempo_map: TempoMap,
+    end_us: f64,
+    // Index of the next event to send
+    next: usize,
+    // The file position (in microseconds) was anchor_us at anchor_wall, moving on at tempo_scale from then on
+    anchor_wall: Instant,
+    anchor_us: f64,
+    tempo_scale: f64,
+    paused: bool,
+    loop_range: Option<(f64, f64)>,
+    position_interval: Option<Duration>,
+    next_position_at: Instant,
+    on_update: F,
+}
+
+impl<F: FnMut(PlayerUpdate)> Player<F> {
+    // Plays until finished, stopped, the handle to the player has gone or the connection is closed, returning the
+    // update to send (if any)
+    fn run(&mut self, rx: Receiver<PlayerCommand>) -> Option<PlayerUpdate> {
+        loop {
+            if !self.paused {
+                let now_us = self.now_us();
+
+                if let Some((loop_start, loop_end)) = self.loop_range {
+                    if now_us >= loop_end {
+                        // The loop's end is exclusive, so events at it are played from the loop's start instead
+                        self.send_while(|event_us| event_us < loop_end);
+                        self.all_notes_off();
+                        self.move_to(loop_start);
+                        continue;
+                    }
+                }
+
+                self.send_while(|event_us| event_us <= now_us);
+
+                if self.next >= self.events.len()
+                    && self.loop_range.is_none()
+                    && now_us >= self.end_us
+                {
+                    self.report_position();
+                    return Some(PlayerUpdate::Finished);
+                }
+
+                if self.is_closed() {
+                    return None;
+                }
+            }
+
+            if self.position_interval.is_some() && Instant::now() >= self.next_position_at {
+                self.report_position();
+            }
+
+            // The handle to the player has gone when the channel is disconnected, which stops it as Stop does
+            let command = match self.wake_at() {
+                None => match rx.recv() {
+                    Ok(command) => command,
+                    Err(_) => PlayerCommand::Stop,
+                },
+                Some(wake_at) => {
+                    let wait = wake_at.saturating_duration_since(Instant::now());
+
+                    if wait > SPIN_THRESHOLD {
+                        match rx.recv_timeout(wait - SPIN_THRESHOLD) {
+                            Ok(command) => command,
+                            Err(RecvTimeoutError::Timeout) => continue,
+                            Err(RecvTimeoutError::Disconnected) => PlayerCommand::Stop,
+                        }
+                    } else {
+                        while Instant::now() < wake_at {
+                            std::hint::spin_loop();
+                        }
+                        match rx.try_recv() {
+                            Ok(command) => command,
+                            Err(TryRecvError::Empty) => continue,
+                            Err(TryRecvError::Disconnected) => PlayerCommand::Stop,
+                        }
+                    }
+                }
+            };
+
+            match command {
+                PlayerCommand::Pause if !self.paused => {
+                    self.anchor_us = self.now_us();
+                    self.paused = true;
+                    self.all_notes_off();
+                }
+                PlayerCommand::Resume if self.paused => {
+                    self.anchor_wall = Instant::now();
+                    self.paused = false;
+                }
+                PlayerCommand::Seek(position) => self.seek(position),
+                PlayerCommand::SetTempoScale(tempo_scale) => {
+                    self.anchor_us = self.now_us();
+                    self.anchor_wall = Instant::now();
+                    self.tempo_scale = tempo_scale;
+                }
+                PlayerCommand::SetLoop(loop_range) => self.set_loop(loop_range),
+                PlayerCommand::Stop => {
+                    self.all_notes_off();
+                    return Some(PlayerUpdate::Stopped);
+                }
+                // Pausing when paused, or resuming when playing
+                PlayerCommand::Pause | PlayerCommand::Resume => (),
+            }
+        }
+    }
+
+    // Microseconds into the file
+    fn now_us(&self) -> f64 {
+        if self.paused {
+            self.anchor_us
+        } else {
+            self.anchor_us + self.anchor_wall.elapsed().as_micros() as f64 * self.tempo_scale
+        }
+    }
+
+    // When the file will be at us, if it's playing
+    fn wall_time(&self, us: f64) -> Option<Instant> {
+        if self.paused {
+            return None;
+        }
+        let wait_us = ((us - self.anchor_us) / self.tempo_scale).max(0.0);
+        Some(self.anchor_wall + Duration::from_micros(wait_us as u64))
+    }
+
+    // The next time something needs doing: sending an event, looping, finishing or reporting the position
+    fn wake_at(&self) -> Option<Instant> {
+        let next_us = match self.events.get(self.next) {
+            Some((us, _)) => *us,
+            None => self.end_us,
+        };
+        let next_us = match self.loop_range {
+            Some((_, loop_end)) => next_us.min(loop_end),
+            None => next_us,
+        };
+
+        let position_at = self.position_interval.map(|_| self.next_position_at);
+
+        match (self.wall_time(next_us), position_at) {
+            (Some(event_at), Some(position_at)) => Some(event_at.min(position_at)),
+            (event_at, position_at) => event_at.or(position_at),
+        }
+    }
+
+    fn send_while(&mut self, is_due: impl Fn(f64) -> bool) {
+        while let Some((event_us, msg)) = self.events.get(self.next) {
+            if !is_due(*event_us) {
+                break;
+            }
+            scheduler::send(&self.conn, msg);
+            self.next += 1;
+        }
+    }
+
+    // Moves to us without sending the events skipped over
+    fn move_to(&mut self, us: f64) {
+        self.anchor_us = us;
+        self.anchor_wall = Instant::now();
+        self.next = self.events.partition_point(|(event_us, _)| *event_us < us);
+    }
+
+    fn seek(&mut self, position: Position) {
+        self.all_notes_off();
+        let us = self.tempo_map.position_to_us(position);
+        self.move_to(us);
+    }
+
+    fn set_loop(&mut self, loop_range: Option<(Position, Position)>) {
+        self.loop_range = loop_range
+            .map(|(start, end)| {
+                (
+                    self.tempo_map.position_to_us(start),
+                    self.tempo_map.position_to_us(end),
+                )
+            })
+            .filter(|(start, end)| start < end);
+    }
+
+    fn report_position(&mut self) {
+        let us = self.now_us();
+        let tick = self.tempo_map.us_to_tick(us);
+        (self.on_update)(PlayerUpdate::Position(tick, (us / 1000.0) as u64));
+
+        if let Some(interval) = self.position_interval {
+            self.next_position_at = Instant::now() + interval;
+        }
+    }
+
+    fn all_notes_off(&self) {
+        for channel in 0..16 {
+            scheduler::send(&self.conn, &[CONTROL_CHANGE | channel, ALL_NOTES_OFF, 0]);
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        match self.conn.lock() {
+            Ok(binding) => binding.is_none(),
+            Err(_) => true,
+        }
+    }
+}
@@ -0,0 +1,130 @@
+// ---------------------------------------
+// RECORDER
+// ---------------------------------------
+// Buffers messages received on an input port, with their times, so
+// they can be turned into a standard MIDI file track once recording
+// stops, rather than each being sent to an Elixir process.
+// ---------------------------------------
+
+use std::time::Instant;
+
+use crate::player::Position;
+use crate::smf::{Division, Smf, SmfEvent};
+
+pub struct RecorderOptions {
+    // Microseconds per quarter note
+    pub tempo: u32,
+    pub ppq: u16,
+    // Time before recording starts, during which messages aren't recorded
+    pub count_in: Option<Position>,
+    // Only messages between punch in and punch out (measured from the end of the count in) are recorded
+    pub punch_in: Option<Position>,
+    pub punch_out: Option<Position>,
+    // Grid, in ticks, to round each message's time to. 0 or 1 rounds to the nearest tick.
+    pub quantize: u32,
+}
+
+pub struct Take {
+    options: RecorderOptions,
+    started_at: Instant,
+    count_in_us: u64,
+    punch_in_us: u64,
+    punch_out_us: u64,
+    // Difference between midir's timestamps and microseconds since started_at, found from the first message
+    stamp_offset: Option<i128>,
+    // Microseconds from the end of the count in, and the message
+    events: Vec<(u64, Vec<u8>)>,
+}
+
+impl Take {
+    pub fn new(options: RecorderOptions) -> Self {
+        let count_in_us = position_to_us(&options, options.count_in).unwrap_or(0);
+        let punch_in_us = position_to_us(&options, options.punch_in).unwrap_or(0);
+        let punch_out_us = position_to_us(&options, options.punch_out).unwrap_or(u64::MAX);
+
+        Self {
+            options,
+            started_at: Instant::now(),
+            count_in_us,
+            punch_in_us,
+            punch_out_us,
+            stamp_offset: None,
+            events: Vec::new(),
+        }
+    }
+
+    // Records a message, using midir's timestamp for when it was received. Only channel and system exclusive
+    // messages are kept, as other system messages (such as clock) have no place in a standard MIDI file.
+    pub fn push(&mut self, stamp: u64, message: &[u8]) {
+        if !matches!(message.first(), Some(0x80..=0xF0)) {
+            return;
+        }
+
+        let offset = *self
+            .stamp_offset
+            .get_or_insert_with(|| stamp as i128 - self.started_at.elapsed().as_micros() as i128);
+        let since_start = (stamp as i128 - offset).max(0) as u64;
+
+        let us = match since_start.checked_sub(self.count_in_us) {
+            Some(us) => us,
+            None => return,
+        };
+
+        if us >= self.punch_in_us && us < self.punch_out_us {
+            self.events.push((us, message.to_vec()));
+        }
+    }
+
+    // Returns the take as a format 0 file with a single track, which starts with the tempo
+    pub fn to_smf(&self) -> Smf {
+        let quantize = self.options.quantize.max(1) as u64;
+
+        let mut events: Vec<(u64, SmfEvent)> = self
+            .events
+            .iter()
+            .map(|(us, message)| {
+                let tick = us_to_tick(&self.options, *us);
+                let tick = (tick + quantize / 2) / quantize * quantize;
+
+                let event = match message[0] {
+                    0xF0 => SmfEvent::Sysex(message.clone()),
+                    _ => SmfEvent::Midi(message.clone()),
+                };
+                (tick, event)
+            })
+            .collect();
+
+        // Quantizing can put messages out of order, the sort being stable keeps messages at the same tick in order
+        events.sort_by_key(|(tick, _)| *tick);
+
+        let mut track = vec![(0, SmfEvent::Tempo(self.options.tempo))];
+        let mut last = 0;
+
+        for (tick, event) in events {
+            track.push(((tick - last).min(u32::MAX as u64) as u32, event));
+            last = tick;
+        }
+        track.push((0, SmfEvent::EndOfTrack));
+
+        Smf {
+            format: 0,
+            division: Division::Ppq(self.options.ppq),
+            tracks: vec![track],
+        }
+    }
+}
+
+fn us_to_tick(options: &RecorderOptions, us: u64) -> u64 {
+    let tempo = options.tempo.max(1) as u128;
+    ((us as u128 * options.ppq as u128 + tempo / 2) / tempo) as u64
+}
+
+fn position_to_us(options: &RecorderOptions, position: Option<Position>) -> Option<u64> {
+    match position? {
+        Position::Tick(tick) => {
+            let us = tick as u128 * options.tempo as u128 / options.ppq.max(1) as u128;
+            Some(us.min(u64::MAX as u128) as u64)
+        }
+        Position::Ms(ms) => Some(ms.saturating_mul(1000)),
+    }
+}
@@ -0,0 +1,142 @@
+// ---------------------------------------
+// RUNNING STATUS
+// ---------------------------------------
+// MIDI 1.0 lets a channel message leave out its status byte when it's
+// the same as the last one sent, saving a third of the bandwidth of a
+// 31.25 kbaud DIN link. The Encoder does this for an output
+// connection, and the StreamDecoder does the reverse for raw bytes
+// (from a serial port, file or network), splitting them into complete
+// messages.
+// ---------------------------------------
+
+use std::time::{Duration, Instant};
+
+use crate::midi::{is_realtime, message_len, SYSEX_END, SYSEX_START};
+
+// Only for backends which take what's sent as a stream of bytes, see scheduler::Connection::takes_running_status
+pub struct Encoder {
+    // The status byte is sent again at least this often, so a receiver that missed it (or was plugged in late)
+    // recovers. Zero never sends it again.
+    refresh: Duration,
+    // The running status, and when it was last sent
+    running: Option<(u8, Instant)>,
+}
+
+impl Encoder {
+    pub fn new(refresh: Duration) -> Self {
+        Self {
+            refresh,
+            running: None,
+        }
+    }
+
+    // Returns the bytes to send for message, leaving out its status byte if it can be
+    pub fn encode<'a>(&mut self, message: &'a [u8], now: Instant) -> &'a [u8] {
+        let status = match message.first() {
+            Some(status) => *status,
+            None => return message,
+        };
+
+        match status {
+            // A single, complete channel message
+            0x80..=0xEF if message_len(status) == Some(message.len()) => {
+                let running = match self.running {
+                    Some((running, sent_at)) => {
+                        running == status
+                            && (self.refresh.is_zero()
+                                || now.saturating_duration_since(sent_at) < self.refresh)
+                    }
+                    None => false,
+                };
+
+                if running {
+                    &message[1..]
+                } else {
+                    self.running = Some((status, now));
+                    message
+                }
+            }
+            // Real-time messages can go between any two bytes, so leave the running status alone
+            _ if is_realtime(status) && message.len() == 1 => message,
+            // Anything else (system common messages, SysEx, or several messages at once) cancels it
+            _ => {
+                self.running = None;
+                message
+            }
+        }
+    }
+
+    // Called when sending fails, as the receiver may not have the running status
+    pub fn reset(&mut self) {
+        self.running = None;
+    }
+}
+
+#[derive(Default)]
+pub struct StreamDecoder {
+    running_status: Option<u8>,
+    // The message being collected, starting with its status byte
+    message: Vec<u8>,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Decodes the next bytes of the stream, returning the messages they complete. The bytes needn't start or end
+    // on a message boundary, as any incomplete message is kept until the next call.
+    pub fn decode(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+
+        for byte in bytes.iter().copied() {
+            match byte {
+                _ if is_realtime(byte) => messages.push(vec![byte]),
+                // Data bytes
+                0x00..=0x7F => match self.message.first() {
+                    Some(_) => self.message.push(byte),
+                    None => match self.running_status {
+                        Some(status) => self.message.extend([status, byte]),
+                        // Without a status byte there's nothing to say what the data is, so it's dropped
+                        None => continue,
+                    },
+                },
+                0x80..=0xEF => {
+                    self.running_status = Some(byte);
+                    self.message = vec![byte];
+                }
+                SYSEX_START => {
+                    self.running_status = None;
+                    self.message = vec![byte];
+                }
+                SYSEX_END => {
+                    if self.message.first() == Some(&SYSEX_START) {
+                        self.message.push(byte);
+                        messages.push(std::mem::take(&mut self.message));
+                    }
+                    self.running_status = None;
+                    self.message.clear();
+                    continue;
+                }
+                // System common messages, and the undefined 0xF4, 0xF5, 0xF9 and 0xFD, which are dropped
+                _ => {
+                    self.running_status = None;
+                    self.message.clear();
+                    if message_len(byte).is_some() {
+                        self.message.push(byte);
+                    }
+                }
+            }
+
+            let complete = match self.message.first() {
+                Some(&SYSEX_START) | None => false,
+                Some(status) => message_len(*status) == Some(self.message.len()),
+            };
+            if complete {
+                messages.push(std::mem::take(&mut self.message));
+            }
+        }
+
+        messages
+    }
+}
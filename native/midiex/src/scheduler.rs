@@ -0,0 +1,258 @@
+// ---------------------------------------
+// SCHEDULED OUTPUT
+// ---------------------------------------
+// Each output connection can have a scheduler thread, which holds a
+// queue of timestamped messages and sends each one at its time.
+// Timestamps are microseconds on a monotonic clock, see now_us().
+// The thread exits, dropping anything still queued, once the
+// Scheduler is dropped.
+// ---------------------------------------
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use midir::{MidiOutputConnection, SendError};
+
+#[cfg(target_os = "linux")]
+use crate::alsa_seq;
+use crate::loopback;
+use crate::running_status;
+
+pub type SharedOutConn = Arc<Mutex<Option<OutPort>>>;
+
+// A connection to an output port from the MIDI backend, or to a loopback device's output port, see loopback.rs.
+// On Linux connections and virtual outputs are from our own ALSA ports, see alsa_seq::Output.
+pub enum Connection {
+    Midir(MidiOutputConnection),
+    Loopback(u32),
+    // A virtual output's loopback device, which is removed once the connection is closed
+    LoopbackVirtual(u32),
+    #[cfg(target_os = "linux")]
+    Alsa(alsa_seq::Output),
+}
+
+impl Connection {
+    fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        match self {
+            Connection::Midir(conn) => conn.send(message),
+            Connection::Loopback(device) | Connection::LoopbackVirtual(device) => {
+                loopback::send(*device, message)
+            }
+            #[cfg(target_os = "linux")]
+            Connection::Alsa(conn) => conn.send(message),
+        }
+    }
+
+    // Whether the backend takes messages without their status byte. ALSA parses what's sent as a byte stream, as
+    // loopback devices pass it on.
+    fn takes_running_status(&self) -> bool {
+        match self {
+            // Only used off Linux, where CoreMIDI and WinMM need every message whole
+            Connection::Midir(_) => false,
+            _ => true,
+        }
+    }
+
+    // An ALSA output's port is deleted as it's dropped
+    fn close(self) {
+        match self {
+            Connection::Midir(conn) => {
+                conn.close();
+            }
+            Connection::LoopbackVirtual(device) => loopback::remove_device(device),
+            _ => (),
+        }
+    }
+}
+
+// An output connection, which can leave out repeated status bytes when sending, see running_status.rs
+pub struct OutPort {
+    conn: Connection,
+    running_status: Option<running_status::Encoder>,
+}
+
+impl OutPort {
+    pub fn new(conn: Connection) -> Self {
+        Self {
+            conn,
+            running_status: None,
+        }
+    }
+
+    pub fn send(&mut self, message: &[u8]) -> Result<(), SendError> {
+        match self.running_status.as_mut() {
+            Some(encoder) => {
+                let result = self.conn.send(encoder.encode(message, Instant::now()));
+                if result.is_err() {
+                    encoder.reset();
+                }
+                result
+            }
+            None => self.conn.send(message),
+        }
+    }
+
+    // Returns false, leaving running status off, if the backend can't take it
+    pub fn set_running_status(&mut self, encoder: Option<running_status::Encoder>) -> bool {
+        if encoder.is_some() && !self.conn.takes_running_status() {
+            return false;
+        }
+        self.running_status = encoder;
+        true
+    }
+
+    pub fn close(self) {
+        self.conn.close()
+    }
+}
+
+lazy_static! {
+    static ref CLOCK_EPOCH: Instant = Instant::now();
+}
+
+// Within this long of the next message the thread spins rather than sleeps, as sleeping can overshoot by more
+// than the timing we're aiming for
+const SPIN_THRESHOLD: Duration = Duration::from_millis(1);
+
+// Microseconds since the clock's epoch (the first time the clock was used)
+pub fn now_us() -> u64 {
+    CLOCK_EPOCH.elapsed().as_micros() as u64
+}
+
+pub fn instant_from_us(timestamp_us: u64) -> Instant {
+    *CLOCK_EPOCH + Duration::from_micros(timestamp_us)
+}
+
+enum Command {
+    Schedule(Vec<(Instant, Vec<u8>)>),
+    // Send everything queued now, replying once it's been sent
+    Flush(SyncSender<()>),
+    // Drop everything queued, replying once it's been dropped
+    CancelAll(SyncSender<()>),
+}
+
+// Queued messages are ordered by time, then by the order they were queued in
+type QueuedMsg = Reverse<(Instant, u64, Vec<u8>)>;
+
+pub struct Scheduler {
+    tx: Sender<Command>,
+    queue_len: Arc<AtomicUsize>,
+}
+
+impl Scheduler {
+    pub fn spawn(conn: SharedOutConn) -> Self {
+        let (tx, rx) = mpsc::channel::<Command>();
+        let queue_len = Arc::new(AtomicUsize::new(0));
+        let worker_queue_len = queue_len.clone();
+
+        std::thread::spawn(move || run(conn, rx, worker_queue_len));
+
+        Self { tx, queue_len }
+    }
+
+    pub fn schedule(&self, msgs: Vec<(Instant, Vec<u8>)>) {
+        self.queue_len.fetch_add(msgs.len(), Ordering::SeqCst);
+        let _ = self.tx.send(Command::Schedule(msgs));
+    }
+
+    pub fn flush(&self) {
+        self.request(Command::Flush);
+    }
+
+    pub fn cancel_all(&self) {
+        self.request(Command::CancelAll);
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.queue_len.load(Ordering::SeqCst)
+    }
+
+    fn request(&self, command: fn(SyncSender<()>) -> Command) {
+        let (reply_tx, reply_rx) = mpsc::sync_channel::<()>(1);
+        if self.tx.send(command(reply_tx)).is_ok() {
+            let _ = reply_rx.recv();
+        }
+    }
+}
+
+fn run(conn: SharedOutConn, rx: Receiver<Command>, queue_len: Arc<AtomicUsize>) {
+    let mut queue: BinaryHeap<QueuedMsg> = BinaryHeap::new();
+    let mut seq: u64 = 0;
+
+    loop {
+        let next_at = queue.peek().map(|Reverse((at, _, _))| *at);
+
+        let command = match next_at {
+            None => match rx.recv() {
+                Ok(command) => Some(command),
+                Err(_) => return,
+            },
+            Some(at) => {
+                let wait = at.saturating_duration_since(Instant::now());
+
+                if wait > SPIN_THRESHOLD {
+                    match rx.recv_timeout(wait - SPIN_THRESHOLD) {
+                        Ok(command) => Some(command),
+                        Err(RecvTimeoutError::Timeout) => None,
+                        Err(RecvTimeoutError::Disconnected) => return,
+                    }
+                } else {
+                    while Instant::now() < at {
+                        std::hint::spin_loop();
+                    }
+                    send_due(&conn, &mut queue, &queue_len);
+                    None
+                }
+            }
+        };
+
+        match command {
+            Some(Command::Schedule(msgs)) => {
+                for (at, msg) in msgs {
+                    queue.push(Reverse((at, seq, msg)));
+                    seq += 1;
+                }
+            }
+            Some(Command::Flush(reply_tx)) => {
+                while let Some(Reverse((_, _, msg))) = queue.pop() {
+                    send(&conn, &msg);
+                    queue_len.fetch_sub(1, Ordering::SeqCst);
+                }
+                let _ = reply_tx.send(());
+            }
+            Some(Command::CancelAll(reply_tx)) => {
+                queue_len.fetch_sub(queue.len(), Ordering::SeqCst);
+                queue.clear();
+                let _ = reply_tx.send(());
+            }
+            None => (),
+        }
+    }
+}
+
+fn send_due(conn: &SharedOutConn, queue: &mut BinaryHeap<QueuedMsg>, queue_len: &AtomicUsize) {
+    let now = Instant::now();
+
+    while let Some(Reverse((at, _, _))) = queue.peek() {
+        if *at > now {
+            break;
+        }
+        if let Some(Reverse((_, _, msg))) = queue.pop() {
+            send(conn, &msg);
+            queue_len.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+}
+
+// There's no one to report a failed send to, so, as with a closed connection, the message is dropped
+pub fn send(conn: &SharedOutConn, msg: &[u8]) {
+    if let Ok(mut binding) = conn.lock() {
+        if let Some(conn) = binding.as_mut() {
+            let _ = conn.send(msg);
+        }
+    }
+}
@@ -0,0 +1,601 @@
+// ---------------------------------------
+// STANDARD MIDI FILES
+// ---------------------------------------
+// Parses Standard MIDI Files (format 0, 1 and 2) into their header
+// and tracks, each track being a list of {delta_ticks, event}, and
+// writes tracks of the same events back out as format 0 or 1 files.
+// ---------------------------------------
+
+use std::fmt;
+
+const HEADER_MAGIC: &[u8; 4] = b"MThd";
+const TRACK_MAGIC: &[u8; 4] = b"MTrk";
+
+const META_TRACK_NAME: u8 = 0x03;
+const META_END_OF_TRACK: u8 = 0x2F;
+const META_TEMPO: u8 = 0x51;
+const META_TIME_SIGNATURE: u8 = 0x58;
+const META_KEY_SIGNATURE: u8 = 0x59;
+
+pub struct Smf {
+    pub format: u16,
+    pub division: Division,
+    pub tracks: Vec<Vec<(u32, SmfEvent)>>,
+}
+
+pub enum Division {
+    // Ticks per quarter note
+    Ppq(u16),
+    // Frames per second (24, 25, 29 or 30) and ticks per frame
+    Smpte(u8, u8),
+}
+
+pub enum SmfEvent {
+    // A channel message, always including its status byte (running status is expanded)
+    Midi(Vec<u8>),
+    // An F0 event, including the leading 0xF0
+    Sysex(Vec<u8>),
+    // An F7 (escape) event's bytes, sent as they are
+    Escape(Vec<u8>),
+    // Microseconds per quarter note
+    Tempo(u32),
+    TimeSignature {
+        numerator: u8,
+        denominator: u32,
+        clocks_per_click: u8,
+        thirty_seconds_per_quarter: u8,
+    },
+    KeySignature {
+        // Negative for flats, positive for sharps
+        sharps_flats: i8,
+        minor: bool,
+    },
+    TrackName(String),
+    EndOfTrack,
+    // Any other meta event, by type
+    Meta(u8, Vec<u8>),
+}
+
+#[derive(Debug)]
+pub enum SmfError {
+    NotSmf,
+    Truncated,
+    InvalidHeader(String),
+    InvalidVlq,
+    // A data byte with no previous status byte to use as running status
+    NoRunningStatus,
+    UnexpectedStatus(u8),
+    // An event which can't be written, e.g. a MIDI event which isn't a channel message
+    InvalidEvent(String),
+}
+
+impl fmt::Display for SmfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SmfError::NotSmf => "not a standard MIDI file (no MThd header)".fmt(f),
+            SmfError::Truncated => "the file ends part way through a chunk or event".fmt(f),
+            SmfError::InvalidHeader(msg) => write!(f, "invalid header: {}", msg),
+            SmfError::InvalidVlq => "a variable-length quantity is longer than 4 bytes".fmt(f),
+            SmfError::NoRunningStatus => "a data byte was found with no running status".fmt(f),
+            SmfError::UnexpectedStatus(status) => {
+                write!(f, "unexpected status byte 0x{:02X} in a track", status)
+            }
+            SmfError::InvalidEvent(msg) => write!(f, "invalid event: {}", msg),
+        }
+    }
+}
+
+pub fn is_smf(bytes: &[u8]) -> bool {
+    bytes.starts_with(HEADER_MAGIC)
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Smf, SmfError> {
+    if !is_smf(bytes) {
+        return Err(SmfError::NotSmf);
+    }
+
+    let mut reader = Reader::new(bytes);
+    let (_, header) = reader.chunk()?;
+
+    if header.len() < 6 {
+        return Err(SmfError::InvalidHeader(
+            "the header chunk is shorter than 6 bytes".to_string(),
+        ));
+    }
+
+    let format = u16::from_be_bytes([header[0], header[1]]);
+    let num_tracks = u16::from_be_bytes([header[2], header[3]]);
+    let division = parse_division(u16::from_be_bytes([header[4], header[5]]))?;
+
+    if format > 2 {
+        return Err(SmfError::InvalidHeader(format!(
+            "unknown format {}",
+            format
+        )));
+    }
+
+    let mut tracks = Vec::with_capacity(num_tracks as usize);
+
+    while tracks.len() < num_tracks as usize {
+        let (chunk_type, data) = reader.chunk()?;
+
+        // Chunks other than tracks are skipped, as the spec asks
+        if chunk_type == TRACK_MAGIC {
+            tracks.push(parse_track(data)?);
+        }
+    }
+
+    Ok(Smf {
+        format,
+        division,
+        tracks,
+    })
+}
+
+fn parse_division(division: u16) -> Result<Division, SmfError> {
+    if division & 0x8000 == 0 {
+        return Ok(Division::Ppq(division));
+    }
+
+    // The upper byte is the negative frames per second, as a two's complement number
+    let fps = -((division >> 8) as u8 as i8 as i16);
+
+    match fps {
+        24 | 25 | 29 | 30 => Ok(Division::Smpte(fps as u8, (division & 0xFF) as u8)),
+        _ => Err(SmfError::InvalidHeader(format!(
+            "unknown SMPTE frame rate {}",
+            fps
+        ))),
+    }
+}
+
+fn parse_track(data: &[u8]) -> Result<Vec<(u32, SmfEvent)>, SmfError> {
+    let mut reader = Reader::new(data);
+    let mut events = Vec::new();
+    let mut running_status: Option<u8> = None;
+
+    while !reader.is_empty() {
+        let delta = reader.vlq()?;
+        let first = reader.u8()?;
+
+        let event = match first {
+            0xFF => {
+                running_status = None;
+                let meta_type = reader.u8()?;
+                let len = reader.vlq()? as usize;
+                parse_meta(meta_type, reader.bytes(len)?)
+            }
+            0xF0 => {
+                running_status = None;
+                let len = reader.vlq()? as usize;
+                // Read first, so a length running past the end of the track isn't allocated
+                let data = reader.bytes(len)?;
+                let mut sysex = Vec::with_capacity(data.len() + 1);
+                sysex.push(0xF0);
+                sysex.extend_from_slice(data);
+                SmfEvent::Sysex(sysex)
+            }
+            0xF7 => {
+                running_status = None;
+                let len = reader.vlq()? as usize;
+                SmfEvent::Escape(reader.bytes(len)?.to_vec())
+            }
+            0x80..=0xEF => {
+                running_status = Some(first);
+                channel_message(first, None, &mut reader)?
+            }
+            0x00..=0x7F => {
+                let status = running_status.ok_or(SmfError::NoRunningStatus)?;
+                channel_message(status, Some(first), &mut reader)?
+            }
+            _ => return Err(SmfError::UnexpectedStatus(first)),
+        };
+
+        let end_of_track = matches!(event, SmfEvent::EndOfTrack);
+        events.push((delta, event));
+
+        // Anything after the end of track is ignored
+        if end_of_track {
+            break;
+        }
+    }
+
+    Ok(events)
+}
+
+// Reads a channel message's data bytes, first_data being the first data byte if it has already been read (running
+// status)
+fn channel_message(
+    status: u8,
+    first_data: Option<u8>,
+    reader: &mut Reader,
+) -> Result<SmfEvent, SmfError> {
+    let num_data = match status & 0xF0 {
+        0xC0 | 0xD0 => 1,
+        _ => 2,
+    };
+
+    let mut msg = Vec::with_capacity(num_data + 1);
+    msg.push(status);
+
+    if let Some(data) = first_data {
+        msg.push(data);
+    }
+    while msg.len() < num_data + 1 {
+        msg.push(reader.u8()?);
+    }
+
+    Ok(SmfEvent::Midi(msg))
+}
+
+fn parse_meta(meta_type: u8, data: &[u8]) -> SmfEvent {
+    match (meta_type, data) {
+        (META_END_OF_TRACK, _) => SmfEvent::EndOfTrack,
+        (META_TEMPO, [a, b, c]) => SmfEvent::Tempo(u32::from_be_bytes([0, *a, *b, *c])),
+        // A denominator too large for a u32 is left as it is, so the file is written back unchanged
+        (META_TIME_SIGNATURE, [numerator, denominator, clocks, thirty_seconds])
+            if *denominator < 32 =>
+        {
+            SmfEvent::TimeSignature {
+                numerator: *numerator,
+                denominator: 1 << *denominator,
+                clocks_per_click: *clocks,
+                thirty_seconds_per_quarter: *thirty_seconds,
+            }
+        }
+        (META_KEY_SIGNATURE, [sharps_flats, minor]) => SmfEvent::KeySignature {
+            sharps_flats: *sharps_flats as i8,
+            minor: *minor == 1,
+        },
+        (META_TRACK_NAME, name) => SmfEvent::TrackName(String::from_utf8_lossy(name).into_owned()),
+        _ => SmfEvent::Meta(meta_type, data.to_vec()),
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.bytes.len()
+    }
+
+    fn u8(&mut self) -> Result<u8, SmfError> {
+        let byte = *self.bytes.get(self.pos).ok_or(SmfError::Truncated)?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], SmfError> {
+        let end = self.pos.checked_add(len).ok_or(SmfError::Truncated)?;
+        let bytes = self.bytes.get(self.pos..end).ok_or(SmfError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    // A variable-length quantity: 7 bits per byte, most significant first, with the top bit set on all but the last
+    fn vlq(&mut self) -> Result<u32, SmfError> {
+        let mut value: u32 = 0;
+
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(SmfError::InvalidVlq)
+    }
+
+    fn chunk(&mut self) -> Result<(&'a [u8], &'a [u8]), SmfError> {
+        let chunk_type = self.bytes(4)?;
+        let len = u32::from_be_bytes(self.bytes(4)?.try_into().map_err(|_| SmfError::Truncated)?);
+        let data = self.bytes(len as usize)?;
+        Ok((chunk_type, data))
+    }
+}
+
+// ------
+// WRITER
+// ------
+
+type Track = Vec<(u32, SmfEvent)>;
+
+// Writes a format 0 or 1 file. tempo_map is a list of {absolute_ticks, microseconds_per_quarter}, which is added as
+// its own first track in format 1, or merged into the track in format 0 (as are all tracks in format 0). Every track
+// is given a single end of track event at its end.
+pub fn write(
+    smf: Smf,
+    tempo_map: &[(u32, u32)],
+    running_status: bool,
+) -> Result<Vec<u8>, SmfError> {
+    if smf.format > 1 {
+        return Err(SmfError::InvalidHeader(format!(
+            "only formats 0 and 1 can be written, not format {}",
+            smf.format
+        )));
+    }
+
+    let mut tracks: Vec<Track> = smf.tracks;
+
+    if !tempo_map.is_empty() {
+        tracks.insert(0, tempo_track(tempo_map));
+    }
+    if smf.format == 0 && tracks.len() != 1 {
+        tracks = vec![merge_tracks(tracks)];
+    }
+
+    let mut bytes = Vec::new();
+    let division = match smf.division {
+        Division::Ppq(ticks) => {
+            if ticks & 0x8000 != 0 {
+                return Err(SmfError::InvalidHeader(format!(
+                    "{} ticks per quarter note is more than the maximum of 32767",
+                    ticks
+                )));
+            }
+            ticks
+        }
+        Division::Smpte(fps, ticks) => {
+            if !matches!(fps, 24 | 25 | 29 | 30) {
+                return Err(SmfError::InvalidHeader(format!(
+                    "unknown SMPTE frame rate {}",
+                    fps
+                )));
+            }
+            (((-(fps as i8)) as u8 as u16) << 8) | ticks as u16
+        }
+    };
+    if tracks.len() > u16::MAX as usize {
+        return Err(SmfError::InvalidHeader(format!(
+            "{} tracks is more than the maximum of {}",
+            tracks.len(),
+            u16::MAX
+        )));
+    }
+
+    let mut header = Vec::with_capacity(6);
+    header.extend_from_slice(&smf.format.to_be_bytes());
+    header.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+    header.extend_from_slice(&division.to_be_bytes());
+    write_chunk(&mut bytes, HEADER_MAGIC, &header);
+
+    for track in tracks {
+        let data = write_track(with_end_of_track(track), running_status)?;
+        write_chunk(&mut bytes, TRACK_MAGIC, &data);
+    }
+
+    Ok(bytes)
+}
+
+fn tempo_track(tempo_map: &[(u32, u32)]) -> Track {
+    let mut tempo_map = tempo_map.to_vec();
+    tempo_map.sort_by_key(|(ticks, _)| *ticks);
+
+    let mut last = 0;
+    tempo_map
+        .into_iter()
+        .map(|(ticks, tempo)| {
+            let delta = ticks - last;
+            last = ticks;
+            (delta, SmfEvent::Tempo(tempo))
+        })
+        .collect()
+}
+
+// Merges tracks into one by absolute time, keeping events at the same time in track order
+fn merge_tracks(tracks: Vec<Track>) -> Track {
+    let mut events: Vec<(u64, usize, SmfEvent)> = Vec::new();
+
+    for (i, track) in tracks.into_iter().enumerate() {
+        let mut time: u64 = 0;
+        for (delta, event) in track {
+            time += delta as u64;
+            events.push((time, i, event));
+        }
+    }
+
+    // Stable, so each track's events stay in order
+    events.sort_by_key(|(time, i, _)| (*time, *i));
+
+    let mut last = 0;
+    events
+        .into_iter()
+        .map(|(time, _, event)| {
+            let delta = (time - last).min(u32::MAX as u64) as u32;
+            last = time;
+            (delta, event)
+        })
+        .collect()
+}
+
+// Removes any end of track events, keeping their delta times, and adds one at the end
+fn with_end_of_track(track: Track) -> Track {
+    let mut events: Track = Vec::with_capacity(track.len() + 1);
+    let mut carried: u32 = 0;
+
+    for (delta, event) in track {
+        let delta = delta.saturating_add(carried);
+
+        match event {
+            SmfEvent::EndOfTrack => carried = delta,
+            event => {
+                carried = 0;
+                events.push((delta, event));
+            }
+        }
+    }
+
+    events.push((carried, SmfEvent::EndOfTrack));
+    events
+}
+
+fn write_track(track: Track, running_status: bool) -> Result<Vec<u8>, SmfError> {
+    let mut bytes = Vec::new();
+    let mut last_status: Option<u8> = None;
+
+    for (delta, event) in track {
+        write_vlq(&mut bytes, delta)?;
+
+        match event {
+            SmfEvent::Midi(msg) => {
+                let status = check_channel_message(&msg)?;
+
+                if running_status && last_status == Some(status) {
+                    bytes.extend_from_slice(&msg[1..]);
+                } else {
+                    bytes.extend_from_slice(&msg);
+                }
+                last_status = Some(status);
+            }
+            event => {
+                // System exclusive and meta events cancel running status
+                last_status = None;
+                write_non_midi_event(&mut bytes, event)?;
+            }
+        }
+    }
+
+    Ok(bytes)
+}
+
+fn check_channel_message(msg: &[u8]) -> Result<u8, SmfError> {
+    let status = match msg.first() {
+        Some(status @ 0x80..=0xEF) => *status,
+        _ => {
+            return Err(SmfError::InvalidEvent(format!(
+                "{:02X?} isn't a channel message",
+                msg
+            )))
+        }
+    };
+
+    let len = match status & 0xF0 {
+        0xC0 | 0xD0 => 2,
+        _ => 3,
+    };
+
+    if msg.len() != len || msg[1..].iter().any(|byte| *byte >= 0x80) {
+        return Err(SmfError::InvalidEvent(format!(
+            "{:02X?} isn't a complete channel message",
+            msg
+        )));
+    }
+
+    Ok(status)
+}
+
+fn write_non_midi_event(bytes: &mut Vec<u8>, event: SmfEvent) -> Result<(), SmfError> {
+    match event {
+        SmfEvent::Sysex(msg) => match msg.split_first() {
+            Some((0xF0, data)) => {
+                bytes.push(0xF0);
+                write_vlq(bytes, data.len() as u32)?;
+                bytes.extend_from_slice(data);
+            }
+            _ => {
+                return Err(SmfError::InvalidEvent(
+                    "a sysex event must start with 0xF0".to_string(),
+                ))
+            }
+        },
+        SmfEvent::Escape(data) => {
+            bytes.push(0xF7);
+            write_vlq(bytes, data.len() as u32)?;
+            bytes.extend_from_slice(&data);
+        }
+        SmfEvent::Tempo(tempo) => {
+            if tempo > 0xFFFFFF {
+                return Err(SmfError::InvalidEvent(format!(
+                    "a tempo of {} microseconds per quarter note doesn't fit in 3 bytes",
+                    tempo
+                )));
+            }
+            write_meta(bytes, META_TEMPO, &tempo.to_be_bytes()[1..])?;
+        }
+        SmfEvent::TimeSignature {
+            numerator,
+            denominator,
+            clocks_per_click,
+            thirty_seconds_per_quarter,
+        } => {
+            if !denominator.is_power_of_two() {
+                return Err(SmfError::InvalidEvent(format!(
+                    "a time signature denominator must be a power of 2, not {}",
+                    denominator
+                )));
+            }
+            write_meta(
+                bytes,
+                META_TIME_SIGNATURE,
+                &[
+                    numerator,
+                    denominator.trailing_zeros() as u8,
+                    clocks_per_click,
+                    thirty_seconds_per_quarter,
+                ],
+            )?;
+        }
+        SmfEvent::KeySignature {
+            sharps_flats,
+            minor,
+        } => write_meta(
+            bytes,
+            META_KEY_SIGNATURE,
+            &[sharps_flats as u8, minor as u8],
+        )?,
+        SmfEvent::TrackName(name) => write_meta(bytes, META_TRACK_NAME, name.as_bytes())?,
+        SmfEvent::EndOfTrack => write_meta(bytes, META_END_OF_TRACK, &[])?,
+        SmfEvent::Meta(meta_type, data) => write_meta(bytes, meta_type, &data)?,
+        SmfEvent::Midi(_) => (),
+    }
+
+    Ok(())
+}
+
+fn write_meta(bytes: &mut Vec<u8>, meta_type: u8, data: &[u8]) -> Result<(), SmfError> {
+    bytes.push(0xFF);
+    bytes.push(meta_type);
+    write_vlq(bytes, data.len() as u32)?;
+    bytes.extend_from_slice(data);
+    Ok(())
+}
+
+fn write_vlq(bytes: &mut Vec<u8>, value: u32) -> Result<(), SmfError> {
+    if value > 0x0FFFFFFF {
+        return Err(SmfError::InvalidVlq);
+    }
+
+    let mut groups = [0u8; 4];
+    let mut len = 0;
+    let mut value = value;
+
+    loop {
+        groups[len] = (value & 0x7F) as u8;
+        len += 1;
+        value >>= 7;
+        if value == 0 {
+            break;
+        }
+    }
+
+    for i in (0..len).rev() {
+        bytes.push(if i > 0 { groups[i] | 0x80 } else { groups[i] });
+    }
+
+    Ok(())
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, chunk_type: &[u8; 4], data: &[u8]) {
+    bytes.extend_from_slice(chunk_type);
+    bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(data);
+}
@@ -0,0 +1,412 @@
+// ---------------------------------------
+// SYSEX REASSEMBLY
+// ---------------------------------------
+// Backends can hand a long System Exclusive message to the input
+// callback in several chunks. The Assembler joins the chunks back
+// into one message, passing on real-time messages interleaved with
+// them, and gives up on SysExs that grow too large or stop arriving.
+// As the callback is only called when something arrives, a watchdog
+// thread shared by every assembler checks for SysExs that have
+// stopped arriving.
+//
+// Going the other way, a dump of SysExs can be sent in small chunks
+// paced on its own thread, for hardware with small input buffers.
+// ---------------------------------------
+
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use crate::error::MidiexError;
+use crate::midi::{self, is_realtime, FramingError, SYSEX_END, SYSEX_START};
+use crate::scheduler::SharedOutConn;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SysexOptions {
+    // The largest SysEx, including its 0xF0 and 0xF7, to reassemble
+    pub max_size: usize,
+    // How long to wait for the next chunk of a SysEx. Zero waits forever.
+    pub timeout: Duration,
+}
+
+impl Default for SysexOptions {
+    fn default() -> Self {
+        Self {
+            max_size: 1024 * 1024,
+            timeout: Duration::from_secs(1),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Assembled {
+    // A complete message and the timestamp of its first chunk
+    Message(u64, Vec<u8>),
+    // The SysEx grew larger than max_size, so the rest of it is dropped
+    Overflow,
+    // The SysEx was ended by a status byte other than 0xF7
+    Unterminated,
+    // No more of the SysEx arrived within the timeout
+    Timeout,
+}
+
+struct Partial {
+    stamp: u64,
+    data: Vec<u8>,
+    last_chunk_at: Instant,
+    overflowed: bool,
+}
+
+pub struct Assembler {
+    options: SysexOptions,
+    partial: Option<Partial>,
+    // Set once a SysEx times out, so the rest of it is dropped if it turns up late, until the next status byte
+    skipping: bool,
+}
+
+impl Assembler {
+    pub fn new(options: SysexOptions) -> Self {
+        Self {
+            options,
+            partial: None,
+            skipping: false,
+        }
+    }
+
+    // Adds a chunk received at stamp, returning the messages (and errors) it completes
+    pub fn push(&mut self, stamp: u64, chunk: &[u8], now: Instant) -> Vec<Assembled> {
+        let mut assembled = Vec::new();
+
+        if self.expire(now) {
+            assembled.push(Assembled::Timeout);
+        }
+
+        let chunk = self.skip_late_chunk(stamp, chunk, &mut assembled);
+
+        // Most chunks are whole messages outside a SysEx, which are passed on as they are
+        if self.partial.is_none() && chunk.first() != Some(&SYSEX_START) {
+            if !chunk.is_empty() {
+                assembled.push(Assembled::Message(stamp, chunk.to_vec()));
+            }
+            return assembled;
+        }
+
+        let mut rest = chunk;
+        while !rest.is_empty() {
+            rest = self.push_bytes(stamp, rest, now, &mut assembled);
+        }
+        assembled
+    }
+
+    // Drops a SysEx which hasn't had a chunk within the timeout, returning true if there was one
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let expired = match &self.partial {
+            Some(partial) => {
+                !self.options.timeout.is_zero()
+                    && now.saturating_duration_since(partial.last_chunk_at) > self.options.timeout
+            }
+            None => false,
+        };
+
+        if expired {
+            self.partial = None;
+            self.skipping = true;
+        }
+        expired
+    }
+
+    // Drops data bytes arriving after a SysEx has timed out, which are the rest of it, returning the bytes from the
+    // next status byte on
+    fn skip_late_chunk<'a>(
+        &mut self,
+        stamp: u64,
+        chunk: &'a [u8],
+        assembled: &mut Vec<Assembled>,
+    ) -> &'a [u8] {
+        if !self.skipping || self.partial.is_some() {
+            return chunk;
+        }
+
+        for (i, byte) in chunk.iter().copied().enumerate() {
+            if is_realtime(byte) {
+                assembled.push(Assembled::Message(stamp, vec![byte]));
+            } else if byte == SYSEX_END {
+                self.skipping = false;
+                return &chunk[i + 1..];
+            } else if byte >= 0x80 {
+                self.skipping = false;
+                return &chunk[i..];
+            }
+        }
+        &[]
+    }
+
+    // Takes bytes from the start of chunk, returning the ones left for the next call
+    fn push_bytes<'a>(
+        &mut self,
+        stamp: u64,
+        chunk: &'a [u8],
+        now: Instant,
+        assembled: &mut Vec<Assembled>,
+    ) -> &'a [u8] {
+        let partial = match self.partial.as_mut() {
+            Some(partial) => partial,
+            None if chunk[0] == SYSEX_START => self.partial.insert(Partial {
+                stamp,
+                data: Vec::new(),
+                last_chunk_at: now,
+                overflowed: false,
+            }),
+            // A message following a SysEx in the same chunk. Anything after it is left to the backend's framing.
+            None => {
+                assembled.push(Assembled::Message(stamp, chunk.to_vec()));
+                return &[];
+            }
+        };
+        partial.last_chunk_at = now;
+
+        for (i, byte) in chunk.iter().copied().enumerate() {
+            if is_realtime(byte) {
+                assembled.push(Assembled::Message(stamp, vec![byte]));
+                continue;
+            }
+
+            let starts_sysex =
+                byte == SYSEX_START && partial.data.is_empty() && !partial.overflowed;
+            if byte >= 0x80 && byte != SYSEX_END && !starts_sysex {
+                // Any other status byte ends the SysEx, and is the start of the next message
+                if !partial.overflowed {
+                    assembled.push(Assembled::Unterminated);
+                }
+                self.partial = None;
+                return &chunk[i..];
+            }
+
+            if !partial.overflowed {
+                if partial.data.len() < self.options.max_size {
+                    partial.data.push(byte);
+                } else {
+                    partial.overflowed = true;
+                    partial.data = Vec::new();
+                    assembled.push(Assembled::Overflow);
+                }
+            }
+
+            if byte == SYSEX_END {
+                if let Some(partial) = self.partial.take() {
+                    if !partial.overflowed {
+                        assembled.push(Assembled::Message(partial.stamp, partial.data));
+                    }
+                }
+                return &chunk[i + 1..];
+            }
+        }
+
+        &[]
+    }
+}
+
+struct Watched {
+    assembler: Weak<Mutex<Assembler>>,
+    on_timeout: Box<dyn FnMut() + Send>,
+}
+
+#[derive(Default)]
+struct Watchdog {
+    watched: Vec<Watched>,
+    running: bool,
+}
+
+lazy_static! {
+    static ref WATCHDOG: Mutex<Watchdog> = Mutex::new(Watchdog::default());
+}
+
+// Calls on_timeout whenever the assembler drops a SysEx for timing out. One thread checks every assembler, a few
+// times within the shortest timeout, until they've all been dropped.
+pub fn spawn_watchdog<F>(assembler: Weak<Mutex<Assembler>>, on_timeout: F)
+where
+    F: FnMut() + Send + 'static,
+{
+    let mut watchdog = WATCHDOG.lock().unwrap_or_else(|e| e.into_inner());
+    watchdog.watched.push(Watched {
+        assembler,
+        on_timeout: Box::new(on_timeout),
+    });
+
+    if !watchdog.running {
+        watchdog.running = true;
+        std::thread::spawn(run_watchdog);
+    }
+}
+
+fn run_watchdog() {
+    let max_interval = Duration::from_millis(50);
+    let mut interval = max_interval;
+
+    loop {
+        std::thread::sleep(interval);
+
+        let mut watchdog = WATCHDOG.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        interval = max_interval;
+
+        watchdog.watched.retain_mut(|watched| {
+            let assembler = match watched.assembler.upgrade() {
+                Some(assembler) => assembler,
+                None => return false,
+            };
+            let mut assembler = match assembler.lock() {
+                Ok(assembler) => assembler,
+                Err(_) => return false,
+            };
+
+            if assembler.expire(now) {
+                (watched.on_timeout)();
+            }
+            let timeout = assembler.options.timeout;
+            if !timeout.is_zero() {
+                interval = interval.min((timeout / 4).max(Duration::from_millis(1)));
+            }
+            true
+        });
+
+        if watchdog.watched.is_empty() {
+            watchdog.running = false;
+            return;
+        }
+    }
+}
+
+// ---------------------------------------
+// SYSEX TRANSMISSION
+// ---------------------------------------
+
+// Splits a dump into its SysExs (the packets sent one at a time in handshake mode), checking each is well formed
+pub fn packets(dump: &[u8]) -> Result<Vec<Vec<u8>>, FramingError> {
+    if dump.is_empty() {
+        return Err(FramingError::Empty);
+    }
+
+    let mut packets = Vec::new();
+    let mut rest = dump;
+
+    while !rest.is_empty() {
+        if rest[0] != SYSEX_START {
+            return Err(match rest[0] {
+                0x00..=0x7F => FramingError::MissingStatus,
+                SYSEX_END => FramingError::UnexpectedEndOfExclusive,
+                _ => FramingError::InvalidDataByte,
+            });
+        }
+
+        let end = rest
+            .iter()
+            .position(|byte| *byte == SYSEX_END)
+            .ok_or(FramingError::UnterminatedSysex)?;
+        let (packet, next) = rest.split_at(end + 1);
+
+        midi::validate(packet)?;
+        packets.push(packet.to_vec());
+        rest = next;
+    }
+
+    Ok(packets)
+}
+
+pub struct Handshake {
+    // SysExs received on the paired input port
+    pub received: Receiver<Vec<u8>>,
+    // A SysEx starting with these bytes acknowledges a packet. If empty, any SysEx does.
+    pub ack: Vec<u8>,
+    pub timeout: Duration,
+}
+
+pub struct TransmitOptions {
+    // The most bytes to send at once, 0 sending each packet whole
+    pub chunk_size: usize,
+    pub inter_chunk_delay: Duration,
+    // If given, each packet has to be acknowledged before the next is sent (and before the dump is done)
+    pub handshake: Option<Handshake>,
+}
+
+pub enum TransmitUpdate {
+    // Bytes sent so far, and the total to send
+    Progress(usize, usize),
+    Done,
+    Failed(MidiexError),
+}
+
+// Sends the packets on a new thread, calling on_update after each chunk, then once it's done or has failed
+pub fn spawn_transmit<F>(
+    conn: SharedOutConn,
+    packets: Vec<Vec<u8>>,
+    options: TransmitOptions,
+    mut on_update: F,
+) where
+    F: FnMut(TransmitUpdate) + Send + 'static,
+{
+    std::thread::spawn(move || {
+        let update = match transmit(&conn, &packets, &options, &mut on_update) {
+            Ok(()) => TransmitUpdate::Done,
+            Err(error) => TransmitUpdate::Failed(error),
+        };
+        on_update(update);
+    });
+}
+
+fn transmit<F>(
+    conn: &SharedOutConn,
+    packets: &[Vec<u8>],
+    options: &TransmitOptions,
+    on_update: &mut F,
+) -> Result<(), MidiexError>
+where
+    F: FnMut(TransmitUpdate),
+{
+    let total: usize = packets.iter().map(|packet| packet.len()).sum();
+    // WinMM sends a SysEx with midiOutLongMsg, which takes it whole
+    let chunk_size = match options.chunk_size {
+        0 => usize::MAX,
+        _ if cfg!(target_os = "windows") => usize::MAX,
+        chunk_size => chunk_size,
+    };
+    let mut sent = 0;
+
+    for packet in packets {
+        for chunk in packet.chunks(chunk_size) {
+            if sent > 0 && !options.inter_chunk_delay.is_zero() {
+                std::thread::sleep(options.inter_chunk_delay);
+            }
+
+            conn.lock()?
+                .as_mut()
+                .ok_or(MidiexError::ConnectionClosed)?
+                .send(chunk)?;
+
+            sent += chunk.len();
+            on_update(TransmitUpdate::Progress(sent, total));
+        }
+
+        if let Some(handshake) = &options.handshake {
+            wait_for_ack(handshake)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn wait_for_ack(handshake: &Handshake) -> Result<(), MidiexError> {
+    let deadline = Instant::now() + handshake.timeout;
+
+    loop {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        let received = handshake
+            .received
+            .recv_timeout(wait)
+            .map_err(|_| MidiexError::HandshakeTimeout)?;
+
+        if received.starts_with(&handshake.ack) {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,325 @@
+// ---------------------------------------
+// UNIVERSAL MIDI PACKETS
+// ---------------------------------------
+// MIDI 2.0 carries messages in Universal MIDI Packets (UMP) of one to
+// four 32-bit words, the top nibble of the first word being the
+// message type and the next nibble the group. MIDI 1.0 messages are
+// converted to system (0x1) packets, SysEx7 (0x3) packets, and either
+// MIDI 1.0 (0x2) or MIDI 2.0 (0x4) channel voice packets, the latter
+// with their values scaled up as the UMP specification describes.
+// Converting back, packets with no MIDI 1.0 equivalent (utility,
+// SysEx8 and mixed data, flex data, UMP stream, per-note and relative
+// controllers) are dropped.
+// ---------------------------------------
+
+use std::fmt;
+
+use crate::midi::{self, is_realtime, message_len, FramingError, SYSEX_END, SYSEX_START};
+
+// Message types
+const SYSTEM: u8 = 0x1;
+const MIDI1_CHANNEL_VOICE: u8 = 0x2;
+const SYSEX7: u8 = 0x3;
+const MIDI2_CHANNEL_VOICE: u8 = 0x4;
+
+// SysEx7 packet statuses
+const SYSEX7_COMPLETE: u8 = 0x0;
+const SYSEX7_START: u8 = 0x1;
+const SYSEX7_CONTINUE: u8 = 0x2;
+const SYSEX7_END: u8 = 0x3;
+
+// MIDI 2.0 channel voice statuses with no MIDI 1.0 status byte of their own
+const REGISTERED_CONTROLLER: u8 = 0x2;
+const ASSIGNABLE_CONTROLLER: u8 = 0x3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    // Channel voice messages are sent as MIDI 1.0 channel voice packets (message type 0x2)
+    Midi1,
+    // Channel voice messages are sent as MIDI 2.0 channel voice packets (message type 0x4)
+    Midi2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UmpError {
+    // The words end part way through a packet
+    Truncated,
+}
+
+impl fmt::Display for UmpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UmpError::Truncated => "the words end part way through a packet".fmt(f),
+        }
+    }
+}
+
+// The number of 32-bit words in a packet of the given message type
+pub fn packet_len(message_type: u8) -> usize {
+    match message_type & 0x0F {
+        0x0..=0x2 | 0x6 | 0x7 => 1,
+        0x3 | 0x4 | 0x8..=0xA => 2,
+        0xB | 0xC => 3,
+        _ => 4,
+    }
+}
+
+// Splits words into packets, by the message type of each
+pub fn packets(words: &[u32]) -> Result<Vec<&[u32]>, UmpError> {
+    let mut packets = Vec::new();
+    let mut rest = words;
+
+    while let Some(first) = rest.first() {
+        let len = packet_len(message_type(*first));
+        if rest.len() < len {
+            return Err(UmpError::Truncated);
+        }
+        let (packet, next) = rest.split_at(len);
+        packets.push(packet);
+        rest = next;
+    }
+
+    Ok(packets)
+}
+
+// Scales value from src_bits to dst_bits with the specification's min-center-max algorithm, so 0 stays 0, the centre
+// stays the centre (e.g. 64 to 0x8000) and the maximum becomes the maximum
+pub fn scale_up(value: u32, src_bits: u32, dst_bits: u32) -> u32 {
+    let value = value as u64 & ((1 << src_bits) - 1);
+    let scale_bits = dst_bits - src_bits;
+    let mut scaled = value << scale_bits;
+
+    let center = 1 << (src_bits - 1);
+    if value <= center {
+        return scaled as u32;
+    }
+
+    // Above the centre, the bits below the top one are repeated to fill the lower bits
+    let repeat_bits = src_bits - 1;
+    let mut repeat = value & ((1 << repeat_bits) - 1);
+    if scale_bits > repeat_bits {
+        repeat <<= scale_bits - repeat_bits;
+    } else {
+        repeat >>= repeat_bits - scale_bits;
+    }
+    while repeat != 0 {
+        scaled |= repeat;
+        repeat >>= repeat_bits;
+    }
+
+    scaled as u32
+}
+
+// Scales value from src_bits down to dst_bits, by dropping the low bits
+pub fn scale_down(value: u32, src_bits: u32, dst_bits: u32) -> u32 {
+    ((value as u64 & ((1 << src_bits) - 1)) >> (src_bits - dst_bits)) as u32
+}
+
+// Converts a single MIDI 1.0 message to UMP words in the given group (0 to 15)
+pub fn from_midi1(message: &[u8], group: u8, protocol: Protocol) -> Result<Vec<u32>, FramingError> {
+    midi::validate(message)?;
+
+    let group = group & 0x0F;
+    let status = message[0];
+    let data = |i: usize| message.get(i).copied().unwrap_or(0);
+
+    let words = match status {
+        SYSEX_START => sysex7(message, group),
+        0xF1..=0xFF => vec![word(SYSTEM, group, status, data(1), data(2))],
+        _ => match protocol {
+            Protocol::Midi1 => vec![word(MIDI1_CHANNEL_VOICE, group, status, data(1), data(2))],
+            Protocol::Midi2 => midi2_channel_voice(status, data(1), data(2), group).to_vec(),
+        },
+    };
+
+    Ok(words)
+}
+
+// Converts UMP words to MIDI 1.0 messages. A SysEx split across SysEx7 packets is joined back into one message.
+pub fn to_midi1(words: &[u32]) -> Result<Vec<Vec<u8>>, UmpError> {
+    let mut messages = Vec::new();
+    // A SysEx started, but not yet ended
+    let mut sysex: Option<Vec<u8>> = None;
+
+    for packet in packets(words)? {
+        let bytes = packet[0].to_be_bytes();
+        let status = bytes[1];
+
+        match message_type(packet[0]) {
+            SYSTEM | MIDI1_CHANNEL_VOICE => {
+                // 0xF0 and 0xF7 have no place in a system packet, and undefined statuses are dropped
+                if let (true, Some(len)) = (status >= 0x80, message_len(status)) {
+                    messages.push([status, bytes[2] & 0x7F, bytes[3] & 0x7F][..len].to_vec());
+                }
+            }
+            SYSEX7 => {
+                let count = ((status & 0x0F) as usize).min(6);
+                let payload = packet
+                    .iter()
+                    .flat_map(|word| word.to_be_bytes())
+                    .skip(2)
+                    .take(count)
+                    .map(|byte| byte & 0x7F);
+
+                match status >> 4 {
+                    SYSEX7_COMPLETE => {
+                        sysex = None;
+                        messages.push(framed_sysex(payload.collect()));
+                    }
+                    SYSEX7_START => sysex = Some(payload.collect()),
+                    SYSEX7_CONTINUE => {
+                        if let Some(sysex) = sysex.as_mut() {
+                            sysex.extend(payload);
+                        }
+                    }
+                    SYSEX7_END => {
+                        if let Some(mut data) = sysex.take() {
+                            data.extend(payload);
+                            messages.push(framed_sysex(data));
+                        }
+                    }
+                    _ => (),
+                }
+            }
+            MIDI2_CHANNEL_VOICE => messages.extend(midi1_channel_voice(packet[0], packet[1])),
+            _ => (),
+        }
+    }
+
+    Ok(messages)
+}
+
+fn message_type(first_word: u32) -> u8 {
+    (first_word >> 28) as u8
+}
+
+fn word(message_type: u8, group: u8, b1: u8, b2: u8, b3: u8) -> u32 {
+    u32::from_be_bytes([message_type << 4 | group, b1, b2, b3])
+}
+
+// Splits a SysEx into SysEx7 packets of up to 6 bytes, without its 0xF0 and 0xF7. Real-time messages interleaved with
+// the SysEx are sent as system packets ahead of it.
+fn sysex7(message: &[u8], group: u8) -> Vec<u32> {
+    let payload: Vec<u8> = message[1..message.len() - 1]
+        .iter()
+        .copied()
+        .filter(|byte| !is_realtime(*byte))
+        .collect();
+
+    let mut words: Vec<u32> = message[1..]
+        .iter()
+        .filter(|byte| is_realtime(**byte))
+        .map(|status| word(SYSTEM, group, *status, 0, 0))
+        .collect();
+
+    let chunks: Vec<&[u8]> = match payload.is_empty() {
+        true => vec![&[]],
+        false => payload.chunks(6).collect(),
+    };
+    let last = chunks.len() - 1;
+
+    for (i, chunk) in chunks.iter().enumerate() {
+        let status = match (i, last) {
+            (0, 0) => SYSEX7_COMPLETE,
+            (0, _) => SYSEX7_START,
+            (i, last) if i == last => SYSEX7_END,
+            _ => SYSEX7_CONTINUE,
+        };
+
+        let mut bytes = [0u8; 8];
+        bytes[0] = SYSEX7 << 4 | group;
+        bytes[1] = status << 4 | chunk.len() as u8;
+        bytes[2..2 + chunk.len()].copy_from_slice(chunk);
+
+        words.push(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+        words.push(u32::from_be_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]));
+    }
+
+    words
+}
+
+fn framed_sysex(data: Vec<u8>) -> Vec<u8> {
+    let mut message = Vec::with_capacity(data.len() + 2);
+    message.push(SYSEX_START);
+    message.extend(data);
+    message.push(SYSEX_END);
+    message
+}
+
+// Converts a MIDI 1.0 channel voice message to a MIDI 2.0 channel voice packet, scaling its values up
+fn midi2_channel_voice(status: u8, data1: u8, data2: u8, group: u8) -> [u32; 2] {
+    let channel = status & 0x0F;
+    let up = |value: u8| scale_up(value as u32, 7, 32);
+
+    let (status, index1, data) = match status >> 4 {
+        0x8 => (status, data1, scale_up(data2 as u32, 7, 16) << 16),
+        // A Note On with velocity 0 ends a note in MIDI 1.0 but not in MIDI 2.0, so it's sent as a Note Off, with the
+        // default velocity of 64 (as decode does)
+        0x9 if data2 == 0 => (0x80 | channel, data1, scale_up(64, 7, 16) << 16),
+        0x9 => (status, data1, scale_up(data2 as u32, 7, 16) << 16),
+        0xA | 0xB => (status, data1, up(data2)),
+        // Without a bank, as Bank Select is a separate Control Change in MIDI 1.0
+        0xC => (status, 0, (data1 as u32) << 24),
+        0xD => (status, 0, up(data1)),
+        _ => {
+            let bend = (data2 as u32) << 7 | data1 as u32;
+            (status, 0, scale_up(bend, 14, 32))
+        }
+    };
+
+    [word(MIDI2_CHANNEL_VOICE, group, status, index1, 0), data]
+}
+
+// Converts a MIDI 2.0 channel voice packet to MIDI 1.0 messages, scaling its values down
+fn midi1_channel_voice(first: u32, data: u32) -> Vec<Vec<u8>> {
+    let [_, status, index1, index2] = first.to_be_bytes();
+    let channel = status & 0x0F;
+    let index1 = index1 & 0x7F;
+    let index2 = index2 & 0x7F;
+    let down = |value: u32| scale_down(value, 32, 7) as u8;
+    let control = |control: u8, value: u8| vec![0xB0 | channel, control, value];
+
+    match status >> 4 {
+        0x8 => vec![vec![status, index1, scale_down(data >> 16, 16, 7) as u8]],
+        // Velocity 0 would end the note, so the lowest a MIDI 2.0 Note On can become is 1
+        0x9 => vec![vec![
+            status,
+            index1,
+            (scale_down(data >> 16, 16, 7) as u8).max(1),
+        ]],
+        0xA | 0xB => vec![vec![status, index1, down(data)]],
+        0xC => {
+            let program = vec![status, (data >> 24) as u8 & 0x7F];
+            // Bit 0 of the option flags is set when the bank is valid
+            match index2 & 0x01 {
+                0 => vec![program],
+                _ => vec![
+                    control(0, (data >> 8) as u8 & 0x7F),
+                    control(32, data as u8 & 0x7F),
+                    program,
+                ],
+            }
+        }
+        0xD => vec![vec![status, down(data)]],
+        0xE => {
+            let bend = scale_down(data, 32, 14);
+            vec![vec![status, (bend & 0x7F) as u8, (bend >> 7) as u8]]
+        }
+        // RPNs and NRPNs are sent as the Control Change sequence MIDI 1.0 uses for them
+        kind @ (REGISTERED_CONTROLLER | ASSIGNABLE_CONTROLLER) => {
+            let (msb, lsb) = match kind {
+                REGISTERED_CONTROLLER => (101, 100),
+                _ => (99, 98),
+            };
+            let value = scale_down(data, 32, 14);
+            vec![
+                control(msb, index1),
+                control(lsb, index2),
+                control(6, (value >> 7) as u8),
+                control(38, (value & 0x7F) as u8),
+            ]
+        }
+        // Per-note and relative controllers, per-note pitch bend and per-note management
+        _ => Vec::new(),
+    }
+}
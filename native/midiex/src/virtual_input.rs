@@ -0,0 +1,90 @@
+// ---------------------------------------
+// VIRTUAL INPUTS
+// ---------------------------------------
+// A virtual input port exists from when it's created until it's
+// closed, whether or not anything is subscribed to it. Its input
+// callback passes each message to a Dispatcher, which calls every
+// subscriber's callback in turn. While there are no subscribers the
+// most recent messages are kept, up to the buffer size, and passed
+// to the next subscriber; the rest are dropped.
+// ---------------------------------------
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+pub type Callback = Box<dyn FnMut(u64, &[u8]) + Send>;
+
+pub struct Dispatcher(Mutex<State>);
+
+struct State {
+    subscribers: Vec<(u64, Callback)>,
+    next_id: u64,
+    buffer: VecDeque<(u64, Vec<u8>)>,
+    buffer_size: usize,
+}
+
+impl Dispatcher {
+    pub fn new(buffer_size: usize) -> Arc<Self> {
+        Arc::new(Self(Mutex::new(State {
+            subscribers: Vec::new(),
+            next_id: 0,
+            buffer: VecDeque::new(),
+            buffer_size,
+        })))
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.0.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn dispatch(&self, stamp: u64, message: &[u8]) {
+        let mut state = self.state();
+
+        if state.subscribers.is_empty() {
+            if state.buffer_size > 0 {
+                if state.buffer.len() == state.buffer_size {
+                    state.buffer.pop_front();
+                }
+                state.buffer.push_back((stamp, message.to_vec()));
+            }
+            return;
+        }
+
+        for (_, callback) in state.subscribers.iter_mut() {
+            callback(stamp, message);
+        }
+    }
+
+    // Adds a subscriber, first passing it any messages buffered while there were none
+    pub fn subscribe(self: &Arc<Self>, mut callback: Callback) -> Subscription {
+        let mut state = self.state();
+
+        for (stamp, message) in state.buffer.drain(..) {
+            callback(stamp, &message);
+        }
+
+        state.next_id += 1;
+        let id = state.next_id;
+        state.subscribers.push((id, callback));
+
+        Subscription {
+            dispatcher: self.clone(),
+            id,
+        }
+    }
+}
+
+pub struct Subscription {
+    dispatcher: Arc<Dispatcher>,
+    id: u64,
+}
+
+impl Subscription {
+    // Removes the subscriber, whose callback won't be called again once this returns
+    pub fn close(self) {
+        self.dispatcher
+            .state()
+            .subscribers
+            .retain(|(id, _)| *id != self.id);
+    }
+}
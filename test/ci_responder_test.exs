@@ -0,0 +1,110 @@
+defmodule MidiexCIResponderTest do
+  use ExUnit.Case, async: false
+
+  alias Midiex.CI
+  alias Midiex.CI.Message
+  alias Midiex.Loopback
+
+  # The responder listens on Loopback A and answers on Loopback B
+  @muid 0x0100
+  @peer 0x0200
+  @profile <<0x7E, 0, 1, 1, 0>>
+
+  setup do
+    Loopback.enable(["Loopback A", "Loopback B"])
+
+    on_exit(fn ->
+      Midiex.unsubscribe(:all)
+      Loopback.disable()
+    end)
+
+    [to_responder] = Midiex.ports("Loopback A", :output)
+    [input] = Midiex.ports("Loopback A", :input)
+    [replies_out] = Midiex.ports("Loopback B", :output)
+    [replies_in] = Midiex.ports("Loopback B", :input)
+
+    Midiex.subscribe(replies_in)
+
+    responder =
+      CI.start_responder(Midiex.open(replies_out), input,
+        muid: @muid,
+        family: 300,
+        profiles: [{@profile, false}],
+        properties: %{"DeviceInfo" => ~s({"model":"Loopback"})}
+      )
+
+    %{responder: responder, out_conn: Midiex.open(to_responder)}
+  end
+
+  defp send_ci(out_conn, type, body) do
+    Midiex.send_msg(out_conn, CI.build(%Message{type: type, source: @peer, destination: @muid, body: body}))
+  end
+
+  defp discover(out_conn, max_sysex_size \\ 4096) do
+    send_ci(out_conn, :discovery, %{manufacturer: <<0x7D, 0, 0>>, version: <<0, 0, 0, 0>>, max_sysex_size: max_sysex_size})
+  end
+
+  defp next_reply() do
+    assert_receive %Midiex.MidiMessage{data: data}
+    CI.parse(:erlang.list_to_binary(data))
+  end
+
+  test "replies to discovery and keeps the peer", %{responder: responder, out_conn: out_conn} do
+    discover(out_conn)
+
+    assert %Message{type: :discovery_reply, source: @muid, destination: @peer, body: %{family: 300, function_block: 0x7F}} = next_reply()
+    assert_receive {:midiex_ci, _id, %Message{type: :discovery, source: @peer}}
+    assert [%{muid: @peer, max_sysex_size: 4096}] = CI.Responder.peers(responder)
+  end
+
+  test "turns profiles on and off, and NAKs unknown ones", %{out_conn: out_conn} do
+    send_ci(out_conn, :set_profile_on, %{profile: @profile, channels: 1})
+    assert %Message{type: :profile_enabled, destination: 0x0FFFFFFF, body: %{profile: @profile}} = next_reply()
+    assert_receive {:midiex_ci, _id, {:profile, @profile, true}}
+
+    send_ci(out_conn, :set_profile_off, %{profile: @profile})
+    assert %Message{type: :profile_disabled, body: %{profile: @profile}} = next_reply()
+    assert_receive {:midiex_ci, _id, {:profile, @profile, false}}
+
+    send_ci(out_conn, :set_profile_on, %{profile: <<0x7E, 0, 9, 9, 0>>})
+    assert %Message{type: :nak, destination: @peer, body: %{original: 0x22}} = next_reply()
+  end
+
+  test "splits property data into chunks the peer can take", %{responder: responder, out_conn: out_conn} do
+    discover(out_conn, 128)
+    assert %Message{type: :discovery_reply} = next_reply()
+
+    data = String.duplicate("x", 200)
+    CI.Responder.set_property(responder, "Patch", data)
+    send_ci(out_conn, :get_property, %{request_id: 1, header: ~s({"resource":"Patch"}), chunks: 1, chunk: 1})
+
+    assert %Message{type: :get_property_reply, body: %{header: ~s({"status":200}), chunks: 3, chunk: 1, data: first}} = next_reply()
+    assert %Message{body: %{header: "", chunk: 2, data: second}} = next_reply()
+    assert %Message{body: %{chunk: 3, data: third}} = next_reply()
+    assert data == first <> second <> third
+
+    send_ci(out_conn, :get_property, %{request_id: 2, header: ~s({"resource":"Missing"}), chunks: 1, chunk: 1})
+    assert %Message{type: :get_property_reply, body: %{request_id: 2, header: ~s({"status":404})}} = next_reply()
+  end
+
+  test "reassembles properties set in chunks", %{out_conn: out_conn} do
+    send_ci(out_conn, :set_property, %{request_id: 3, header: ~s({"resource":"DeviceInfo"}), chunks: 2, chunk: 1, data: ~s({"model":)})
+    send_ci(out_conn, :set_property, %{request_id: 3, chunks: 2, chunk: 2, data: ~s("Renamed"})})
+
+    assert %Message{type: :set_property_reply, body: %{request_id: 3, header: ~s({"status":200})}} = next_reply()
+    assert_receive {:midiex_ci, _id, {:property, "DeviceInfo", ~s({"model":"Renamed"})}}
+
+    send_ci(out_conn, :get_property, %{request_id: 4, header: ~s({"resource":"DeviceInfo"}), chunks: 1, chunk: 1})
+    assert %Message{body: %{data: ~s({"model":"Renamed"})}} = next_reply()
+  end
+
+  test "forgets peers which invalidate their MUID", %{responder: responder, out_conn: out_conn} do
+    discover(out_conn)
+    assert %Message{type: :discovery_reply} = next_reply()
+    assert [%{muid: @peer}] = CI.Responder.peers(responder)
+
+    Midiex.send_msg(out_conn, CI.build(%Message{type: :invalidate_muid, source: @peer, body: %{target: @peer}}))
+    assert_receive {:midiex_ci, _id, %Message{type: :invalidate_muid, body: %{target: @peer}}}
+    assert [] = CI.Responder.peers(responder)
+  end
+end
@@ -0,0 +1,42 @@
+defmodule MidiexCITest do
+  use ExUnit.Case, async: true
+
+  alias Midiex.CI
+  alias Midiex.CI.Message
+
+  test "parse and build an Invalidate MUID message" do
+    sysex = <<0xF0, 0x7E, 0x7F, 0x0D, 0x7E, 0x02, 1, 0, 0, 0, 0x7F, 0x7F, 0x7F, 0x7F, 1, 0, 0, 0, 0xF7>>
+
+    assert %Message{type: :invalidate_muid, source: 1, destination: 0x0FFFFFFF, body: %{target: 1}} = message = CI.parse(sysex)
+    assert ^sysex = CI.build(message)
+  end
+
+  test "build a discovery message and parse it back" do
+    message = %Message{
+      type: :discovery,
+      source: 0x1234567,
+      body: %{manufacturer: <<0x7D, 0, 0>>, family: 300, model: 2, version: <<0, 0, 1, 0>>, categories: 0x0E, max_sysex_size: 4096, output_path: 0}
+    }
+
+    assert <<0xF0, 0x7E, 0x7F, 0x0D, 0x70, 0x02, _::binary>> = sysex = CI.build(message)
+    assert ^message = CI.parse(sysex)
+  end
+
+  test "build property exchange and profile messages with left out keys" do
+    get = %Message{type: :get_property, source: 1, destination: 2, body: %{request_id: 1, header: ~s({"resource":"DeviceInfo"}), chunks: 1, chunk: 1}}
+    assert %Message{body: %{header: ~s({"resource":"DeviceInfo"}), data: ""}} = get |> CI.build() |> CI.parse()
+
+    reply = %Message{type: :profile_inquiry_reply, source: 1, destination: 2, body: %{enabled: [<<0x7E, 0, 1, 1, 0>>]}}
+    assert %Message{body: %{enabled: [<<0x7E, 0, 1, 1, 0>>], disabled: []}} = reply |> CI.build() |> CI.parse()
+  end
+
+  test "reject SysExs which aren't MIDI-CI" do
+    assert {:error, {:invalid_ci, _}} = CI.parse(<<0xF0, 0x7E, 0x7F, 0x06, 0x01, 0xF7>>)
+    assert {:error, {:invalid_ci, _}} = CI.parse(<<0xF0, 0x7E, 0x7F, 0x0D, 0x70, 0x02, 1, 0, 0xF7>>)
+    assert {:error, {:invalid_ci, _}} = CI.build(%Message{type: :invalidate_muid, source: 1, body: %{target: 0x10000000}})
+  end
+
+  test "pick MUIDs outside the reserved range" do
+    for _ <- 1..100, do: assert(CI.new_muid() < 0x0FFFFF00)
+  end
+end
@@ -0,0 +1,40 @@
+defmodule MidiexDecodeTest do
+  use ExUnit.Case, async: true
+
+  alias Midiex.Message, as: M
+
+  test "decode channel voice messages" do
+    assert {:note_on, 2, 60, 40} = Midiex.decode(M.note_on(:C4, 40, channel: 2))
+    assert {:note_off, 0, 60, 123} = Midiex.decode(M.note_off(:C4))
+    assert {:control_change, 0, 7, 100} = Midiex.decode(<<0xB0, 7, 100>>)
+    assert {:program_change, 1, 5} = Midiex.decode(<<0xC1, 5>>)
+    assert {:pitch_bend, 0, 8192} = Midiex.decode(M.pitch_bend(8192))
+    assert {:pitch_bend, 0, 16383} = Midiex.decode(M.pitch_bend(16383))
+  end
+
+  test "decode note on with velocity 0 as note off" do
+    assert {:note_on, 0, 60, 0} = Midiex.decode(<<0x90, 60, 0>>)
+    assert {:note_off, 0, 60, 64} = Midiex.decode(<<0x90, 60, 0>>, note_off: true)
+  end
+
+  test "decode channel mode messages" do
+    assert {:all_notes_off, 0} = Midiex.decode(M.all_notes_off())
+    assert {:local_control, 0, true} = Midiex.decode(<<0xB0, 122, 127>>)
+    assert {:mono_on, 3, 4} = Midiex.decode(<<0xB3, 126, 4>>)
+  end
+
+  test "decode system messages" do
+    assert {:sysex, <<0x41>>, <<1, 52>>} = Midiex.decode(M.sysex(0x41, <<1, 52>>))
+    assert {:sysex, <<0, 0x20, 0x6B>>, <<1>>} = Midiex.decode(<<0xF0, 0, 0x20, 0x6B, 1, 0xF7>>)
+    assert {:mtc_quarter_frame, 1, 5} = Midiex.decode(M.quarter_frame(0x15))
+    assert {:song_position, 129} = Midiex.decode(<<0xF2, 1, 1>>)
+    assert {:clock} = Midiex.decode(M.clock())
+    assert {:continue} = Midiex.decode(M.resume())
+    assert {:reset} = Midiex.decode(M.reset())
+  end
+
+  test "decode malformed messages" do
+    assert {:error, {:invalid_message, :wrong_length}} = Midiex.decode(<<0x90, 60>>)
+    assert {:error, {:invalid_message, :undefined_status}} = Midiex.decode(<<0xF4>>)
+  end
+end
@@ -0,0 +1,186 @@
+defmodule MidiexLoopbackTest do
+  use ExUnit.Case, async: false
+
+  alias Midiex.Loopback
+
+  setup do
+    Loopback.enable(["Loopback A", "Loopback B"])
+
+    on_exit(fn ->
+      Midiex.unsubscribe(:all)
+      Loopback.disable()
+    end)
+  end
+
+  test "lists only the loopback devices" do
+    assert Loopback.enabled?()
+    assert %{input: 2, output: 2} = Midiex.port_count()
+    assert ["Loopback A", "Loopback B"] = Midiex.ports(:input) |> Enum.map(& &1.name)
+    assert [%Midiex.MidiPort{direction: :output, num: 1}] = Midiex.ports("Loopback B", :output)
+  end
+
+  test "ports keep their id as other devices come and go" do
+    [port] = Midiex.ports("Loopback B", :input)
+    assert "loopback:" <> _ = port.id
+
+    Loopback.remove_device("Loopback A")
+    assert %Midiex.MidiPort{num: 0, id: id} = Midiex.find_port_by_id(port.id, :input)
+    assert id == port.id
+    assert %Midiex.MidiPort{direction: :output} = Midiex.find_port_by_id(port.id, :output)
+
+    Loopback.remove_device("Loopback B")
+    assert nil == Midiex.find_port_by_id(port.id, :input)
+  end
+
+  test "port info tells apart our own virtual ports" do
+    Midiex.create_virtual_output("Virtual Out")
+
+    assert [
+             %{port: %Midiex.MidiPort{name: "Loopback A"}, client_type: :user, capabilities: [:read], own: false},
+             %{port: %Midiex.MidiPort{name: "Loopback B"}, own: false},
+             %{port: %Midiex.MidiPort{name: "Virtual Out"}, own: true}
+           ] = Midiex.port_info(Midiex.ports(:input))
+
+    assert %{capabilities: [:write], midi_channels: 16} = Midiex.port_info(hd(Midiex.ports(:output)))
+  end
+
+  test "messages sent to a device are received on its input with the loopback clock's timestamps" do
+    [input] = Midiex.ports("Loopback A", :input)
+    [output] = Midiex.ports("Loopback A", :output)
+
+    Midiex.subscribe(input)
+    out_conn = Midiex.open(output)
+
+    Loopback.set_clock(1_000)
+    Midiex.send_msg(out_conn, <<0x90, 60, 100>>)
+    assert 1_500 = Loopback.advance_clock(500)
+    Midiex.send_msg(out_conn, <<0x80, 60, 0>>)
+
+    assert_receive %Midiex.MidiMessage{port: ^input, data: [0x90, 60, 100], timestamp: 1_000}
+    assert_receive %Midiex.MidiMessage{port: ^input, data: [0x80, 60, 0], timestamp: 1_500}
+    refute_received %Midiex.MidiMessage{port: %Midiex.MidiPort{name: "Loopback B"}}
+  end
+
+  test "the rest of a timed out SysEx is dropped when it arrives late" do
+    [input] = Midiex.ports("Loopback A", :input)
+    [output] = Midiex.ports("Loopback A", :output)
+
+    Midiex.subscribe(input, sysex: [timeout: 20])
+    out_conn = Midiex.open(output)
+
+    Midiex.send_msg(out_conn, <<0xF0, 0x41, 1>>)
+    assert_receive {:error, :sysex_timeout}, 1000
+
+    Midiex.send_msg(out_conn, <<2, 0xF8, 3, 0xF7>>)
+    Midiex.send_msg(out_conn, <<0x90, 60, 100>>)
+    assert_receive %Midiex.MidiMessage{data: [0xF8]}
+    assert_receive %Midiex.MidiMessage{data: [0x90, 60, 100]}
+    refute_received %Midiex.MidiMessage{data: [2 | _]}
+  end
+
+  test "virtual ports are loopback devices" do
+    out_conn = Midiex.create_virtual_output("Virtual Out")
+    assert [input] = Midiex.ports("Virtual Out", :input)
+    assert [] = Midiex.ports("Virtual Out", :output)
+    assert %Midiex.OutConn{midi_port: %Midiex.MidiPort{direction: :input, id: id}, port_num: port_num, virtual: true} = out_conn
+    assert {id, port_num} == {input.id, input.num}
+
+    Midiex.subscribe(input)
+    Midiex.send_msg(out_conn, <<0xB0, 7, 64>>)
+    assert_receive %Midiex.MidiMessage{data: [0xB0, 7, 64], timestamp: 0}
+  end
+
+  test "virtual inputs send messages with their port and a timestamp" do
+    virtual_in = Midiex.create_virtual_input("Virtual In")
+    Midiex.subscribe(virtual_in, decode: true)
+
+    [output] = Midiex.ports("Virtual In", :output)
+    out_conn = Midiex.open(output)
+
+    Loopback.set_clock(2_000)
+    Midiex.send_msg(out_conn, <<0x90, 60, 100>>)
+
+    assert_receive %Midiex.MidiMessage{port: ^virtual_in, data: [0x90, 60, 100], timestamp: 2_000, decoded: {:note_on, 0, 60, 100}}
+  end
+
+  test "virtual inputs exist until closed, keeping buffered messages for the next subscriber" do
+    virtual_in = Midiex.create_virtual_input("Buffered In", buffer: 2)
+    assert [output] = Midiex.ports("Buffered In", :output)
+    out_conn = Midiex.open(output)
+
+    Midiex.send_msg(out_conn, <<0x90, 60, 100>>)
+    Midiex.send_msg(out_conn, <<0x90, 62, 100>>)
+    Midiex.send_msg(out_conn, <<0x90, 64, 100>>)
+
+    Midiex.subscribe(virtual_in)
+    assert_receive %Midiex.MidiMessage{data: [0x90, 62, 100]}
+    assert_receive %Midiex.MidiMessage{data: [0x90, 64, 100]}
+    refute_received %Midiex.MidiMessage{data: [0x90, 60, 100]}
+
+    Midiex.unsubscribe(virtual_in)
+    assert [_output] = Midiex.ports("Buffered In", :output)
+
+    Midiex.close(virtual_in)
+    assert [] = Midiex.ports("Buffered In")
+  end
+
+  test "virtual devices receive on their input and send from their output" do
+    device = Midiex.create_virtual_device("Virtual Synth", decode: true)
+    assert %Midiex.VirtualDevice{output: %Midiex.OutConn{virtual: true}} = device
+
+    assert [to_device] = Midiex.ports("Virtual Synth", :output)
+    assert [from_device] = Midiex.ports("Virtual Synth", :input)
+
+    Midiex.subscribe(from_device)
+    Midiex.send_msg(Midiex.open(to_device), <<0x90, 60, 100>>)
+    assert_receive %Midiex.MidiMessage{port: %Midiex.VirtualMidiPort{name: "Virtual Synth"}, decoded: {:note_on, 0, 60, 100}}
+
+    assert ^device = Midiex.send_msg(device, <<0xB0, 7, 64>>)
+    assert_receive %Midiex.MidiMessage{port: %Midiex.MidiPort{name: "Virtual Synth"}, data: [0xB0, 7, 64]}
+
+    Midiex.close(device)
+    assert [] = Midiex.ports("Virtual Synth")
+  end
+
+  test "names set for clients and ports are kept until they're set again" do
+    on_exit(fn -> Midiex.set_names([]) end)
+
+    assert [client_name: nil, port_name: nil] = Midiex.names()
+    assert :ok = Midiex.set_names(client_name: "Drum Machine")
+    assert [client_name: "Drum Machine", port_name: nil] = Midiex.names()
+
+    Midiex.create_virtual_output("Kick Out")
+    Midiex.create_virtual_output("Snare Out", client_name: "Sampler")
+    assert %{client_name: "Drum Machine"} = Midiex.port_info(hd(Midiex.ports("Kick Out")))
+    assert %{client_name: "Sampler"} = Midiex.port_info(hd(Midiex.ports("Snare Out")))
+
+    assert :ok = Midiex.set_names([])
+    assert [client_name: nil, port_name: nil] = Midiex.names()
+    Midiex.create_virtual_output("Hat Out")
+    assert %{client_name: "MIDIex"} = Midiex.port_info(hd(Midiex.ports("Hat Out")))
+  end
+
+  test "owned clients are the virtual ports made on the loopback backend" do
+    virtual_out = Midiex.create_virtual_output("Owned Out", client_name: "Drum Machine")
+    virtual_in = Midiex.create_virtual_input("Owned In")
+
+    assert [
+             %{client_name: "Drum Machine", client: nil, ports: [%{port_name: "Owned Out", id: out_id, connections: []}]},
+             %{client_name: "MIDIex input", client: nil, ports: [%{port_name: "Owned In"}]}
+           ] = Midiex.owned_clients()
+    assert out_id == virtual_out.midi_port.id
+
+    Midiex.close([virtual_out, virtual_in])
+    assert [] = Midiex.owned_clients()
+  end
+
+  test "removed devices can no longer be sent to" do
+    [output] = Midiex.ports("Loopback B", :output)
+    out_conn = Midiex.open(output)
+
+    assert :ok = Loopback.remove_device("Loopback B")
+    assert [] = Midiex.ports("Loopback B")
+    assert {:error, {:backend, _}} = Midiex.send_msg(out_conn, <<0x90, 60, 100>>)
+    assert {:error, {:invalid_port, _}} = Loopback.remove_device("Loopback B")
+  end
+end
@@ -35,10 +35,7 @@ defmodule MidiexTest do
     assert virtual_in_port.name == port_name, "expected %Midiex.VirtualMidiPort{} name to be \"#{port_name}\""
     assert virtual_in_port.direction == :input, "expected %Midiex.VirtualMidiPort{} direction to be :input"
 
-    # Subscribe to the port, this will create an %Midiex.MidiPort{direction: :output}
-    Midiex.subscribe(virtual_in_port)
-
-    # Port visible
+    # Port visible as soon as it's created
     output_port = Midiex.ports(port_name, :output) |> List.first()
     assert is_struct(output_port, Midiex.MidiPort), "expected a %Midiex.MidiPort{} struct"
     assert output_port.name == port_name, "expected %Midiex.MidiPort{} name to be \"#{port_name}\""
@@ -49,7 +46,7 @@ defmodule MidiexTest do
     assert num_output_ports == (initial_num_output_ports + 1), "expected the number of output ports to be \"#{initial_num_output_ports + 1}\""
 
     # Clean up
-    Midiex.unsubscribe(virtual_in_port)
+    Midiex.close(virtual_in_port)
   end
 
   # test "send and recieve MIDI messages" do
@@ -112,4 +109,205 @@ defmodule MidiexTest do
     GenServer.stop(pid)
   end
 
+  test "play a standard MIDI file to a virtual output" do
+    out_conn = Midiex.create_virtual_output("SMF player test")
+
+    smf = %Midiex.Smf{
+      format: 0,
+      division: {:ppq, 96},
+      tracks: [[{0, {:midi, <<0x90, 60, 100>>}}, {10, {:midi, <<0x80, 60, 64>>}}]]
+    }
+
+    player = Midiex.play_smf(out_conn, smf, position_interval: 0)
+    assert is_struct(player, Midiex.Player), "expected a %Midiex.Player{} struct"
+
+    # 10 ticks at 120 BPM and 96 PPQ is about 52 ms
+    player_id = player.id
+    assert_receive {:midiex_player, ^player_id, :finished}, 1000
+
+    # Commands can't be sent to a player which has finished
+    assert {:error, {:player_stopped, _message}} = Midiex.Player.pause(player)
+
+    Midiex.close(out_conn)
+  end
+
+
+  test "record from a virtual output" do
+    port_name = "Recorder test"
+    out_conn = Midiex.create_virtual_output(port_name)
+    input_port = Midiex.ports(port_name, :input) |> List.first()
+
+    recorder = Midiex.start_recording(input_port, ppq: 96)
+    assert is_struct(recorder, Midiex.Recorder), "expected a %Midiex.Recorder{} struct"
+
+    Midiex.send_msg(out_conn, <<0x90, 60, 100>>)
+    :timer.sleep(50)
+    Midiex.send_msg(out_conn, <<0x80, 60, 64>>)
+    Midiex.send_msg(out_conn, <<0xF8>>) # clock isn't recorded
+    :timer.sleep(25)
+
+    assert %Midiex.Smf{format: 0, division: {:ppq, 96}, tracks: [track]} = Midiex.stop_recording(recorder)
+
+    assert [
+             {0, {:tempo, 500_000}},
+             {_, {:midi, <<0x90, 60, 100>>}},
+             {_, {:midi, <<0x80, 60, 64>>}},
+             {0, :end_of_track}
+           ] = track
+
+    # The same take can be had as a .mid binary
+    assert <<"MThd", _rest::binary>> = Midiex.stop_recording(recorder, as: :binary)
+
+    Midiex.close(out_conn)
+  end
+
+  test "subscribe with decoded messages" do
+    port_name = "Decode test"
+    out_conn = Midiex.create_virtual_output(port_name)
+    input_port = Midiex.ports(port_name, :input) |> List.first()
+
+    Midiex.subscribe(input_port, decode: true, note_off: true)
+    Midiex.send_msg(out_conn, <<0x91, 60, 0>>)
+
+    assert_receive %Midiex.MidiMessage{data: [0x91, 60, 0], decoded: {:note_off, 1, 60, 64}}, 1000
+
+    Midiex.unsubscribe(input_port)
+    Midiex.close(out_conn)
+  end
+
+  test "subscribe with a filter" do
+    port_name = "Filter test"
+    out_conn = Midiex.create_virtual_output(port_name)
+    input_port = Midiex.ports(port_name, :input) |> List.first()
+
+    Midiex.subscribe(input_port, filter: [ignore: [:time], channels: [9], notes: 36..51, min_velocity: 10])
+
+    Midiex.send_msg(out_conn, Midiex.Message.clock())
+    Midiex.send_msg(out_conn, <<0x90, 36, 100>>) # wrong channel
+    Midiex.send_msg(out_conn, <<0x99, 60, 100>>) # outside the note range
+    Midiex.send_msg(out_conn, <<0x99, 36, 5>>) # too quiet
+    Midiex.send_msg(out_conn, <<0x99, 36, 100>>)
+    Midiex.send_msg(out_conn, <<0x99, 36, 0>>) # ends the note, so isn't too quiet
+
+    assert_receive %Midiex.MidiMessage{data: [0x99, 36, 100]}, 1000
+    assert_receive %Midiex.MidiMessage{data: [0x99, 36, 0]}, 1000
+    refute_received %Midiex.MidiMessage{}
+
+    Midiex.unsubscribe(input_port)
+    Midiex.close(out_conn)
+  end
+
+  test "subscribe with batched messages" do
+    port_name = "Batch test"
+    out_conn = Midiex.create_virtual_output(port_name)
+    input_port = Midiex.ports(port_name, :input) |> List.first()
+
+    assert {:ok, id} = Midiex.subscribe(input_port, batch: [max_messages: 3, max_delay: 50_000])
+
+    for note <- 60..63, do: Midiex.send_msg(out_conn, <<0x90, note, 100>>)
+
+    # The first three are sent as soon as there are three, the last once it's waited 50 ms
+    assert_receive {:midi_batch, ^id, [{_, <<0x90, 60, 100>>}, {_, <<0x90, 61, 100>>}, {_, <<0x90, 62, 100>>}]}, 1000
+    assert_receive {:midi_batch, ^id, [{_, <<0x90, 63, 100>>}]}, 1000
+
+    Midiex.unsubscribe(input_port)
+
+    assert {:error, {:invalid_argument, _}} = Midiex.subscribe(input_port, batch: [max_messages: 0])
+    assert {:error, {:invalid_argument, _}} = Midiex.subscribe(input_port, batch: [max_messages: 65_537])
+
+    Midiex.close(out_conn)
+  end
+
+  test "subscribe with a SysEx size limit" do
+    port_name = "SysEx limit test"
+    out_conn = Midiex.create_virtual_output(port_name)
+    input_port = Midiex.ports(port_name, :input) |> List.first()
+
+    Midiex.subscribe(input_port, sysex: [max_size: 8])
+
+    Midiex.send_msg(out_conn, Midiex.Message.sysex(0x41, <<1, 2, 3, 4, 5, 6, 7, 8>>))
+    assert_receive {:error, :sysex_overflow}, 1000
+
+    Midiex.send_msg(out_conn, Midiex.Message.sysex(0x41, <<1, 2, 3>>))
+    assert_receive %Midiex.MidiMessage{data: [0xF0, 0x41, 1, 2, 3, 0xF7]}, 1000
+
+    Midiex.unsubscribe(input_port)
+    Midiex.close(out_conn)
+  end
+
+  test "send a SysEx dump in chunks" do
+    port_name = "SysEx dump test"
+    out_conn = Midiex.create_virtual_output(port_name)
+    input_port = Midiex.ports(port_name, :input) |> List.first()
+    Midiex.subscribe(input_port)
+
+    dump = Midiex.Message.sysex(0x41, <<1, 2, 3, 4, 5>>) <> Midiex.Message.sysex(0x41, <<6, 7>>)
+    assert {:ok, id} = Midiex.send_sysex(out_conn, dump, chunk_size: 4, inter_chunk_delay_us: 1_000)
+
+    assert_receive {:midiex_sysex, ^id, {:progress, 4, 12}}, 1000
+    assert_receive {:midiex_sysex, ^id, :done}, 1000
+    assert_receive %Midiex.MidiMessage{data: [0xF0, 0x41, 1, 2, 3, 4, 5, 0xF7]}, 1000
+    assert_receive %Midiex.MidiMessage{data: [0xF0, 0x41, 6, 7, 0xF7]}, 1000
+
+    assert {:error, {:invalid_message, :unterminated_sysex}} = Midiex.send_sysex(out_conn, <<0xF0, 1>>)
+
+    Midiex.unsubscribe(input_port)
+    Midiex.close(out_conn)
+  end
+
+  if match?({:unix, :linux}, :os.type()) do
+    test "send with running status" do
+      port_name = "Running status test"
+      out_conn = Midiex.create_virtual_output(port_name)
+      input_port = Midiex.ports(port_name, :input) |> List.first()
+      Midiex.subscribe(input_port)
+
+      assert %Midiex.OutConn{} = Midiex.set_running_status(out_conn, true, refresh: 0)
+      assert %Midiex.OutConn{} = Midiex.send_msg(out_conn, <<0xB0, 1, 10>>)
+      assert %Midiex.OutConn{} = Midiex.send_msg(out_conn, <<0xB0, 1, 20>>)
+
+      assert_receive %Midiex.MidiMessage{data: [0xB0, 1, 10]}, 1000
+      assert_receive %Midiex.MidiMessage{data: [0xB0, 1, 20]}, 1000
+
+      assert %Midiex.OutConn{} = Midiex.set_running_status(out_conn, false)
+
+      Midiex.unsubscribe(input_port)
+      Midiex.close(out_conn)
+    end
+
+    test "owned clients list connections by the client and port names they're opened with" do
+      virtual_in = Midiex.create_virtual_input("Owned clients test")
+      output_port = Midiex.ports("Owned clients test", :output) |> List.first()
+      out_conn = Midiex.open(output_port, client_name: "Midiex Test Client", port_name: "Test out")
+
+      assert [%{client: client, ports: [%{port_name: "Test out", id: "alsa:" <> _, connections: connections}]}] =
+               Midiex.owned_clients() |> Enum.filter(&(&1.client_name == "Midiex Test Client"))
+      assert is_integer(client)
+      assert output_port.id in connections
+
+      Midiex.close([out_conn, virtual_in])
+      assert [] = Midiex.owned_clients() |> Enum.filter(&(&1.client_name == "Midiex Test Client"))
+    end
+
+    test "owned clients include virtual outputs under their client name" do
+      out_conn = Midiex.create_virtual_output("Owned virtual output test", client_name: "Midiex Test Synth")
+
+      assert [%{ports: [%{port_name: "Owned virtual output test", id: id}]}] =
+               Midiex.owned_clients() |> Enum.filter(&(&1.client_name == "Midiex Test Synth"))
+      assert id == out_conn.midi_port.id
+
+      Midiex.close(out_conn)
+    end
+
+    test "a virtual output's port is found by its ALSA client and port" do
+      out_conn = Midiex.create_virtual_output("Virtual output address test", client_name: "Midiex Test Address")
+
+      assert [%{client: client}] =
+               Midiex.owned_clients() |> Enum.filter(&(&1.client_name == "Midiex Test Address"))
+      assert %Midiex.MidiPort{direction: :input, native_id: {^client, port}, id: id} = out_conn.midi_port
+      assert id == "alsa:#{client}:#{port}"
+
+      Midiex.close(out_conn)
+    end
+  end
 end
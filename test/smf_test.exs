@@ -0,0 +1,145 @@
+defmodule MidiexSmfTest do
+  use ExUnit.Case, async: true
+
+  @fixtures Path.join(__DIR__, "fixtures")
+
+  test "read a format 0 file, expanding running status" do
+    smf = Midiex.read_smf(Path.join(@fixtures, "format0.mid"))
+
+    assert is_struct(smf, Midiex.Smf), "expected a %Midiex.Smf{} struct"
+    assert smf.format == 0, "expected format 0"
+    assert smf.division == {:ppq, 96}, "expected a division of 96 ticks per quarter note"
+
+    assert smf.tracks == [
+             [
+               {0, {:tempo, 500_000}},
+               {0, {:midi, <<0xC0, 5>>}},
+               {0, {:midi, <<0x90, 60, 100>>}},
+               {0, {:midi, <<0x90, 64, 100>>}},
+               {96, {:midi, <<0x90, 60, 0>>}},
+               {0, {:midi, <<0x90, 64, 0>>}},
+               {200, {:midi, <<0x80, 67, 64>>}},
+               {0, :end_of_track}
+             ]
+           ]
+  end
+
+  test "read a format 1 file with meta, sysex and escape events" do
+    smf = Midiex.read_smf(Path.join(@fixtures, "format1.mid"))
+
+    assert smf.format == 1, "expected format 1"
+    assert smf.division == {:ppq, 480}, "expected a division of 480 ticks per quarter note"
+    assert length(smf.tracks) == 2, "expected 2 tracks, skipping the unknown chunk"
+
+    [tempo_track, piano_track] = smf.tracks
+
+    assert tempo_track == [
+             {0, {:track_name, "Tempo"}},
+             {0, {:time_signature, 6, 8, 24, 8}},
+             {0, {:key_signature, -3, :minor}},
+             {0, {:tempo, 400_000}},
+             {1920, {:tempo, 500_000}},
+             {0, :end_of_track}
+           ]
+
+    assert piano_track == [
+             {0, {:track_name, "Piano"}},
+             {0, {:meta, 1, "text"}},
+             {0, {:sysex, <<0xF0, 0x7E, 0x7F, 0x09, 0x01, 0xF7>>}},
+             {10, {:escape, <<0xF8, 0xFA>>}},
+             {0, {:midi, <<0x91, 60, 100>>}},
+             {480, {:midi, <<0x81, 60, 64>>}},
+             {0, {:midi, <<0xE1, 0, 64>>}},
+             {0, :end_of_track}
+           ]
+  end
+
+  test "read a file with SMPTE timing" do
+    smf = Midiex.read_smf(Path.join(@fixtures, "smpte.mid"))
+
+    assert smf.division == {:smpte, 25, 40}, "expected 25 fps and 40 ticks per frame"
+  end
+
+  test "read a file from a binary" do
+    path = Path.join(@fixtures, "format1.mid")
+
+    assert Midiex.read_smf(File.read!(path)) == Midiex.read_smf(path)
+  end
+
+  test "write files which read back the same" do
+    for fixture <- ["format0.mid", "smpte.mid"] do
+      binary = File.read!(Path.join(@fixtures, fixture))
+
+      assert binary |> Midiex.read_smf() |> Midiex.write_smf() == binary,
+             "expected #{fixture} to be written back unchanged"
+    end
+
+    smf = Midiex.read_smf(Path.join(@fixtures, "format1.mid"))
+    assert smf |> Midiex.write_smf() |> Midiex.read_smf() == smf
+  end
+
+  test "write back a time signature whose denominator is out of range" do
+    track = [{0, {:meta, 0x58, <<4, 40, 24, 8>>}}]
+    binary = Midiex.write_smf(track)
+
+    assert %Midiex.Smf{tracks: [[{0, {:meta, 0x58, <<4, 40, 24, 8>>}}, {0, :end_of_track}]]} = smf = Midiex.read_smf(binary)
+    assert Midiex.write_smf(smf) == binary
+  end
+
+  test "write a single track with a tempo and end of track added" do
+    track = [
+      {0, {:midi, <<0x90, 60, 100>>}},
+      {0, {:midi, <<0x90, 64, 100>>}},
+      {480, {:midi, <<0x80, 60, 64>>}},
+      {0, {:midi, <<0x80, 64, 64>>}}
+    ]
+
+    smf = track |> Midiex.write_smf(ppq: 96, tempo: 400_000) |> Midiex.read_smf()
+
+    assert smf.format == 0, "expected format 0"
+    assert smf.division == {:ppq, 96}, "expected a division of 96 ticks per quarter note"
+    assert smf.tracks == [[{0, {:tempo, 400_000}}] ++ track ++ [{0, :end_of_track}]]
+  end
+
+  test "write a struct with its division replaced by ppq" do
+    smf = Midiex.read_smf(Path.join(@fixtures, "format1.mid"))
+    rewritten = smf |> Midiex.write_smf(ppq: 960) |> Midiex.read_smf()
+
+    assert rewritten.division == {:ppq, 960}, "expected ppq: to replace the struct's division"
+    assert rewritten.tracks == smf.tracks
+  end
+
+  test "write with and without running status" do
+    track = [{0, {:midi, <<0x90, 60, 100>>}}, {10, {:midi, <<0x90, 60, 0>>}}]
+
+    compressed = Midiex.write_smf(track)
+    uncompressed = Midiex.write_smf(track, running_status: false)
+
+    assert byte_size(uncompressed) == byte_size(compressed) + 1
+    assert Midiex.read_smf(compressed) == Midiex.read_smf(uncompressed)
+  end
+
+  test "write a tempo map as the first track of a format 1 file" do
+    tracks = [[{0, {:track_name, "Piano"}}], [{0, {:track_name, "Bass"}}]]
+    smf = tracks |> Midiex.write_smf(tempo: [{960, 300_000}, {0, 500_000}]) |> Midiex.read_smf()
+
+    assert smf.format == 1, "expected format 1"
+
+    assert smf.tracks == [
+             [{0, {:tempo, 500_000}}, {960, {:tempo, 300_000}}, {0, :end_of_track}],
+             [{0, {:track_name, "Piano"}}, {0, :end_of_track}],
+             [{0, {:track_name, "Bass"}}, {0, :end_of_track}]
+           ]
+  end
+
+  test "return errors for events which can't be written" do
+    assert {:error, {:invalid_smf, _message}} = Midiex.write_smf([{0, {:midi, <<0x90, 60>>}}])
+  end
+
+  test "return errors for invalid files" do
+    truncated = File.read!(Path.join(@fixtures, "format0.mid")) |> binary_part(0, 30)
+
+    assert {:error, {:invalid_smf, _message}} = Midiex.read_smf(truncated)
+    assert {:error, {:io, _message}} = Midiex.read_smf(Path.join(@fixtures, "missing.mid"))
+  end
+end
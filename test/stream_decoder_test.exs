@@ -0,0 +1,28 @@
+defmodule MidiexStreamDecoderTest do
+  use ExUnit.Case, async: true
+
+  alias Midiex.StreamDecoder
+
+  test "expand running status" do
+    decoder = StreamDecoder.new()
+
+    assert [<<0x90, 60, 100>>, <<0x90, 64, 100>>, <<0x90, 60, 0>>] =
+             StreamDecoder.decode(decoder, <<0x90, 60, 100, 64, 100, 60, 0>>)
+  end
+
+  test "keep incomplete messages until the next call" do
+    decoder = StreamDecoder.new()
+
+    assert [<<0xE0, 0, 64>>] = StreamDecoder.decode(decoder, <<0xE0, 0, 64, 1>>)
+    assert [<<0xE0, 1, 64>>] = StreamDecoder.decode(decoder, <<64>>)
+    assert [] = StreamDecoder.decode(decoder, <<0xF0, 0x41>>)
+    assert [<<0xF0, 0x41, 1, 0xF7>>] = StreamDecoder.decode(decoder, <<1, 0xF7>>)
+  end
+
+  test "pass on real-time messages and cancel running status with system messages" do
+    decoder = StreamDecoder.new()
+
+    assert [<<0xF8>>, <<0xB0, 7, 100>>, <<0xF3, 2>>] =
+             StreamDecoder.decode(decoder, <<0xB0, 7, 0xF8, 100, 0xF3, 2, 7, 100>>)
+  end
+end
@@ -0,0 +1,51 @@
+defmodule MidiexUmpTest do
+  use ExUnit.Case, async: true
+
+  alias Midiex.Ump
+
+  test "convert channel voice messages to MIDI 2.0 packets and back" do
+    assert [0x40913C00, 0xC9240000] = Ump.from_midi1(<<0x91, 60, 100>>)
+    assert [0x40E00000, 0x80000000] = Ump.from_midi1(<<0xE0, 0, 64>>)
+
+    for message <- [<<0x91, 60, 100>>, <<0xB2, 7, 127>>, <<0xC5, 7>>, <<0xD0, 33>>, <<0xE0, 127, 127>>] do
+      assert [^message] = message |> Ump.from_midi1() |> Ump.to_midi1()
+    end
+  end
+
+  test "convert a note on with velocity 0 to a MIDI 2.0 note off" do
+    assert [0x40803C00, 0x80000000] = Ump.from_midi1(<<0x90, 60, 0>>)
+  end
+
+  test "convert channel voice and system messages to MIDI 1.0 packets" do
+    assert [0x22913C64] = Ump.from_midi1(<<0x91, 60, 100>>, protocol: :midi1, group: 2)
+    assert [0x10F20102] = Ump.from_midi1(<<0xF2, 1, 2>>)
+    assert [<<0x91, 60, 100>>, <<0xF8>>] = Ump.to_midi1([0x22913C64, 0x10F80000])
+  end
+
+  test "split a SysEx into SysEx7 packets and join it back" do
+    sysex = <<0xF0, 1, 2, 3, 4, 5, 6, 7, 8, 0xF7>>
+    assert [0x30160102, 0x03040506, 0x30320708, 0] = words = Ump.from_midi1(sysex)
+    assert [^sysex] = Ump.to_midi1(words)
+  end
+
+  test "convert MIDI 2.0 only messages to MIDI 1.0" do
+    # Program Change with a bank
+    assert [<<0xB2, 0, 1>>, <<0xB2, 32, 2>>, <<0xC2, 5>>] = Ump.to_midi1([0x40C20001, 0x05000102])
+    # RPN 0 (pitch bend sensitivity)
+    assert [<<0xB0, 101, 0>>, <<0xB0, 100, 0>>, <<0xB0, 6, 64>>, <<0xB0, 38, 0>>] = Ump.to_midi1([0x40200000, 0x80000000])
+  end
+
+  test "drop packets with no MIDI 1.0 equivalent" do
+    assert [[0], [0xD0000000, 0, 0, 0], [0x10F80000]] = words_packets = Ump.packets([0, 0xD0000000, 0, 0, 0, 0x10F80000])
+    assert [<<0xF8>>] = words_packets |> List.flatten() |> Ump.to_midi1()
+    assert {:error, {:invalid_ump, _}} = Ump.to_midi1([0x40903C00])
+  end
+
+  test "scale values" do
+    assert 0x8000 = Ump.scale_up(64, 7, 16)
+    assert 0xFFFFFFFF = Ump.scale_up(127, 7, 32)
+    assert 0 = Ump.scale_up(0, 7, 32)
+    assert 64 = Ump.scale_down(0x8000, 16, 7)
+    assert {:error, {:invalid_argument, _}} = Ump.scale_up(1, 16, 7)
+  end
+end